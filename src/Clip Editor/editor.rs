@@ -49,6 +49,7 @@ impl ClipEditor {
         let media_dur = media.ictx.duration();
         let media_length = media_dur as f32 / ffmpeg_next::sys::AV_TIME_BASE as f32;
         println!("media_length {} {}", media_dur, media_length);
+        let chapters = media.chapters.iter().map(|chapter| (chapter.start_secs, chapter.title.clone())).collect();
 
         let (ctx_tx, ctx_rx) = tokio::sync::oneshot::channel();
         thread::spawn(move || {
@@ -67,7 +68,7 @@ impl ClipEditor {
         let _ = eframe::run_native(
             "Clip Editor",
             options,
-            Box::new(|cc| Ok(Box::new(EditorGui::new(cc, ctx_tx, width, height, media_length, worker_message_receiver, gui_message_sender)))),
+            Box::new(|cc| Ok(Box::new(EditorGui::new(cc, ctx_tx, width, height, media_length, chapters, worker_message_receiver, gui_message_sender)))),
         );
     }
 
@@ -34,6 +34,7 @@ struct VideoUI {
 
     slider_pos: f32,
     slider_range: RangeInclusive<f32>,
+    chapters: Vec<(f32, String)>, // (start in seconds, title), in order
 }
 
 struct AudioTrack {
@@ -64,6 +65,7 @@ impl EditorGui {
         width: u32,
         height: u32,
         length: f32,
+        chapters: Vec<(f32, String)>,
         message_receiver: sync_mpsc::Receiver<WorkerMessage>,
         message_sender: tokio_mpsc::UnboundedSender<GUIMessage>,
     ) -> Self {
@@ -85,6 +87,7 @@ impl EditorGui {
             texture,
             slider_pos: 0.0,
             slider_range: 0.0..=length,
+            chapters,
         };
 
         let audio_ui = AudioUI {
@@ -117,6 +120,24 @@ impl EditorGui {
         self.playing = !self.playing;
         self.send_play_state();
     }
+
+    // Like dragging the slider there
+    fn seek_and_send(&mut self, pos: f32) {
+        self.set_playing_and_send(false);
+        self.video_ui.slider_pos = pos;
+        self.message_sender.send(GUIMessage::VideoPosChanged(pos)).unwrap();
+        self.set_playing_and_send(true);
+    }
+
+    // The chapter before or after the current position, a little slack so pressing back twice doesn't stick to the one just passed
+    fn neighbour_chapter(&self, forward: bool) -> Option<f32> {
+        let pos = self.video_ui.slider_pos;
+        let starts = self.video_ui.chapters.iter().map(|(start, _)| *start);
+        match forward {
+            true => { starts.filter(|start| *start > pos + 0.1).reduce(f32::min) }
+            false => { starts.filter(|start| *start < pos - 1.).reduce(f32::max).or(Some(0.)) }
+        }
+    }
 }
 
 impl eframe::App for EditorGui {
@@ -172,6 +193,19 @@ impl eframe::App for EditorGui {
                         self.set_playing_and_send(true);
                     }
 
+                    if !self.video_ui.chapters.is_empty() {
+                        if ui.button("⏮").on_hover_text("previous marker").clicked() {
+                            if let Some(pos) = self.neighbour_chapter(false) {
+                                self.seek_and_send(pos);
+                            }
+                        }
+                        if ui.button("⏭").on_hover_text("next marker").clicked() {
+                            if let Some(pos) = self.neighbour_chapter(true) {
+                                self.seek_and_send(pos);
+                            }
+                        }
+                    }
+
                     let volume_slider = ui.add(egui::Slider::new(&mut self.audio_ui.volume, self.audio_ui.volume_range.clone())
                         .custom_formatter(|val, _| format!("{:.2}", val))
                         .text("VOLUME"));
@@ -183,6 +217,23 @@ impl eframe::App for EditorGui {
             });
 
 
+            if !self.video_ui.chapters.is_empty() {
+                let mut jump_to = None;
+                ui.group(|ui| {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.strong("MARKERS");
+                        for (start, title) in &self.video_ui.chapters {
+                            if ui.button(format!("{} ({:.1}s)", title, start)).clicked() {
+                                jump_to = Some(*start);
+                            }
+                        }
+                    });
+                });
+                if let Some(pos) = jump_to {
+                    self.seek_and_send(pos);
+                }
+            }
+
             for (index, audio_ui) in &mut self.track_audio_uis {
                 ui.group(|ui| {
                     ui.strong(format!("Audio {}", index));
@@ -3,9 +3,16 @@ use crate::debug_println;
 
 pub struct Media {
     pub streams: HashMap<usize, StreamInfo>,
+    pub chapters: Vec<Chapter>,
     pub ictx: ffmpeg_next::format::context::Input,
 }
 
+// A marker set while recording, see the recorder's markers.rs
+pub struct Chapter {
+    pub start_secs: f32,
+    pub title: String,
+}
+
 pub struct StreamInfo {
     pub stream_index: usize,
     pub parameters: ffmpeg_next::codec::Parameters,
@@ -43,8 +50,16 @@ impl Media {
 
         ictx.seek(i64::MIN, std::ops::RangeFull).unwrap();
 
+        let chapters = ictx.chapters()
+            .map(|chapter| Chapter {
+                start_secs: (chapter.start() as f64 * f64::from(chapter.time_base())) as f32,
+                title: chapter.metadata().get("title").unwrap_or_default().to_string(),
+            })
+            .collect();
+
         Self {
             streams,
+            chapters,
             ictx,
         }
     }
@@ -0,0 +1,656 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::config::ApiConfig;
+use crate::control::{ask, ControlMessage, ControlRequest, ControlResponse};
+use crate::error::Error;
+use crate::events::Events;
+use crate::types::Result;
+
+// Requests are small, anything bigger is refused. Game state posts (see game_events.rs) can be a few dozen KB
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+const MAX_BODY_BYTES: usize = 256 * 1024;
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: HashMap<String, String>, // names in lower case
+    pub body: Vec<u8>,
+}
+
+// Who may use the API, see check_access
+#[derive(Clone)]
+struct Access {
+    token: Option<String>,
+    allowed_origins: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SaveBody {
+    file_name: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct MarkBody {
+    label: Option<String>,
+}
+
+// Serves the API in the background and returns where it listens. Saves, marks, status and clips go through the control channel like `ctl` does
+pub async fn start_api(
+    settings: &ApiConfig,
+    control_tx: mpsc::UnboundedSender<ControlMessage>,
+    events: Events,
+) -> Result<SocketAddr> {
+    let address: IpAddr = settings.bind.parse().map_err(|_| Error::InvalidConfig(format!("api.bind must be an IP address, got {}", settings.bind)))?;
+    let listener = TcpListener::bind(SocketAddr::new(address, settings.port)).await?;
+    let local_address = listener.local_addr()?;
+    let access = Access {
+        token: settings.token.clone(),
+        allowed_origins: settings.allowed_origins.clone(),
+    };
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let (reader, writer) = stream.into_split();
+                    tokio::spawn(handle_connection(BufReader::new(reader), writer, access.clone(), control_tx.clone(), events.clone()));
+                }
+                Err(err) => {
+                    eprintln!("API failed: {:?}", err);
+                    break;
+                }
+            }
+        }
+    });
+    Ok(local_address)
+}
+
+// One request per connection, or a WebSocket on /events
+async fn handle_connection(
+    mut reader: BufReader<OwnedReadHalf>,
+    mut writer: OwnedWriteHalf,
+    access: Access,
+    control_tx: mpsc::UnboundedSender<ControlMessage>,
+    events: Events,
+) {
+    let request = match read_request(&mut reader).await {
+        Ok(Some(request)) => request,
+        Ok(None) => return,
+        Err(status) => {
+            let _ = write_response(&mut writer, status, &error_json(status_text(status))).await;
+            return;
+        }
+    };
+
+    if let Err((status, message)) = check_access(&access, &request) {
+        let _ = write_response(&mut writer, status, &error_json(message)).await;
+        return;
+    }
+
+    let control_request = match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/save") => {
+            let body: SaveBody = match parse_body(&request) {
+                Ok(body) => body,
+                Err(message) => {
+                    let _ = write_response(&mut writer, 400, &error_json(&message)).await;
+                    return;
+                }
+            };
+            ControlRequest::Save { file_name: body.file_name.or_else(|| request.query.get("file_name").cloned()) }
+        }
+        ("POST", "/mark") => {
+            let body: MarkBody = match parse_body(&request) {
+                Ok(body) => body,
+                Err(message) => {
+                    let _ = write_response(&mut writer, 400, &error_json(&message)).await;
+                    return;
+                }
+            };
+            ControlRequest::Mark { label: body.label.or_else(|| request.query.get("label").cloned()) }
+        }
+        ("GET", "/status") => { ControlRequest::Status }
+        ("GET", "/clips") => { ControlRequest::Clips }
+        ("GET", "/events") => {
+            serve_events(reader, writer, &request, events).await;
+            return;
+        }
+        (_, "/save" | "/mark" | "/status" | "/clips" | "/events") => {
+            let _ = write_response(&mut writer, 405, &error_json(status_text(405))).await;
+            return;
+        }
+        _ => {
+            let _ = write_response(&mut writer, 404, &error_json(status_text(404))).await;
+            return;
+        }
+    };
+
+    let response = ask(&control_tx, control_request).await;
+    let status = match response {
+        ControlResponse::Error { .. } => { 500 }
+        _ => { 200 }
+    };
+    let _ = write_response(&mut writer, status, &serde_json::to_string(&response).unwrap_or_default()).await;
+}
+
+// Err is the status to answer with and why
+fn check_access(
+    access: &Access,
+    request: &Request,
+) -> std::result::Result<(), (u16, &'static str)> {
+    // a page of another site could otherwise save clips or follow the events, WebSockets aren't held back by CORS
+    if let Some(origin) = request.headers.get("origin") {
+        let origin = origin.trim_end_matches('/');
+        if !access.allowed_origins.iter().any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin)) {
+            return Err((403, "origin not in api.allowed_origins"));
+        }
+    }
+    // a domain that resolves to 127.0.0.1 (DNS rebinding) would make the page same-origin with the API
+    if let Some(host) = request.headers.get("host") {
+        if !known_host(host) {
+            return Err((403, "host must be localhost or an IP address"));
+        }
+    }
+
+    if let Some(token) = &access.token {
+        let bearer = request.headers.get("authorization").and_then(|value| value.strip_prefix("Bearer "));
+        // browsers can't set headers on a WebSocket, so the query works too
+        if bearer != Some(token.as_str()) && request.query.get("token") != Some(token) {
+            return Err((401, "missing or wrong token"));
+        }
+    }
+    Ok(())
+}
+
+// "localhost" or an IP address, with or without the port
+fn known_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => { rest.split_once(']').map_or(rest, |(address, _)| address) }
+        None => { host.rsplit_once(':').map_or(host, |(name, _)| name) }
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok()
+}
+
+// An empty body is the default, so `curl -X POST` is enough
+fn parse_body<T: DeserializeOwned + Default>(request: &Request) -> std::result::Result<T, String> {
+    match request.body.is_empty() {
+        true => { Ok(T::default()) }
+        false => { serde_json::from_slice(&request.body).map_err(|err| format!("invalid body: {}", err)) }
+    }
+}
+
+// None if the client closed the connection without sending anything, Err is the status to answer with
+pub async fn read_request<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> std::result::Result<Option<Request>, u16> {
+    let mut head_bytes = 0;
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        let read = reader.read_line(&mut line).await.map_err(|_| 400u16)?;
+        if read == 0 {
+            return match lines.is_empty() {
+                true => { Ok(None) }
+                false => { Err(400) }
+            };
+        }
+        head_bytes += read;
+        if head_bytes > MAX_HEAD_BYTES {
+            return Err(431);
+        }
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+
+    let mut lines = lines.into_iter();
+    let request_line = lines.next().ok_or(400u16)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(400);
+    };
+
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':').map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string())))
+        .collect();
+
+    let content_length = match headers.get("content-length") {
+        Some(value) => { value.parse::<usize>().map_err(|_| 400u16)? }
+        None => { 0 }
+    };
+    if content_length > MAX_BODY_BYTES {
+        return Err(413);
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.map_err(|_| 400u16)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect();
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query,
+        headers,
+        body,
+    }))
+}
+
+pub async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    status: u16,
+    json: &str,
+) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, status_text(status), json.len(), json,
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+pub fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => { "OK" }
+        400 => { "Bad Request" }
+        401 => { "Unauthorized" }
+        403 => { "Forbidden" }
+        404 => { "Not Found" }
+        405 => { "Method Not Allowed" }
+        413 => { "Payload Too Large" }
+        431 => { "Request Header Fields Too Large" }
+        500 => { "Internal Server Error" }
+        _ => { "" }
+    }
+}
+
+pub fn error_json(message: &str) -> String {
+    serde_json::to_string(&ControlResponse::Error { message: message.to_string() }).unwrap_or_default()
+}
+
+// Pushes every Event as a JSON text message until the client closes
+async fn serve_events(
+    mut reader: BufReader<OwnedReadHalf>,
+    mut writer: OwnedWriteHalf,
+    request: &Request,
+    events: Events,
+) {
+    let upgrade = request.headers.get("upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let Some(key) = request.headers.get("sec-websocket-key").filter(|_| upgrade) else {
+        let _ = write_response(&mut writer, 400, &error_json("/events needs a WebSocket upgrade")).await;
+        return;
+    };
+    // subscribed before the handshake, so nothing published after the client sees it is missed
+    let mut events = events.subscribe();
+    let accept = base64(&Sha1::digest(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()));
+    let handshake = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept);
+    if writer.write_all(handshake.as_bytes()).await.is_err() {
+        return;
+    }
+
+    // reading a frame isn't cancel safe, so it gets its own task instead of a select! branch
+    let (frames_tx, mut frames_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok(frame) = read_frame(&mut reader).await {
+            if frames_tx.send(frame).is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                match event {
+                    Ok(event) => {
+                        let json = serde_json::to_string(&event).unwrap_or_default();
+                        if write_frame(&mut writer, OPCODE_TEXT, json.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => { break; }
+                }
+            },
+            frame = frames_rx.recv() => {
+                match frame {
+                    Some((OPCODE_PING, payload)) => {
+                        if write_frame(&mut writer, OPCODE_PONG, &payload).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some((OPCODE_CLOSE, _)) | None => {
+                        let _ = write_frame(&mut writer, OPCODE_CLOSE, &[]).await;
+                        break;
+                    }
+                    Some(_) => {}
+                }
+            },
+        }
+    }
+}
+
+// (opcode, unmasked payload), clients always mask
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await?;
+    let opcode = head[0] & 0x0F;
+    let masked = head[1] & 0x80 != 0;
+
+    let length = match head[1] & 0x7F {
+        126 => { reader.read_u16().await? as u64 }
+        127 => { reader.read_u64().await? }
+        length => { length as u64 }
+    };
+    if length > MAX_BODY_BYTES as u64 {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+
+    let mut mask = [0u8; 4];
+    if masked {
+        reader.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload).await?;
+    if masked {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    Ok((opcode, payload))
+}
+
+// Unfragmented and unmasked, as servers send them
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    opcode: u8,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length if length < 126 => { frame.push(length as u8); }
+        length if length <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame).await
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => { decoded.push(b' '); }
+            b'%' if i + 2 < bytes.len() => {
+                match ((bytes[i + 1] as char).to_digit(16), (bytes[i + 2] as char).to_digit(16)) {
+                    (Some(high), Some(low)) => {
+                        decoded.push((high << 4 | low) as u8);
+                        i += 2;
+                    }
+                    _ => { decoded.push(b'%'); }
+                }
+            }
+            byte => { decoded.push(byte); }
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let triple = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => { encoded.push(ALPHABET[(triple >> (18 - 6 * i) & 0x3F) as usize] as char); }
+                false => { encoded.push('='); }
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::TcpStream;
+
+    use crate::control::Status;
+    use crate::events::Event;
+    use super::*;
+
+    // An API on a free port whose requests are answered like the main loop would
+    async fn start_test_api(
+        token: Option<&str>,
+        allowed_origins: &[&str],
+    ) -> (SocketAddr, Events) {
+        let settings = ApiConfig {
+            enabled: true,
+            bind: "127.0.0.1".to_string(),
+            port: 0,
+            token: token.map(str::to_string),
+            allowed_origins: allowed_origins.iter().map(|origin| origin.to_string()).collect(),
+        };
+        let (control_tx, mut control_rx) = mpsc::unbounded_channel::<ControlMessage>();
+        let events = Events::new();
+        let address = start_api(&settings, control_tx, events.clone()).await.unwrap();
+
+        tokio::spawn(async move {
+            while let Some(ControlMessage { request, reply }) = control_rx.recv().await {
+                let response = match request {
+                    ControlRequest::Save { file_name } => {
+                        ControlResponse::Saved { files: vec![format!("out/{}.mp4", file_name.unwrap_or_else(|| "clip".to_string()))] }
+                    }
+                    ControlRequest::Status => {
+                        ControlResponse::Status(Status {
+                            paused: false,
+                            replay_secs: 30,
+                            buffers: Vec::new(),
+                            process_tracks: Vec::new(),
+                            video_encoders: vec!["libx264".to_string()],
+                            audio_codec: "AAC".to_string(),
+                            last_save: None,
+                        })
+                    }
+                    other => { ControlResponse::Error { message: format!("unexpected {:?}", other) } }
+                };
+                let _ = reply.send(response);
+            }
+        });
+        (address, events)
+    }
+
+    // Sends `request` as is and returns the status and body of the answer
+    async fn send(
+        address: SocketAddr,
+        request: &str,
+    ) -> (u16, String) {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let status = response.split_whitespace().nth(1).and_then(|status| status.parse().ok()).unwrap_or_default();
+        let body = response.split_once("\r\n\r\n").map(|(_, body)| body.to_string()).unwrap_or_default();
+        (status, body)
+    }
+
+    fn get(
+        path: &str,
+        headers: &str,
+    ) -> String {
+        format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n{}\r\n", path, headers)
+    }
+
+    fn post(
+        path: &str,
+        body: &str,
+    ) -> String {
+        format!("POST {} HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: {}\r\n\r\n{}", path, body.len(), body)
+    }
+
+    #[tokio::test]
+    async fn save_goes_through_the_control_channel() {
+        let (address, _events) = start_test_api(None, &[]).await;
+
+        let (status, body) = send(address, &post("/save", r#"{"file_name": "boss fight"}"#)).await;
+        assert_eq!(status, 200);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["result"], "saved");
+        assert_eq!(json["files"][0], "out/boss fight.mp4");
+
+        // no body is fine, the query works too
+        let (status, body) = send(address, &post("/save?file_name=from%20query", "")).await;
+        assert_eq!(status, 200);
+        assert!(body.contains("out/from query.mp4"), "{}", body);
+
+        let (status, _) = send(address, &post("/save", "{not json")).await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn status_is_answered_as_json() {
+        let (address, _events) = start_test_api(None, &[]).await;
+
+        let (status, body) = send(address, &get("/status", "")).await;
+        assert_eq!(status, 200);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["result"], "status");
+        assert_eq!(json["replay_secs"], 30);
+        assert_eq!(json["video_encoders"][0], "libx264");
+    }
+
+    #[tokio::test]
+    async fn a_missing_or_wrong_token_is_refused() {
+        let (address, _events) = start_test_api(Some("secret"), &[]).await;
+
+        assert_eq!(send(address, &get("/status", "")).await.0, 401);
+        assert_eq!(send(address, &get("/status", "Authorization: Bearer wrong\r\n")).await.0, 401);
+        assert_eq!(send(address, &get("/status?token=wrong", "")).await.0, 401);
+
+        assert_eq!(send(address, &get("/status", "Authorization: Bearer secret\r\n")).await.0, 200);
+        assert_eq!(send(address, &get("/status?token=secret", "")).await.0, 200);
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_and_methods() {
+        let (address, _events) = start_test_api(None, &[]).await;
+
+        assert_eq!(send(address, &get("/nothing", "")).await.0, 404);
+        assert_eq!(send(address, &get("/save", "")).await.0, 405);
+        assert_eq!(send(address, &post("/status", "")).await.0, 405);
+        assert_eq!(send(address, "DELETE /clips HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n").await.0, 405);
+    }
+
+    #[tokio::test]
+    async fn too_big_bodies_are_refused() {
+        let (address, _events) = start_test_api(None, &[]).await;
+
+        // refused from the head alone, the body is never sent
+        let request = format!("POST /save HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_BYTES + 1);
+        assert_eq!(send(address, &request).await.0, 413);
+    }
+
+    #[tokio::test]
+    async fn foreign_origins_and_host_names_are_refused() {
+        let (address, _events) = start_test_api(None, &["http://localhost:8080", "null"]).await;
+
+        // another site's page, and a domain rebound to 127.0.0.1
+        assert_eq!(send(address, &get("/status", "Origin: https://evil.example\r\n")).await.0, 403);
+        assert_eq!(send(address, "GET /status HTTP/1.1\r\nHost: evil.example:7373\r\n\r\n").await.0, 403);
+        assert_eq!(send(address, &post("/save", "")).await.0, 200);
+
+        assert_eq!(send(address, &get("/status", "Origin: http://localhost:8080\r\n")).await.0, 200);
+        assert_eq!(send(address, &get("/status", "Origin: null\r\n")).await.0, 200);
+        for host in ["localhost:7373", "LOCALHOST", "127.0.0.1:7373", "[::1]:7373", "192.168.1.20"] {
+            assert_eq!(send(address, &format!("GET /status HTTP/1.1\r\nHost: {}\r\n\r\n", host)).await.0, 200, "{}", host);
+        }
+    }
+
+    #[tokio::test]
+    async fn events_are_pushed_over_a_websocket() {
+        let (address, events) = start_test_api(None, &[]).await;
+
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        // the sample handshake of RFC 6455
+        let handshake = get("/events", "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n");
+        writer.write_all(handshake.as_bytes()).await.unwrap();
+
+        let mut head = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            head.push(line.trim_end().to_string());
+        }
+        assert_eq!(head[0], "HTTP/1.1 101 Switching Protocols");
+        assert!(head.contains(&"Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_string()), "{:?}", head);
+
+        events.publish(Event::Marked { label: Some("boss".to_string()) });
+        let (opcode, payload) = tokio::time::timeout(Duration::from_secs(5), read_frame(&mut reader)).await.unwrap().unwrap();
+        assert_eq!(opcode, OPCODE_TEXT);
+        let json: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(json["event"], "marked");
+        assert_eq!(json["label"], "boss");
+
+        // a masked ping is answered with the same payload
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut ping = vec![0x80 | OPCODE_PING, 0x80 | 2];
+        ping.extend_from_slice(&mask);
+        ping.extend(b"hi".iter().zip(mask).map(|(byte, mask)| byte ^ mask));
+        writer.write_all(&ping).await.unwrap();
+        let (opcode, payload) = tokio::time::timeout(Duration::from_secs(5), read_frame(&mut reader)).await.unwrap().unwrap();
+        assert_eq!((opcode, payload.as_slice()), (OPCODE_PONG, b"hi".as_slice()));
+
+        writer.write_all(&[0x80 | OPCODE_CLOSE, 0x80, 0, 0, 0, 0]).await.unwrap();
+        let (opcode, _) = tokio::time::timeout(Duration::from_secs(5), read_frame(&mut reader)).await.unwrap().unwrap();
+        assert_eq!(opcode, OPCODE_CLOSE);
+    }
+
+    #[test]
+    fn host_names() {
+        assert!(known_host("localhost"));
+        assert!(known_host("127.0.0.1:7373"));
+        assert!(known_host("[::1]:7373"));
+        assert!(!known_host("evil.example"));
+        assert!(!known_host("localhost.evil.example:7373"));
+    }
+}
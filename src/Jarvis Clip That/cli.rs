@@ -0,0 +1,164 @@
+use crate::config::{Config, CONFIG_PATH};
+use crate::control::ControlRequest;
+use crate::error::Error;
+use crate::recorders::audio::sources::wasapi::source::{list_audio_devices, list_audio_sessions};
+use crate::recorders::video::sources::d3d111::source::list_monitors;
+use crate::types::Result;
+
+pub const USAGE: &str = "Usage: jarvis-clip-that [options]
+       jarvis-clip-that ctl save [file name] | mark [label] | status | clips | shutdown
+
+Options:
+  --config <path>       config file to use, default config.toml
+  --list-sources        print the monitors, audio devices and active audio sessions, then exit
+  --buffer-secs <secs>  length of the replay, overrides recorder.max_seconds
+  --out-dir <path>      where clips are saved, overrides save.save_dir
+  --dry-run             build every recorder, print the resolved settings and exit
+  --once <secs>         record for <secs> seconds, save the clip and exit
+  -h, --help            print this help";
+
+pub struct Cli {
+    pub config_path: String,
+    pub list_sources: bool,
+    pub buffer_secs: Option<u32>,
+    pub out_dir: Option<String>,
+    pub dry_run: bool,
+    pub once_secs: Option<f64>,
+    pub control: Option<ControlRequest>, // `ctl`, sent to the running recorder instead of recording
+}
+
+impl Default for Cli {
+    fn default() -> Self {
+        Self {
+            config_path: CONFIG_PATH.to_string(),
+            list_sources: false,
+            buffer_secs: None,
+            out_dir: None,
+            dry_run: false,
+            once_secs: None,
+            control: None,
+        }
+    }
+}
+
+impl Cli {
+    // Without the program name. None if only the help was asked for
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Self>> {
+        let mut cli = Self::default();
+        let mut args = args.into_iter().peekable();
+
+        if args.peek().map(|arg| arg.as_str()) == Some("ctl") {
+            args.next();
+            cli.control = Some(parse_control(args)?);
+            return Ok(Some(cli));
+        }
+
+        while let Some(arg) = args.next() {
+            // both "--out-dir clips" and "--out-dir=clips"
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+                _ => (arg, None),
+            };
+            let mut value = || inline_value.clone().or_else(|| args.next())
+                .ok_or_else(|| Error::InvalidArguments(format!("{name} needs a value")));
+
+            match name.as_str() {
+                "-h" | "--help" => { return Ok(None); }
+                "--config" => { cli.config_path = value()?; }
+                "--list-sources" => { cli.list_sources = true; }
+                "--buffer-secs" => {
+                    let value = value()?;
+                    match value.parse::<u32>() {
+                        Ok(secs) if secs > 0 => { cli.buffer_secs = Some(secs); }
+                        _ => { return Err(Error::InvalidArguments(format!("--buffer-secs must be a whole number of seconds, at least 1, got {value}")).into()); }
+                    }
+                }
+                "--out-dir" => { cli.out_dir = Some(value()?); }
+                "--dry-run" => { cli.dry_run = true; }
+                "--once" => {
+                    let value = value()?;
+                    match value.parse::<f64>() {
+                        Ok(secs) if secs > 0. && secs.is_finite() => { cli.once_secs = Some(secs); }
+                        _ => { return Err(Error::InvalidArguments(format!("--once must be a positive number of seconds, got {value}")).into()); }
+                    }
+                }
+                _ => { return Err(Error::InvalidArguments(format!("unknown argument {name}")).into()); }
+            }
+        }
+        Ok(Some(cli))
+    }
+
+    // The command line wins over the config file, also after a hot reload
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(buffer_secs) = self.buffer_secs {
+            config.recorder.max_seconds = buffer_secs;
+        } else if let Some(once_secs) = self.once_secs {
+            // the clip should hold everything recorded
+            config.recorder.max_seconds = config.recorder.max_seconds.max(once_secs.ceil() as u32);
+        }
+        if let Some(out_dir) = &self.out_dir {
+            config.save.save_dir = out_dir.clone();
+        }
+    }
+}
+
+fn parse_control<I: Iterator<Item = String>>(mut args: I) -> Result<ControlRequest> {
+    let request = match args.next().as_deref() {
+        Some("save") => { ControlRequest::Save { file_name: args.next() } }
+        Some("mark") => { ControlRequest::Mark { label: args.next() } }
+        Some("status") => { ControlRequest::Status }
+        Some("clips") => { ControlRequest::Clips }
+        Some("shutdown") => { ControlRequest::Shutdown }
+        Some(other) => { return Err(Error::InvalidArguments(format!("unknown ctl command {other}")).into()); }
+        None => { return Err(Error::InvalidArguments("ctl needs a command".to_string()).into()); }
+    };
+    match args.next() {
+        Some(extra) => Err(Error::InvalidArguments(format!("unexpected argument {extra}")).into()),
+        None => Ok(request),
+    }
+}
+
+// --list-sources, with the ids the config expects
+pub fn print_sources() {
+    println!("Monitors (recorder.video_sources, type = \"d3d11\"):");
+    match list_monitors() {
+        Ok(monitors) => {
+            for monitor in monitors {
+                println!("  monitor_id = {}: {} {}x{} at ({}, {})", monitor.id, monitor.name, monitor.width, monitor.height, monitor.x, monitor.y);
+            }
+        }
+        Err(err) => { eprintln!("  Couldn't list the monitors: {:?}", err); }
+    }
+
+    println!("Audio devices:");
+    match list_audio_devices() {
+        Ok(devices) => {
+            for device in devices {
+                let kind = match device.render {
+                    true => { "output" }
+                    false => { "input" }
+                };
+                let default = match device.default {
+                    true => { ", default" }
+                    false => { "" }
+                };
+                println!("  {} ({}{})", device.name, kind, default);
+            }
+        }
+        Err(err) => { eprintln!("  Couldn't list the audio devices: {:?}", err); }
+    }
+
+    println!("Audio sessions of the default output:");
+    match list_audio_sessions() {
+        Ok(sessions) => {
+            for session in sessions {
+                let state = match session.active {
+                    true => { "playing" }
+                    false => { "idle" }
+                };
+                println!("  PID {}: {} ({})", session.process_id, session.process_name, state);
+            }
+        }
+        Err(err) => { eprintln!("  Couldn't list the audio sessions: {:?}", err); }
+    }
+}
\ No newline at end of file
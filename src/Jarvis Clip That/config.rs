@@ -1,40 +1,444 @@
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::SystemTime;
+
 use rdev::Key;
+use regex::Regex;
 use serde::Deserialize;
+use crate::error::Error;
+use crate::recorders::audio::convert::AudioFormatSettings;
 use crate::recorders::audio::sources::enums::{AudioCodec, AudioSourceType};
-use crate::recorders::video::sources::enums::{VideoCodec, VideoSourceType};
+use crate::recorders::audio::sources::process_rules::ProcessRules;
+use crate::recorders::track_settings::TrackSettings;
+use crate::recorders::video::encoder_settings::{QualityPreset, VideoEncoderSettings};
+use crate::recorders::video::transform::VideoTransformSettings;
+use crate::recorders::video::sources::enums::{default_video_codecs, VideoCodec};
+use crate::recorders::video::sources::settings::{default_video_sources, VideoSourceSettings};
+use crate::triggers::game_events::GameEventSettings;
+use crate::triggers::log_files::LogFileRule;
+use crate::triggers::loudness::LoudnessRule;
+use crate::triggers::voice::spotter::{VOICE_RULE, VoiceSettings};
 use crate::types::Result;
 
+// used unless --config points somewhere else
+pub const CONFIG_PATH: &str = "config.toml";
+// written to the config path on first start, documents every key
+const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub save: SaveConfig,
+    pub recorder: RecorderConfig,
+    pub pause: PauseConfig,
+    pub mark: MarkConfig,
+    pub control: ControlConfig,
+    pub api: ApiConfig,
+    pub game_events: GameEventSettings,
+    pub log_files: Vec<LogFileRule>,
+    pub loudness: Vec<LoudnessRule>,
+    pub voice: VoiceSettings,
+    pub audio: AudioFormatSettings,
+    pub video: VideoEncoderSettings,
+    pub transform: VideoTransformSettings,
+    pub processes: ProcessRules,
+    // first matching entry wins, see TrackSettings::find
+    pub tracks: Vec<TrackSettings>,
+    pub long_replay: Option<LongReplayConfig>,
+}
+
+// A second, longer and cheaper encode of every video source, saved by its own shortcuts
+#[derive(Deserialize, PartialEq)]
+pub struct LongReplayConfig {
+    pub max_seconds: u32,
+    #[serde(default = "default_long_replay_video")]
+    pub video: VideoEncoderSettings,
+    #[serde(default = "default_long_replay_shortcuts")]
+    pub shortcuts: Vec<Vec<Key>>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SaveConfig {
+    pub shortcuts: Vec<Vec<Key>>,
+    pub save_dir: String,
+    pub base_file_name: String,
+    pub sound_file: Option<String>,
+
+    // Process tracks quieter than min_track_level_db (peak, dBFS) over the whole clip are left out
+    pub filter_silent_tracks: bool,
+    pub min_track_level_db: f32,
+    pub always_keep_tracks: Vec<String>,
+
+    // one last save of the short replay when the program is closed
+    pub save_on_exit: bool,
+}
+
+impl Default for SaveConfig {
+    fn default() -> Self {
+        Self {
+            shortcuts: default_shortcuts(),
+            save_dir: "out".to_string(),
+            base_file_name: "Chat Clip That".to_string(),
+            sound_file: Some("sounds/BOOM.mp3".to_string()),
+            filter_silent_tracks: true,
+            min_track_level_db: -60.,
+            always_keep_tracks: Vec::new(),
+            save_on_exit: false,
+        }
+    }
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RecorderConfig {
+    pub video_sources: Vec<VideoSourceSettings>,
+    // probed in order at startup, the first encoder that opens is used
+    pub video_codecs: Vec<VideoCodec>,
+    pub audio_source_type: AudioSourceType,
+    pub audio_codec: AudioCodec,
+
+    pub max_seconds: u32,
+    pub fps: i32
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            video_sources: default_video_sources(),
+            video_codecs: default_video_codecs(),
+            audio_source_type: AudioSourceType::WasApiDefaultInput,
+            audio_codec: AudioCodec::AAC,
+
+            max_seconds: 30,
+            fps: 30,
+        }
+    }
+}
+
+// Privacy mode, see Pause
 #[derive(Deserialize)]
-struct Config {
-    save: SaveConfig,
-    recorder: RecorderConfig,
+#[serde(default)]
+pub struct PauseConfig {
+    pub shortcuts: Vec<Vec<Key>>,
+    pub clear_buffers: bool, // also drop everything recorded before the pause
+}
+
+impl Default for PauseConfig {
+    fn default() -> Self {
+        Self {
+            shortcuts: vec![vec![Key::Alt, Key::KeyP]],
+            clear_buffers: false,
+        }
+    }
 }
 
+// Bookmarks the current moment without saving, clips saved later get it as a chapter
 #[derive(Deserialize)]
-struct SaveConfig {
-    #[serde(default = "default_shortcuts")]
-    shortcuts: Vec<Vec<Key>>,
-    save_dir: String,
-    base_file_name: String,
-    sound_file: Option<String>,
+#[serde(default)]
+pub struct MarkConfig {
+    pub shortcuts: Vec<Vec<Key>>,
 }
 
-struct RecorderConfig {
-    video_source_type: VideoSourceType,
-    video_codec: VideoCodec,
-    audio_source_type: AudioSourceType,
-    audio_codec: AudioCodec,
+impl Default for MarkConfig {
+    fn default() -> Self {
+        Self {
+            shortcuts: vec![vec![Key::Alt, Key::KeyB]],
+        }
+    }
+}
+
+// The local socket (a named pipe on Windows) scripts and `jarvis-clip-that ctl` talk to, see control.rs
+#[derive(Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ControlConfig {
+    pub enabled: bool,
+}
 
-    max_seconds: u32,
-    fps: i32
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+        }
+    }
 }
 
-pub fn parse_config() -> Result<Config> {
-    let text = std::fs::read_to_string("config.toml")?;
-    Ok(toml::from_str(&text)?)
+// HTTP and WebSocket API for overlays and other tools, see api.rs
+#[derive(Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub bind: String,
+    pub port: u16,
+    // required as "Authorization: Bearer <token>" or "?token=<token>" when set
+    pub token: Option<String>,
+    // web pages that may call the API, e.g. "http://localhost:8080" for an overlay. Requests without an Origin are always let in
+    pub allowed_origins: Vec<String>,
 }
 
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: "127.0.0.1".to_string(),
+            port: 7373,
+            token: None,
+            allowed_origins: Vec::new(),
+        }
+    }
+}
+
+// Reads the config at `path`, writing the documented default config there first if there is none.
+// Not validated yet, so command line overrides can go in first
+pub fn load_config(path: &str) -> Result<Config> {
+    if !Path::new(path).exists() {
+        std::fs::write(path, DEFAULT_CONFIG)?;
+        eprintln!("Wrote the default config to {path}");
+    }
+    let text = std::fs::read_to_string(path)?;
+    parse_config(&text, path)
+}
+
+// Unknown keys only get a warning, so a typo doesn't stop the recorder
+pub fn parse_config(text: &str, path: &str) -> Result<Config> {
+    let deserializer = toml::Deserializer::new(text);
+    let config: Config = serde_ignored::deserialize(deserializer, |key| {
+        eprintln!("{path}: unknown key `{key}` is ignored");
+    })?;
+    Ok(config)
+}
+
+impl Config {
+    pub fn validated(self) -> Result<Self> {
+        let problems = self.validate();
+        if !problems.is_empty() {
+            return Err(Error::InvalidConfig(problems.join("\n")).into());
+        }
+        Ok(self)
+    }
+
+    // The keys whose changes only take effect after a restart, the recorders are built from them at startup.
+    // Shortcuts, save and pause, buffer lengths, fps and the video encoder settings are applied live
+    pub fn restart_needed(&self, running: &Config) -> Vec<&'static str> {
+        [
+            ("recorder.video_sources", self.recorder.video_sources != running.recorder.video_sources),
+            ("recorder.audio_source_type", self.recorder.audio_source_type != running.recorder.audio_source_type),
+            ("recorder.audio_codec", self.recorder.audio_codec != running.recorder.audio_codec),
+            ("audio", self.audio != running.audio),
+            ("processes", self.processes != running.processes),
+            ("tracks", self.tracks != running.tracks),
+            ("control", self.control != running.control),
+            ("api", self.api != running.api),
+            ("game_events", self.game_events != running.game_events),
+            ("log_files", self.log_files != running.log_files),
+            ("loudness", self.loudness != running.loudness),
+            ("voice", self.voice != running.voice),
+            // adding or removing it, its lengths and encoder settings are applied live
+            ("long_replay", self.long_replay.is_some() != running.long_replay.is_some()),
+        ].into_iter().filter(|(_, changed)| *changed).map(|(key, _)| key).collect()
+    }
+
+    // Everything serde can't check by itself, one message per problem
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, problem: String| {
+            if !ok {
+                problems.push(problem);
+            }
+        };
+
+        check(!self.save.save_dir.is_empty(), "save.save_dir must not be empty".to_string());
+        check(!self.save.base_file_name.is_empty(), "save.base_file_name must not be empty".to_string());
+        if let Some(sound_file) = &self.save.sound_file {
+            check(Path::new(sound_file).is_file(), format!("save.sound_file: {sound_file} doesn't exist"));
+        }
+
+        let recorder = &self.recorder;
+        check((1..=240).contains(&recorder.fps), format!("recorder.fps must be between 1 and 240, got {}", recorder.fps));
+        check(recorder.max_seconds > 0, "recorder.max_seconds must be at least 1".to_string());
+        check(!recorder.video_sources.is_empty(), "recorder.video_sources needs at least one source".to_string());
+        check(!recorder.video_codecs.is_empty(), "recorder.video_codecs needs at least one codec".to_string());
+        for (i, video_source) in recorder.video_sources.iter().enumerate() {
+            for (key, value) in [("width", video_source.width), ("height", video_source.height)] {
+                check(value >= 2 && value % 2 == 0, format!("recorder.video_sources[{i}].{key} must be even and at least 2, got {value}"));
+            }
+        }
+
+        if let Some(crop) = &self.transform.crop {
+            check(crop.width >= 2 && crop.height >= 2, format!("transform.crop must be at least 2x2, got {}x{}", crop.width, crop.height));
+        }
+        if let Some(sample_rate) = self.audio.sample_rate {
+            check((8_000..=192_000).contains(&sample_rate), format!("audio.sample_rate must be between 8000 and 192000, got {sample_rate}"));
+        }
+
+        let mut video_settings = vec![("video", &self.video)];
+        if let Some(long_replay) = &self.long_replay {
+            check(long_replay.max_seconds > recorder.max_seconds, format!("long_replay.max_seconds ({}) must be longer than recorder.max_seconds ({})", long_replay.max_seconds, recorder.max_seconds));
+            video_settings.push(("long_replay.video", &long_replay.video));
+        }
+        for (section, settings) in video_settings {
+            if let Some(gop_secs) = settings.gop_secs {
+                check(gop_secs > 0., format!("{section}.gop_secs must be positive, got {gop_secs}"));
+            }
+            for (key, bitrate) in [("bitrate_kbps", settings.bitrate_kbps), ("max_bitrate_kbps", settings.max_bitrate_kbps)] {
+                check(bitrate != Some(0), format!("{section}.{key} must be positive"));
+            }
+            if let Some(quality) = settings.quality {
+                check(quality <= 63, format!("{section}.quality must be between 0 and 63, got {quality}"));
+            }
+        }
+
+        match self.api.bind.parse::<IpAddr>() {
+            Ok(address) => {
+                // anyone on the network could save and list clips otherwise
+                check(address.is_loopback() || self.api.token.is_some(), format!("api.token is required when api.bind ({address}) isn't a loopback address"));
+            }
+            Err(_) => { check(false, format!("api.bind must be an IP address, got {}", self.api.bind)); }
+        }
+        if let Some(token) = &self.api.token {
+            check(!token.is_empty(), "api.token must not be empty".to_string());
+        }
+        for (i, origin) in self.api.allowed_origins.iter().enumerate() {
+            // what browsers send: scheme, host and port without a path, "null" for local files
+            let valid = origin == "null" || ["http://", "https://"].iter().any(|scheme| origin.strip_prefix(scheme).is_some_and(|rest| !rest.is_empty() && !rest.contains('/')));
+            check(valid, format!("api.allowed_origins[{i}] must look like \"http://localhost:8080\" or be \"null\", got {origin}"));
+        }
+
+        let game_events = &self.game_events;
+        match game_events.bind.parse::<IpAddr>() {
+            Ok(address) => { check(address.is_loopback() || game_events.token.is_some(), format!("game_events.token is required when game_events.bind ({address}) isn't a loopback address")); }
+            Err(_) => { check(false, format!("game_events.bind must be an IP address, got {}", game_events.bind)); }
+        }
+        check(!game_events.enabled || !self.api.enabled || game_events.port != self.api.port, format!("game_events.port and api.port are both {}", self.api.port));
+        // (key, rule name, save_secs, delay_secs, cooldown_secs) of every trigger rule, checked together below
+        let mut save_rules = Vec::new();
+        for (i, rule) in game_events.rules.iter().enumerate() {
+            let name = format!("game_events.rules[{i}]");
+            check(rule.count >= 1, format!("{name}.count must be at least 1"));
+            check(rule.count == 1 || rule.within_secs > 0., format!("{name}.within_secs must be positive when count is more than 1"));
+            for (j, condition) in rule.conditions.iter().enumerate() {
+                check(!condition.path.is_empty(), format!("{name}.conditions[{j}].path must not be empty"));
+            }
+            save_rules.push((name, rule.name.as_str(), rule.save_secs, rule.delay_secs, rule.cooldown_secs));
+        }
+        for (i, rule) in self.log_files.iter().enumerate() {
+            let name = format!("log_files[{i}]");
+            check(!rule.path.is_empty(), format!("{name}.path must not be empty"));
+            if let Err(err) = Regex::new(&rule.pattern) {
+                // the last line of the message is the reason, the ones before point at the pattern
+                let reason = err.to_string().lines().last().unwrap_or_default().trim_start_matches("error: ").to_string();
+                check(false, format!("{name}.match isn't a valid regex: {reason}"));
+            }
+            save_rules.push((name, rule.name.as_str(), rule.save_secs, rule.delay_secs, rule.cooldown_secs));
+        }
+        for (i, rule) in self.loudness.iter().enumerate() {
+            let name = format!("loudness[{i}]");
+            check(!rule.track.is_empty(), format!("{name}.track must not be empty"));
+            check(rule.above_lufs.is_some() || rule.jump_db.is_some(), format!("{name} needs above_lufs, jump_db or both"));
+            if let Some(above_lufs) = rule.above_lufs {
+                check(above_lufs < 0., format!("{name}.above_lufs must be below 0, got {above_lufs}"));
+            }
+            if let Some(jump_db) = rule.jump_db {
+                check(jump_db > 0., format!("{name}.jump_db must be positive, got {jump_db}"));
+            }
+            check(rule.min_duration_secs >= 0., format!("{name}.min_duration_secs must not be negative"));
+            save_rules.push((name, rule.name.as_str(), rule.save_secs, rule.delay_secs, rule.cooldown_secs));
+        }
+        let voice = &self.voice;
+        if voice.enabled {
+            check(!voice.templates.is_empty(), "voice.templates needs at least one recording of the phrase".to_string());
+            for template in voice.templates.iter() {
+                check(Path::new(template).is_file(), format!("voice.templates: {template} doesn't exist"));
+            }
+            check(voice.threshold > 0., format!("voice.threshold must be positive, got {}", voice.threshold));
+            // only the mic is listened to, the speakers would hear the game and other people
+            check(recorder.audio_source_type == AudioSourceType::WasApiDefaultInput, "voice needs recorder.audio_source_type to be default_input".to_string());
+            save_rules.push(("voice".to_string(), VOICE_RULE, voice.save_secs, voice.delay_secs, voice.cooldown_secs));
+        }
+        // a rule can't save more than the longest replay holds
+        let longest_secs = self.long_replay.as_ref().map_or(recorder.max_seconds, |long_replay| long_replay.max_seconds.max(recorder.max_seconds));
+        for (i, (name, rule_name, save_secs, delay_secs, cooldown_secs)) in save_rules.iter().enumerate() {
+            check(!rule_name.is_empty(), format!("{name}.name must not be empty"));
+            // cooldowns are kept per name
+            check(!save_rules[..i].iter().any(|(_, other, _, _, _)| other == rule_name), format!("{name}.name {rule_name} is already used by another rule"));
+            if let Some(save_secs) = save_secs {
+                check((1..=longest_secs).contains(save_secs), format!("{name}.save_secs must be between 1 and {longest_secs}, got {save_secs}"));
+            }
+            check(*delay_secs >= 0. && *cooldown_secs >= 0., format!("{name}.delay_secs and cooldown_secs must not be negative"));
+        }
+
+        for (i, track) in self.tracks.iter().enumerate() {
+            check(!track.pattern.is_empty(), format!("tracks[{i}].match must not be empty"));
+        }
+
+        // every shortcut has to be unique, otherwise one key press would trigger two actions
+        let mut shortcuts: Vec<(String, &Vec<Key>)> = Vec::new();
+        let long_replay_shortcuts = self.long_replay.as_ref().map(|long_replay| long_replay.shortcuts.as_slice()).unwrap_or_default();
+        for (section, section_shortcuts) in [("save.shortcuts", self.save.shortcuts.as_slice()), ("pause.shortcuts", self.pause.shortcuts.as_slice()), ("mark.shortcuts", self.mark.shortcuts.as_slice()), ("long_replay.shortcuts", long_replay_shortcuts)] {
+            for (i, keys) in section_shortcuts.iter().enumerate() {
+                let name = format!("{section}[{i}]");
+                check(!keys.is_empty(), format!("{name} has no keys"));
+                if let Some((other, _)) = shortcuts.iter().find(|(_, other_keys)| same_keys(other_keys, keys)) {
+                    check(false, format!("{name} is the same shortcut as {other}"));
+                }
+                shortcuts.push((name, keys));
+            }
+        }
+
+        problems
+    }
+}
+
+// Polls the config file for edits
+pub struct ConfigWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new<S: Into<String>>(path: S) -> Self {
+        let path = path.into();
+        Self {
+            modified: modified_time(&path),
+            path,
+        }
+    }
+
+    // None while the file is unchanged. A broken edit returns its error once, the next save is read again.
+    // Not validated, like load_config
+    pub fn poll(&mut self) -> Option<Result<Config>> {
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(err) => return Some(Err(err.into())),
+        };
+        Some(parse_config(&text, &self.path))
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn same_keys(a: &[Key], b: &[Key]) -> bool {
+    a.len() == b.len() && a.iter().all(|key| b.contains(key))
+}
 
 fn default_shortcuts() -> Vec<Vec<Key>> {
     vec![vec![Key::Alt, Key::KeyM]]
+}
+
+fn default_long_replay_video() -> VideoEncoderSettings {
+    VideoEncoderSettings {
+        preset: QualityPreset::Low,
+        ..Default::default()
+    }
+}
+
+fn default_long_replay_shortcuts() -> Vec<Vec<Key>> {
+    vec![vec![Key::Alt, Key::KeyL]]
 }
\ No newline at end of file
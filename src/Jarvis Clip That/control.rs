@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::error::{CustomError, Error};
+use crate::types::Result;
+
+// One JSON object per line in both directions, e.g. {"command": "save", "file_name": "boss fight"}
+#[cfg(windows)]
+const PIPE_NAME: &str = r"\\.\pipe\jarvis-clip-that";
+#[cfg(unix)]
+const SOCKET_FILE_NAME: &str = "jarvis-clip-that.sock";
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Save {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file_name: Option<String>, // saved in save.save_dir, without the extension
+    },
+    Mark {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>, // the chapter's title, "Marker <n>" if not set
+    },
+    Status,
+    Clips,
+    Shutdown,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Saved { files: Vec<String> },
+    Marked { at: String },
+    Status(Status),
+    Clips { clips: Vec<ClipInfo> },
+    ShuttingDown,
+    Error { message: String },
+}
+
+#[derive(Serialize, Debug)]
+pub struct Status {
+    pub paused: bool,
+    pub replay_secs: u32,
+    pub buffers: Vec<BufferStatus>, // video and the main audio
+    pub process_tracks: Vec<BufferStatus>,
+    pub video_encoders: Vec<String>,
+    pub audio_codec: String,
+    pub last_save: Option<SaveResult>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BufferStatus {
+    pub name: String,
+    pub buffered_secs: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SaveResult {
+    pub at: String,
+    pub files: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl SaveResult {
+    pub fn new(result: &Result<Vec<String>>) -> Self {
+        let at = chrono::Local::now().to_rfc3339();
+        match result {
+            Ok(files) => Self { at, files: files.clone(), error: None },
+            Err(err) => Self { at, files: Vec::new(), error: Some(err.to_string()) },
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ClipInfo {
+    pub file: String,
+    pub size_bytes: u64,
+    pub modified: String,
+}
+
+// The saved clips in `dir`, newest first
+pub fn list_clips(dir: &str) -> Result<Vec<ClipInfo>> {
+    let mut clips = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("mp4") {
+            continue;
+        }
+        let metadata = std::fs::metadata(&path)?;
+        let modified: chrono::DateTime<chrono::Local> = metadata.modified()?.into();
+        clips.push((modified, ClipInfo {
+            file: path.to_string_lossy().into_owned(),
+            size_bytes: metadata.len(),
+            modified: modified.to_rfc3339(),
+        }));
+    }
+    clips.sort_by(|(a, _), (b, _)| b.cmp(a));
+    Ok(clips.into_iter().map(|(_, clip)| clip).collect())
+}
+
+// A request on its way to the main loop, which answers through `reply`
+pub struct ControlMessage {
+    pub request: ControlRequest,
+    pub reply: oneshot::Sender<ControlResponse>,
+}
+
+// Accepts connections in the background until the main loop drops its receiver
+pub fn start_server(tx: mpsc::UnboundedSender<ControlMessage>) -> Result<()> {
+    #[cfg(windows)]
+    {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        // fails if another recorder already owns the pipe
+        let mut server = ServerOptions::new().first_pipe_instance(true).create(PIPE_NAME)?;
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = server.connect().await {
+                    eprintln!("Control pipe failed: {:?}", err);
+                    break;
+                }
+                let connected = server;
+                server = match ServerOptions::new().create(PIPE_NAME) {
+                    Ok(server) => server,
+                    Err(err) => {
+                        eprintln!("Control pipe failed: {:?}", err);
+                        break;
+                    }
+                };
+                tokio::spawn(handle_connection(connected, tx.clone()));
+            }
+        });
+    }
+    #[cfg(unix)]
+    {
+        let path = std::env::temp_dir().join(SOCKET_FILE_NAME);
+        // left behind by a recorder that didn't shut down cleanly
+        if std::os::unix::net::UnixStream::connect(&path).is_err() {
+            let _ = std::fs::remove_file(&path);
+        }
+        let listener = tokio::net::UnixListener::bind(&path)?;
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => { tokio::spawn(handle_connection(stream, tx.clone())); }
+                    Err(err) => {
+                        eprintln!("Control socket failed: {:?}", err);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+// Hands `request` to the main loop and waits for its answer
+pub async fn ask(
+    tx: &mpsc::UnboundedSender<ControlMessage>,
+    request: ControlRequest,
+) -> ControlResponse {
+    let (reply, response) = oneshot::channel();
+    match tx.send(ControlMessage { request, reply }) {
+        Ok(()) => { response.await.unwrap_or(ControlResponse::Error { message: "the recorder is shutting down".to_string() }) }
+        Err(_) => { ControlResponse::Error { message: "the recorder is shutting down".to_string() } }
+    }
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    tx: mpsc::UnboundedSender<ControlMessage>,
+) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => { ask(&tx, request).await }
+            Err(err) => { ControlResponse::Error { message: format!("invalid request: {}", err) } }
+        };
+
+        let mut json = serde_json::to_string(&response).unwrap_or_default();
+        json.push('\n');
+        if writer.write_all(json.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+// What `jarvis-clip-that ctl` does, returns the recorder's response line
+pub async fn send_request(request: &ControlRequest) -> Result<String> {
+    #[cfg(windows)]
+    let stream = {
+        use tokio::net::windows::named_pipe::ClientOptions;
+
+        // every pipe instance is busy while the recorder sets up the next one
+        let mut attempts = 0;
+        loop {
+            match ClientOptions::new().open(PIPE_NAME) {
+                Ok(client) => break client,
+                Err(err) if err.raw_os_error() == Some(windows::Win32::Foundation::ERROR_PIPE_BUSY.0 as i32) && attempts < 20 => {
+                    attempts += 1;
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    };
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(std::env::temp_dir().join(SOCKET_FILE_NAME)).await?;
+
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut json = serde_json::to_string(request).unwrap_or_default();
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    lines.next_line().await?.ok_or(CustomError::CUSTOM(Error::Unknown))
+}
\ No newline at end of file
@@ -2,6 +2,8 @@
 pub enum Error {
     NotYetImplemented,
     NonExistentParameterCombination,
+    InvalidConfig(String), // every problem on its own line
+    InvalidArguments(String),
 
     Unknown,
 }
@@ -40,6 +42,17 @@ impl From<toml::de::Error> for CustomError {
     }
 }
 
+impl std::fmt::Display for CustomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CustomError::TOML(err) => { write!(f, "{}", err) }
+            CustomError::CUSTOM(Error::InvalidConfig(problems)) => { write!(f, "{}", problems) }
+            CustomError::CUSTOM(Error::InvalidArguments(problem)) => { write!(f, "{}", problem) }
+            CustomError::IO(err) => { write!(f, "{}", err) }
+            other => { write!(f, "{:?}", other) }
+        }
+    }
+}
 
 impl From<Error> for CustomError {
     fn from(value: Error) -> Self {
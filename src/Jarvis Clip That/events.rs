@@ -0,0 +1,41 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+// events a slow listener can fall behind by before it misses some
+const EVENT_BACKLOG: usize = 64;
+
+// What happens while recording, pushed to every /events WebSocket (see api.rs)
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    ClipSaved { files: Vec<String> },
+    Triggered { rule: String }, // a save rule matched, its clip is saved next
+    Marked { label: Option<String> },
+    TrackAdded { name: String },
+    TrackRemoved { name: String },
+    Paused,
+    Resumed,
+    Error { message: String },
+}
+
+#[derive(Clone)]
+pub struct Events {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Self {
+            tx: broadcast::channel(EVENT_BACKLOG).0,
+        }
+    }
+
+    // Nobody listening is fine
+    pub fn publish(&self, event: Event) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
\ No newline at end of file
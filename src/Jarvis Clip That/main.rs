@@ -1,13 +1,36 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use rdev::Key;
+use crate::cli::{Cli, print_sources, USAGE};
+use crate::config::{Config, ConfigWatcher, load_config, SaveConfig};
+use crate::api::start_api;
+use crate::control::{BufferStatus, ControlMessage, ControlRequest, ControlResponse, list_clips, SaveResult, send_request, start_server, Status};
+use crate::error::{CustomError, Error};
+use crate::events::{Event, Events};
+use crate::recorders::audio::convert::AudioFormatSettings;
+use crate::recorders::audio::tap::{AudioTap, TrackTaps};
 use crate::recorders::audio::sources::enums::{AudioCodec, AudioSourceType};
-use crate::recorders::audio::sources::wasapi::source::AudioProcessWatcher;
-use crate::recorders::recorder::{create_audio_recorder, create_video_recorder};
+use crate::recorders::audio::sources::wasapi::source::{AudioProcessWatcher, default_device_name};
+use crate::recorders::recorder::{create_audio_recorder, create_video_recorders, create_video_recorders_with_fallback};
+use crate::recorders::markers::Markers;
+use crate::recorders::pause::Pause;
 use crate::recorders::save::key_listener::KeyListener;
-use crate::recorders::save::saver::SaverEnv;
-use crate::recorders::video::sources::enums::{VideoCodec, VideoSourceType};
+use crate::recorders::save::level::SilenceFilter;
+use crate::recorders::save::saver::{Save, SaverEnv};
+use crate::recorders::supervisor::{RecorderFactory, SUPERVISE_INTERVAL, Supervisor};
+use crate::recorders::track_settings::{gain_of, TrackSettings};
+use crate::recorders::video::encoder_settings::VideoEncoderSettings;
+use crate::recorders::video::transform::VideoTransformSettings;
+use crate::recorders::video::sources::enums::VideoCodec;
+use crate::recorders::video::sources::settings::VideoSourceSettings;
+use crate::triggers::game_events::start_game_events;
+use crate::triggers::log_files::start_log_watcher;
+use crate::triggers::loudness::LoudnessTrigger;
+use crate::triggers::trigger::Triggers;
+use crate::triggers::voice::spotter::VoiceTrigger;
 use crate::ring_buffer::packet_handlers::KeyFrameStartPacketWrapper;
 use crate::ring_buffer::ring_buffer::RingBuffer;
-use crate::types::Packet;
+use crate::types::{Packet, Result};
 
 mod error;
 mod types;
@@ -15,6 +38,11 @@ mod wrappers;
 mod ring_buffer;
 mod recorders;
 mod config;
+mod cli;
+mod control;
+mod events;
+mod api;
+mod triggers;
 #[path = "../shared_macros.rs"]
 mod shared_macros;
 
@@ -26,62 +54,765 @@ async fn main() {
 type VideoPacketRingBufferType = RingBuffer<KeyFrameStartPacketWrapper>;
 type AudioPacketRingBufferType = RingBuffer<Packet>;
 
+// Which of the video ring buffers a save takes its video from
+#[derive(Clone, Copy, Debug)]
+enum Replay {
+    Short,
+    Long,
+    Last(u32), // seconds, from the long replay if the short one doesn't reach back that far
+}
+
+// What the shortcuts ask the main loop for
+#[derive(Clone, Copy, Debug)]
+enum Action {
+    Save(Replay),
+    TogglePause,
+    Mark,
+}
+
+// how often the config file is checked for edits
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
 async fn main_async() {
-    let video_source_type = VideoSourceType::D3d11 { monitor_id: 0 };
-    let video_codec = VideoCodec::Amf;
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(Some(cli)) => cli,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return;
+        }
+    };
+    if cli.list_sources {
+        print_sources();
+        return;
+    }
+    if let Some(request) = &cli.control {
+        match send_request(request).await {
+            Ok(response) => { println!("{}", response); }
+            Err(err) => { eprintln!("Couldn't reach the recorder, is it running with control enabled? {:?}", err); }
+        }
+        return;
+    }
 
-    let audio_source_type = AudioSourceType::WasApiDefaultInput;
-    let audio_codec = AudioCodec::AAC;
+    // the command line overrides are validated together with the file
+    let resolve = |mut config: Config| {
+        cli.apply(&mut config);
+        config.validated()
+    };
+    let config = match load_config(&cli.config_path).and_then(resolve) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Invalid {}:\n{}", cli.config_path, err);
+            return;
+        }
+    };
+    let video_sources = config.recorder.video_sources.clone();
+    // what the video recorders are built from, a reload switches them over to its changes
+    let mut video_setup = VideoSetup::new(&config);
+    // the optional second replay, saved by its own shortcut
+    let long_replay = video_setup.tiers.len() > 1;
 
+    let audio_source_type = config.recorder.audio_source_type;
+    let audio_codec = config.recorder.audio_codec;
+    let audio_format = config.audio.clone();
 
-    let seconds = 5;
-    let fps = 30;
 
-    let mut video_recorder = create_video_recorder::<VideoPacketRingBufferType>(&video_source_type, &video_codec, seconds, 2560, 1440, fps, 0.).unwrap();
-    let mut audio_recorder_input = create_audio_recorder::<AudioPacketRingBufferType>(&audio_source_type, &audio_codec, seconds, 0.).unwrap();
-    let mut audio_recorder = AudioProcessWatcher::<AudioPacketRingBufferType>::new(audio_codec, true, seconds, 0.).unwrap();
+    let mut seconds = video_setup.replay_secs();
+    let mut save_on_exit = config.save.save_on_exit;
+    let mut clear_on_pause = config.pause.clear_buffers;
+    let mut audio_seconds = video_setup.audio_secs();
 
+    let input_device_name = default_device_name(false).unwrap_or_default();
+    let track_settings = config.tracks.clone();
+    let input_settings = TrackSettings::find(&track_settings, &["Main Audio", &input_device_name]).cloned();
+    let input_codec = input_settings.as_ref().and_then(|settings| settings.codec).unwrap_or(audio_codec);
 
-    let save_env = SaverEnv::new("out", "Chat Clip That", Some("sounds/BOOM.mp3"));
+    // restarts recorders whose thread died or got stuck, on top of their old ring buffers
+    let pause = Pause::new();
+    let mut video_supervisor = Supervisor::<VideoPacketRingBufferType>::new(pause.flag());
+    let mut audio_supervisor = Supervisor::<AudioPacketRingBufferType>::new(pause.flag());
 
+    let time_origin = Instant::now();
+    let mut markers = Markers::new(time_origin);
+    let mut video_tracks = Vec::new();
+    let mut video_encoders = Vec::new();
+    for (i, video_source) in video_sources.iter().enumerate() {
+        let title = video_source.title(i);
+        let settings = TrackSettings::find(&track_settings, &[&title]).cloned();
+        let (recorders, video_codec) = match create_video_recorders_with_fallback::<VideoPacketRingBufferType>(&video_source.source, &video_setup.video_codecs, &video_setup.tier_refs(), video_source.width, video_source.height, video_setup.fps, 0., &video_setup.transform, time_origin) {
+            Ok(built) => built,
+            Err(err) => {
+                eprintln!("Invalid {}:\n{}", cli.config_path, invalid_config(&format!("recorder.video_sources[{i}] (none of recorder.video_codecs opened)"), err));
+                return;
+            }
+        };
 
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+        // a restart sticks to the codec that worked, the ring buffers hold its packets
+        let factory = video_factory(video_source, video_codec, &video_setup, time_origin);
+        let index = video_supervisor.add(title.clone(), recorders, factory, true);
+        video_tracks.push((index, title, settings));
+        video_encoders.push(video_codec);
+    }
 
+    // rules that save clips by themselves
+    let (triggers, mut trigger_rx) = Triggers::new();
+    // listens to the mic, so it has to exist before the mic's recorder
+    let voice_trigger = match config.voice.enabled && !cli.dry_run {
+        true => {
+            match VoiceTrigger::start(&config.voice, triggers.clone()) {
+                Ok(voice_trigger) => { Some(voice_trigger) }
+                Err(err) => {
+                    eprintln!("Couldn't start the voice trigger: {:?}", err);
+                    None
+                }
+            }
+        }
+        false => { None }
+    };
+    // loud moments on any track the loudness rules name
+    let loudness_trigger = LoudnessTrigger::new(&config.loudness, triggers.clone());
+    let track_taps: TrackTaps = Arc::new(move |names: &[&str]| loudness_trigger.taps(names));
+    let mut input_taps: Vec<AudioTap> = voice_trigger.iter().map(VoiceTrigger::tap).collect();
+    input_taps.extend(track_taps(&["Main Audio", &input_device_name]));
 
-    let mut key_listener = KeyListener::new();
-    key_listener.register_shortcut(&[Key::Alt, Key::KeyM], move || {
-        eprintln!("OK GARMIN VIDEO SPEICHERN");
-        if let Err(_) = tx.send(()) {
-            eprintln!("Key responder died :(")
+    let input_gain = gain_of(input_settings.as_ref());
+    let audio_recorder_input = match create_audio_recorder::<AudioPacketRingBufferType>(&audio_source_type, &input_codec, &audio_format, audio_seconds, 0., input_gain, &input_taps, None) {
+        Ok(audio_recorder_input) => audio_recorder_input,
+        Err(err) => {
+            eprintln!("Invalid {}:\n{}", cli.config_path, invalid_config(&format!("recorder.audio_source_type ({:?} with {:?})", audio_source_type, input_codec), err));
+            return;
         }
-    });
+    };
+    let input_factory = audio_factory(audio_source_type, input_codec, audio_format.clone(), audio_seconds, input_gain, input_taps);
+    let input_index = audio_supervisor.add("Main Audio", vec![audio_recorder_input], input_factory, false);
+    // clips, tracks and failures as they happen, for the API's WebSocket
+    let events = Events::new();
+    let mut audio_recorder = match AudioProcessWatcher::<AudioPacketRingBufferType>::new(audio_codec, audio_format, true, audio_seconds, 0., config.processes.clone(), track_settings.clone(), track_taps, pause.flag(), events.clone()) {
+        Ok(audio_recorder) => audio_recorder,
+        Err(err) => {
+            eprintln!("Invalid {}:\n{}", cli.config_path, invalid_config("processes (the audio sessions of the default output device can't be watched)", err));
+            return;
+        }
+    };
+
+
+    let mut save_env = saver_env(&config.save);
+
+    if cli.dry_run {
+        print_resolved(&config, &video_tracks, &video_encoders, &input_device_name, input_codec, audio_seconds);
+        return;
+    }
+
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Action>();
+
+
+    let mut key_listener = KeyListener::new();
+    register_all_shortcuts(&mut key_listener, &config, long_replay, &tx);
 
     key_listener.start();
 
+    // scripts and `ctl` next to the hotkeys, answered by the main loop
+    let (control_tx, mut control_rx) = tokio::sync::mpsc::unbounded_channel::<ControlMessage>();
+    if config.control.enabled {
+        if let Err(err) = start_server(control_tx.clone()) {
+            eprintln!("Couldn't open the control socket: {:?}", err);
+        }
+    }
+    if config.api.enabled {
+        match start_api(&config.api, control_tx.clone(), events.clone()).await {
+            Ok(address) => { eprintln!("API listening on http://{}", address); }
+            Err(err) => { eprintln!("Couldn't start the API: {:?}", err); }
+        }
+    }
+    drop(control_tx);
 
-    video_recorder.start_recording(None);
-    audio_recorder_input.start_recording(None);
+    if config.game_events.enabled {
+        match start_game_events(&config.game_events, triggers.clone()).await {
+            Ok(()) => { eprintln!("Listening for game events on http://{}:{}", config.game_events.bind, config.game_events.port); }
+            Err(err) => { eprintln!("Couldn't listen for game events: {:?}", err); }
+        }
+    }
+    if !config.log_files.is_empty() {
+        if let Err(err) = start_log_watcher(&config.log_files, triggers.clone()) {
+            eprintln!("Couldn't watch the log files: {:?}", err);
+        }
+    }
+    let mut last_save: Option<SaveResult> = None;
+
+    // edits to the config file are applied while running as far as the recorders allow, see Config::restart_needed
+    let running_config = config;
+    let mut config_watcher = ConfigWatcher::new(cli.config_path.as_str());
+
+
+    video_supervisor.start();
+    audio_supervisor.start();
     audio_recorder.start_recording().await.unwrap_or_else(|err| panic!("Failed start_recording because: {:?}", err));
 
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut supervise = tokio::time::interval(SUPERVISE_INTERVAL);
+    let mut watch_config = tokio::time::interval(CONFIG_POLL_INTERVAL);
+    // --once, never fires otherwise
+    let once = async {
+        match cli.once_secs {
+            Some(secs) => { tokio::time::sleep(Duration::from_secs_f64(secs)).await; }
+            None => { std::future::pending::<()>().await; }
+        }
+    };
+    tokio::pin!(once);
+
     loop {
         tokio::select! {
-            Some(_) = rx.recv() => {
-                if let Ok(mut save) = save_env.new_save::<String>(None){
-                    save.add_stream(&video_recorder.ring_buffer, &video_recorder.parameters, true, Some("Main Video")).unwrap();
-                    save.add_stream(&audio_recorder_input.ring_buffer, &audio_recorder_input.parameters, false, Some("Main Audio")).unwrap();
-
-                    for (p_id, (recorder, titel, _)) in audio_recorder.audio_recorders.lock().await.iter() {
-                        debug_println!("stream added for: {}", p_id);
-                        save.add_stream(&recorder.ring_buffer, &recorder.parameters, false, Some(titel)).unwrap();
+            Some(action) = rx.recv() => {
+                match action {
+                    Action::Save(replay) => {
+                        let result = save_clip(&save_env, None, replay, seconds, audio_seconds, time_origin, &pause, &video_supervisor, &video_tracks, &audio_supervisor, input_index, input_settings.as_ref(), &audio_recorder, voice_trigger.as_ref(), &markers).await;
+                        last_save = Some(publish_save(&result, &events));
                     }
-
-                    if let Err(error) = save.finalize_and_save() {
-                        eprintln!("Couldn't save clip: {:?}", error);
+                    Action::TogglePause => {
+                        match pause.toggle() {
+                            true => {
+                                eprintln!("Recording paused");
+                                events.publish(Event::Paused);
+                                if clear_on_pause {
+                                    video_supervisor.clear_buffers();
+                                    audio_supervisor.clear_buffers();
+                                    audio_recorder.clear_buffers().await;
+                                }
+                            }
+                            false => {
+                                eprintln!("Recording resumed");
+                                events.publish(Event::Resumed);
+                            }
+                        }
+                    }
+                    Action::Mark => {
+                        add_marker(&mut markers, None, &events);
+                    }
+                }
+            },
+            Some(trigger) = trigger_rx.recv() => {
+                eprintln!("{} triggered a save", trigger.rule);
+                events.publish(Event::Triggered { rule: trigger.rule.clone() });
+                let replay = trigger.save_secs.map_or(Replay::Short, Replay::Last);
+                let result = save_clip(&save_env, None, replay, seconds, audio_seconds, time_origin, &pause, &video_supervisor, &video_tracks, &audio_supervisor, input_index, input_settings.as_ref(), &audio_recorder, voice_trigger.as_ref(), &markers).await;
+                last_save = Some(publish_save(&result, &events));
+            },
+            Some(ControlMessage { request, reply }) = control_rx.recv() => {
+                let response = match request {
+                    ControlRequest::Save { file_name } => {
+                        let result = save_clip(&save_env, file_name.as_deref(), Replay::Short, seconds, audio_seconds, time_origin, &pause, &video_supervisor, &video_tracks, &audio_supervisor, input_index, input_settings.as_ref(), &audio_recorder, voice_trigger.as_ref(), &markers).await;
+                        last_save = Some(publish_save(&result, &events));
+                        match result {
+                            Ok(files) => { ControlResponse::Saved { files } }
+                            Err(err) => { ControlResponse::Error { message: err.to_string() } }
+                        }
+                    }
+                    ControlRequest::Mark { label } => {
+                        add_marker(&mut markers, label, &events);
+                        ControlResponse::Marked { at: chrono::Local::now().to_rfc3339() }
+                    }
+                    ControlRequest::Clips => {
+                        match list_clips(save_env.out_dir()) {
+                            Ok(clips) => { ControlResponse::Clips { clips } }
+                            Err(err) => { ControlResponse::Error { message: err.to_string() } }
+                        }
                     }
+                    ControlRequest::Status => {
+                        ControlResponse::Status(status(&pause, seconds, &video_supervisor, &video_tracks, &video_encoders, &audio_supervisor, input_index, audio_codec, &audio_recorder, last_save.as_ref()).await)
+                    }
+                    ControlRequest::Shutdown => { ControlResponse::ShuttingDown }
+                };
+                let shutting_down = matches!(response, ControlResponse::ShuttingDown);
+                let _ = reply.send(response);
+                if shutting_down {
+                    break;
                 }
+            },
+            _ = supervise.tick() => {
+                let mut problems = video_supervisor.check();
+                problems.extend(audio_supervisor.check());
+                problems.extend(audio_recorder.check().await);
+                for problem in problems {
+                    events.publish(Event::Error { message: problem });
+                }
+            },
+            _ = watch_config.tick() => {
+                match config_watcher.poll().map(|result| result.and_then(resolve)) {
+                    Some(Ok(config)) => {
+                        save_env = saver_env(&config.save);
+                        save_on_exit = config.save.save_on_exit;
+                        clear_on_pause = config.pause.clear_buffers;
+                        key_listener.clear_shortcuts();
+                        register_all_shortcuts(&mut key_listener, &config, long_replay, &tx);
+
+                        let reloaded_setup = video_setup.reloaded(&config);
+                        reload_recorders(&video_setup, &reloaded_setup, &video_sources, &mut video_supervisor, &video_tracks, &mut video_encoders, &mut audio_supervisor, input_index, &audio_recorder, time_origin, &events).await;
+                        video_setup = reloaded_setup;
+                        seconds = video_setup.replay_secs();
+                        audio_seconds = video_setup.audio_secs();
 
+                        let restart_needed = config.restart_needed(&running_config);
+                        match restart_needed.is_empty() {
+                            true => { eprintln!("Reloaded {}", cli.config_path); }
+                            false => { eprintln!("Reloaded {}, changes to [{}] need a restart to take effect", cli.config_path, restart_needed.join("], [")); }
+                        }
+                    }
+                    Some(Err(err)) => { eprintln!("Invalid {}, keeping the last good config:\n{}", cli.config_path, err); }
+                    None => {}
+                }
+            },
+            _ = &mut once => {
+                eprintln!("Recorded for {}s", cli.once_secs.unwrap_or_default());
+                save_on_exit = true;
+                break;
+            },
+            result = &mut shutdown => {
+                if let Err(err) = result {
+                    eprintln!("Couldn't listen for shutdown signals: {:?}", err);
+                }
+                break;
             },
             else => break,
         }
     }
+
+    eprintln!("Shutting down...");
+    // every recorder thread drains its encoder and returns
+    let mut recorder_handles = video_supervisor.stop();
+    recorder_handles.extend(audio_supervisor.stop());
+    recorder_handles.extend(audio_recorder.stop().await);
+    for handle in recorder_handles {
+        match handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => { eprintln!("Recorder failed while stopping: {:?}", err); }
+            Err(_) => { eprintln!("Recorder thread panicked"); }
+        }
+    }
+
+    // the ring buffers now also hold what the encoders still had queued
+    if save_on_exit {
+        let _ = save_clip(&save_env, None, Replay::Short, seconds, audio_seconds, time_origin, &pause, &video_supervisor, &video_tracks, &audio_supervisor, input_index, input_settings.as_ref(), &audio_recorder, voice_trigger.as_ref(), &markers).await;
+    }
+}
+
+// --dry-run, everything the recorders were built with
+fn print_resolved(
+    config: &Config,
+    video_tracks: &[(usize, String, Option<TrackSettings>)],
+    video_encoders: &[VideoCodec],
+    input_device_name: &str,
+    input_codec: AudioCodec,
+    audio_seconds: u32,
+) {
+    let recorder = &config.recorder;
+    println!("Replay: {}s at {} fps", recorder.max_seconds, recorder.fps);
+    if let Some(long_replay) = &config.long_replay {
+        println!("Long replay: {}s, {:?}", long_replay.max_seconds, long_replay.video);
+    }
+    for (((_, title, settings), video_source), video_codec) in video_tracks.iter().zip(recorder.video_sources.iter()).zip(video_encoders.iter()) {
+        println!("Video \"{}\": {:?}, {}x{}, encoder {}", title, video_source.source, video_source.width, video_source.height, video_codec.encoder_name());
+        if let Some(settings) = settings {
+            println!("  track settings: {:?}", settings);
+        }
+    }
+    println!("Video encoder settings: {:?}", config.video);
+    println!("Transform: {:?}", config.transform);
+    println!("Main Audio: {:?} ({}), {:?}, kept for {}s", recorder.audio_source_type, input_device_name, input_codec, audio_seconds);
+    println!("Audio format: {:?}", config.audio);
+    println!("Process tracks: {:?}, codec {:?}", config.processes, recorder.audio_codec);
+    for track in config.tracks.iter() {
+        println!("Track settings: {:?}", track);
+    }
+    let save = &config.save;
+    println!("Saving to {}/{} (sound: {:?}, silent tracks left out: {} (below {} dB), save on exit: {})", save.save_dir, save.base_file_name, save.sound_file, save.filter_silent_tracks, save.min_track_level_db, save.save_on_exit);
+}
+
+// A recorder that couldn't be built, reported like a validation problem of the key it was built from
+fn invalid_config(
+    key: &str,
+    err: CustomError,
+) -> CustomError {
+    Error::InvalidConfig(format!("{key}: {err}")).into()
+}
+
+fn saver_env(save_config: &SaveConfig) -> SaverEnv {
+    let silence_filter = save_config.filter_silent_tracks.then(|| SilenceFilter::new(save_config.min_track_level_db, save_config.always_keep_tracks.clone()));
+    SaverEnv::new(save_config.save_dir.as_str(), save_config.base_file_name.as_str(), save_config.sound_file.as_deref())
+        .with_silence_filter(silence_filter)
+}
+
+// The long replay's shortcuts only count if it was recording from the start
+fn register_all_shortcuts(
+    key_listener: &mut KeyListener,
+    config: &Config,
+    long_replay: bool,
+    tx: &tokio::sync::mpsc::UnboundedSender<Action>,
+) {
+    if let (true, Some(long_replay_config)) = (long_replay, &config.long_replay) {
+        register_shortcuts(key_listener, &long_replay_config.shortcuts, tx, Action::Save(Replay::Long), Some("OK GARMIN LANGES VIDEO SPEICHERN"));
+    }
+    register_shortcuts(key_listener, &config.pause.shortcuts, tx, Action::TogglePause, None);
+    register_shortcuts(key_listener, &config.mark.shortcuts, tx, Action::Mark, None);
+    register_shortcuts(key_listener, &config.save.shortcuts, tx, Action::Save(Replay::Short), Some("OK GARMIN VIDEO SPEICHERN"));
+}
+
+// Every shortcut in `shortcuts` sends `action` to the main loop
+fn register_shortcuts(
+    key_listener: &mut KeyListener,
+    shortcuts: &[Vec<Key>],
+    tx: &tokio::sync::mpsc::UnboundedSender<Action>,
+    action: Action,
+    message: Option<&'static str>,
+) {
+    for keys in shortcuts {
+        let tx = tx.clone();
+        key_listener.register_shortcut(keys, move || {
+            if let Some(message) = message {
+                eprintln!("{}", message);
+            }
+            if let Err(_) = tx.send(action) {
+                eprintln!("Key responder died :(")
+            }
+        });
+    }
+}
+
+// What the video recorders are built from. Everything but the buffer lengths needs new recorders (see Supervisor::reconfigure)
+#[derive(Clone)]
+struct VideoSetup {
+    video_codecs: Vec<VideoCodec>,
+    fps: i32,
+    tiers: Vec<(u32, VideoEncoderSettings)>, // (max_seconds, encoder settings), the long replay second
+    transform: VideoTransformSettings,
+}
+
+impl VideoSetup {
+    fn new(config: &Config) -> Self {
+        let mut tiers = vec![(config.recorder.max_seconds, config.video.clone())];
+        if let Some(long_replay) = &config.long_replay {
+            tiers.push((long_replay.max_seconds, long_replay.video.clone()));
+        }
+        Self {
+            video_codecs: config.recorder.video_codecs.clone(),
+            fps: config.recorder.fps,
+            tiers,
+            transform: config.transform.clone(),
+        }
+    }
+
+    // The long replay can't come or go while running, its shortcuts and status are set up at startup
+    fn reloaded(&self, config: &Config) -> Self {
+        let mut reloaded = Self::new(config);
+        reloaded.tiers.truncate(self.tiers.len());
+        reloaded.tiers.extend(self.tiers.iter().skip(reloaded.tiers.len()).cloned());
+        reloaded
+    }
+
+    fn tier_refs(&self) -> Vec<(u32, &VideoEncoderSettings)> {
+        self.tiers.iter().map(|(min_secs, settings)| (*min_secs, settings)).collect()
+    }
+
+    fn tier_secs(&self) -> Vec<u32> {
+        self.tiers.iter().map(|(min_secs, _)| *min_secs).collect()
+    }
+
+    fn replay_secs(&self) -> u32 {
+        self.tiers[0].0
+    }
+
+    // audio is kept as long as the longest replay
+    fn audio_secs(&self) -> u32 {
+        self.tier_secs().into_iter().max().unwrap_or_default()
+    }
+
+    fn same_encoding(&self, other: &VideoSetup) -> bool {
+        let same_encoders = self.tiers.iter().map(|(_, settings)| settings).eq(other.tiers.iter().map(|(_, settings)| settings));
+        self.video_codecs == other.video_codecs && self.fps == other.fps && self.transform == other.transform && same_encoders
+    }
+}
+
+// Builds the recorders of one video source, on top of the given ring buffers if any (see Supervisor)
+fn video_factory(
+    video_source: &VideoSourceSettings,
+    video_codec: VideoCodec,
+    video_setup: &VideoSetup,
+    time_origin: Instant,
+) -> RecorderFactory<VideoPacketRingBufferType> {
+    let VideoSourceSettings { source, width, height, .. } = video_source.clone();
+    let video_setup = video_setup.clone();
+    Box::new(move |ring_buffers, start_delay_secs| {
+        create_video_recorders(&source, &video_codec, &video_setup.tier_refs(), width, height, video_setup.fps, start_delay_secs, &video_setup.transform, time_origin, ring_buffers)
+    })
+}
+
+// Brings the recorders to a reloaded config. Buffer lengths change in place, the video recorders of other encoder settings
+// replace the running ones, trying the codecs in order like at startup. Clips spanning the switch are split (see save_clip)
+async fn reload_recorders(
+    running: &VideoSetup,
+    reloaded: &VideoSetup,
+    video_sources: &[VideoSourceSettings],
+    video_supervisor: &mut Supervisor<VideoPacketRingBufferType>,
+    video_tracks: &[(usize, String, Option<TrackSettings>)],
+    video_encoders: &mut [VideoCodec],
+    audio_supervisor: &mut Supervisor<AudioPacketRingBufferType>,
+    input_index: usize,
+    audio_recorder: &AudioProcessWatcher<AudioPacketRingBufferType>,
+    time_origin: Instant,
+    events: &Events,
+) {
+    if reloaded.tier_secs() != running.tier_secs() {
+        for (index, _, _) in video_tracks.iter() {
+            video_supervisor.set_retention(*index, &reloaded.tier_secs());
+        }
+        audio_supervisor.set_retention(input_index, &[reloaded.audio_secs()]);
+        audio_recorder.set_retention(reloaded.audio_secs()).await;
+    }
+
+    if reloaded.same_encoding(running) {
+        return;
+    }
+    for (((index, title, _), video_source), video_encoder) in video_tracks.iter().zip(video_sources.iter()).zip(video_encoders.iter_mut()) {
+        let reconfigured = reloaded.video_codecs.iter().find(|video_codec| {
+            match video_supervisor.reconfigure(*index, video_factory(video_source, **video_codec, reloaded, time_origin)) {
+                Ok(()) => { true }
+                Err(err) => {
+                    eprintln!("Video encoder {} unavailable: {:?}", video_codec.encoder_name(), err);
+                    false
+                }
+            }
+        });
+        match reconfigured {
+            Some(video_codec) => { *video_encoder = *video_codec; }
+            None => {
+                let problem = format!("Couldn't switch {} to the new video settings, it keeps recording with the old ones", title);
+                eprintln!("{}", problem);
+                events.publish(Event::Error { message: problem });
+            }
+        }
+    }
+}
+
+fn audio_factory(
+    source: AudioSourceType,
+    audio_codec: AudioCodec,
+    audio_format: AudioFormatSettings,
+    min_secs: u32,
+    gain: f32,
+    taps: Vec<AudioTap>,
+) -> RecorderFactory<AudioPacketRingBufferType> {
+    Box::new(move |ring_buffers, start_delay_secs| {
+        create_audio_recorder(&source, &audio_codec, &audio_format, min_secs, start_delay_secs, gain, &taps, ring_buffers.first().cloned()).map(|recorder| vec![recorder])
+    })
+}
+
+// Returns the saved files, more than one if the clip spans a reconfiguration
+async fn save_clip(
+    save_env: &SaverEnv,
+    file_name: Option<&str>,
+    replay: Replay,
+    seconds: u32,
+    audio_seconds: u32,
+    time_origin: Instant,
+    pause: &Pause,
+    video_supervisor: &Supervisor<VideoPacketRingBufferType>,
+    video_tracks: &[(usize, String, Option<TrackSettings>)],
+    audio_supervisor: &Supervisor<AudioPacketRingBufferType>,
+    input_index: usize,
+    input_settings: Option<&TrackSettings>,
+    audio_recorder: &AudioProcessWatcher<AudioPacketRingBufferType>,
+    voice_trigger: Option<&VoiceTrigger>,
+    markers: &Markers,
+) -> Result<Vec<String>> {
+    // audio is kept as long as the longest replay, so it's cut to the video's length
+    let (tier, max_secs) = match replay {
+        Replay::Short => { (0, seconds) }
+        Replay::Long => { (1, audio_seconds) }
+        Replay::Last(secs) => {
+            // audio_seconds is only longer than seconds with a long replay
+            match secs > seconds && audio_seconds > seconds {
+                true => { (1, secs.min(audio_seconds)) }
+                false => { (0, secs.min(seconds)) }
+            }
+        }
+    };
+
+    // streams can't change their encoder parameters midway, so a clip spanning a reconfiguration is saved as one file per configuration.
+    // All streams share a clock in seconds since time_origin
+    let window_start = time_origin.elapsed().as_secs_f64() - max_secs as f64;
+    let mut switches: Vec<Instant> = video_tracks.iter()
+        .flat_map(|(index, _, _)| video_supervisor.switches(*index))
+        .chain(audio_supervisor.switches(input_index))
+        .filter(|at| at.duration_since(time_origin).as_secs_f64() > window_start)
+        .collect();
+    switches.sort();
+    switches.dedup();
+
+    let mut files = Vec::new();
+    let mut part_start = None;
+    for part_end in switches.iter().copied().map(Some).chain([None]) {
+        let at = part_start.unwrap_or(time_origin + Duration::from_secs_f64(window_start.max(0.)));
+        let secs_since_origin = |instant: Option<Instant>| instant.map(|instant| instant.duration_since(time_origin).as_secs_f64());
+
+        let save = match file_name {
+            None => { save_env.new_save::<String>(None) }
+            Some(file_name) => { save_env.new_named_save(file_name) }
+        };
+        let mut save = match save {
+            Ok(save) => save,
+            Err(error) => {
+                eprintln!("Couldn't create clip: {:?}", error);
+                return Err(error);
+            }
+        };
+        save.set_gaps(pause.gaps_since(time_origin));
+        save.set_markers(markers.all());
+        match switches.is_empty() {
+            true => { save.set_max_duration(Some(max_secs as f64)); }
+            false => { save.set_time_range(Some(secs_since_origin(part_start).unwrap_or(window_start)), secs_since_origin(part_end)); }
+        }
+        add_streams(&mut save, tier, at, video_supervisor, video_tracks, audio_supervisor, input_index, input_settings, audio_recorder).await;
+        if let Some(voice_trigger) = voice_trigger {
+            save.set_silenced("Main Audio", voice_trigger.commands());
+        }
+
+        let saved_file = save.file_name().to_string();
+        if let Err(error) = save.finalize_and_save() {
+            eprintln!("Couldn't save clip: {:?}", error);
+            return Err(error);
+        }
+        files.push(saved_file);
+        part_start = part_end;
+    }
+    Ok(files)
+}
+
+// The recorders that were current at `at`
+async fn add_streams(
+    save: &mut Save,
+    tier: usize,
+    at: Instant,
+    video_supervisor: &Supervisor<VideoPacketRingBufferType>,
+    video_tracks: &[(usize, String, Option<TrackSettings>)],
+    audio_supervisor: &Supervisor<AudioPacketRingBufferType>,
+    input_index: usize,
+    input_settings: Option<&TrackSettings>,
+    audio_recorder: &AudioProcessWatcher<AudioPacketRingBufferType>,
+) {
+    for (index, title, settings) in video_tracks.iter() {
+        if let Some(video_recorder) = video_supervisor.recorders_at(*index, at).get(tier) {
+            save.add_stream(video_recorder, true, Some(title.as_str()), settings.as_ref()).unwrap();
+        }
+    }
+    for audio_recorder_input in audio_supervisor.recorders_at(input_index, at) {
+        save.add_stream(audio_recorder_input, false, Some("Main Audio"), input_settings).unwrap();
+    }
+
+    for (key, track) in audio_recorder.audio_recorders.lock().await.iter() {
+        save.add_stream_if_audible(&track.recorder, &track.name, track.settings.as_ref()).unwrap();
+        debug_println!("stream added for: {:?}", key);
+    }
+    for track in audio_recorder.orphan_recorders.lock().await.iter() {
+        save.add_stream_if_audible(&track.recorder, &track.name, track.settings.as_ref()).unwrap();
+        debug_println!("orphaned stream added for: {}", track.name);
+    }
+}
+
+// The shortcut, `ctl mark` and POST /mark, nothing is saved until the next clip
+fn add_marker(
+    markers: &mut Markers,
+    label: Option<String>,
+    events: &Events,
+) {
+    match &label {
+        Some(label) => { eprintln!("Marked: {}", label); }
+        None => { eprintln!("Marked"); }
+    }
+    markers.add(label.clone());
+    events.publish(Event::Marked { label });
+}
+
+// Keeps the outcome for `status` and tells the event listeners
+fn publish_save(
+    result: &Result<Vec<String>>,
+    events: &Events,
+) -> SaveResult {
+    match result {
+        Ok(files) => { events.publish(Event::ClipSaved { files: files.clone() }); }
+        Err(err) => { events.publish(Event::Error { message: format!("Couldn't save clip: {}", err) }); }
+    }
+    SaveResult::new(result)
+}
+
+// What `ctl status` shows
+async fn status(
+    pause: &Pause,
+    seconds: u32,
+    video_supervisor: &Supervisor<VideoPacketRingBufferType>,
+    video_tracks: &[(usize, String, Option<TrackSettings>)],
+    video_encoders: &[VideoCodec],
+    audio_supervisor: &Supervisor<AudioPacketRingBufferType>,
+    input_index: usize,
+    audio_codec: AudioCodec,
+    audio_recorder: &AudioProcessWatcher<AudioPacketRingBufferType>,
+    last_save: Option<&SaveResult>,
+) -> Status {
+    let mut buffers = Vec::new();
+    for (index, title, _) in video_tracks.iter() {
+        for (tier, video_recorder) in video_supervisor.recorders(*index).iter().enumerate() {
+            let name = match tier {
+                0 => { title.clone() }
+                _ => { format!("{} (long replay)", title) }
+            };
+            buffers.push(BufferStatus { name, buffered_secs: video_recorder.buffered_secs() });
+        }
+    }
+    for audio_recorder_input in audio_supervisor.recorders(input_index) {
+        buffers.push(BufferStatus { name: "Main Audio".to_string(), buffered_secs: audio_recorder_input.buffered_secs() });
+    }
+
+    let mut process_tracks = Vec::new();
+    for track in audio_recorder.audio_recorders.lock().await.values() {
+        process_tracks.push(BufferStatus { name: track.name.clone(), buffered_secs: track.recorder.buffered_secs() });
+    }
+    for track in audio_recorder.orphan_recorders.lock().await.iter() {
+        process_tracks.push(BufferStatus { name: format!("{} (exited)", track.name), buffered_secs: track.recorder.buffered_secs() });
+    }
+
+    Status {
+        paused: pause.is_paused(),
+        replay_secs: seconds,
+        buffers,
+        process_tracks,
+        video_encoders: video_encoders.iter().map(|video_codec| video_codec.encoder_name().to_string()).collect(),
+        audio_codec: format!("{:?}", audio_codec),
+        last_save: last_save.cloned(),
+    }
+}
+
+// Ctrl+C, closing the console window or Windows shutting down, SIGTERM elsewhere
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(windows)]
+    {
+        let mut ctrl_close = tokio::signal::windows::ctrl_close()?;
+        let mut ctrl_shutdown = tokio::signal::windows::ctrl_shutdown()?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => { result?; }
+            _ = ctrl_close.recv() => {}
+            _ = ctrl_shutdown.recv() => {}
+        }
+    }
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => { result?; }
+            _ = terminate.recv() => {}
+        }
+    }
+    Ok(())
 }
\ No newline at end of file
@@ -6,7 +6,8 @@ use ffmpeg_next::encoder::audio::Encoder;
 use ffmpeg_next::util::frame::audio::Audio;
 
 use crate::recorders::audio::sources::traits::AudioSource;
-use crate::recorders::traits::TRecorder;
+use crate::recorders::supervisor::Heartbeat;
+use crate::recorders::traits::{drain_encoder, RingBufferWriter, TRecorder};
 use crate::ring_buffer::traits::PacketRingBuffer;
 use crate::types::{RecorderJoinHandle, Result};
 
@@ -42,26 +43,34 @@ impl<PRB: PacketRingBuffer + 'static, AS: AudioSource + Send + 'static> TRecorde
     fn start_capturing(
         mut self: Box<Self>,
         stop_capturing_callback: Option<Arc<AtomicBool>>,
+        heartbeat: Option<Arc<Heartbeat>>,
+        paused: Option<Arc<AtomicBool>>,
     ) -> RecorderJoinHandle {
-        fn help<PRB: PacketRingBuffer, AS: AudioSource + Send>(selbst: &mut Box<AudioRecorder<PRB, AS>>) {
+        fn help<PRB: PacketRingBuffer, AS: AudioSource + Send>(selbst: &mut Box<AudioRecorder<PRB, AS>>, ring_buffer: &RingBufferWriter<PRB>, paused: bool) -> Result<()> {
             selbst.audio_source.await_new_audio();
 
-            selbst.audio_source.gather_new_audio(&selbst.ring_buffer, &mut selbst.audio_encoder, &mut selbst.frame, &mut selbst.silent_frame).unwrap_or_else(|err| panic!("AudioRecorder: Failed to send_frame_and_receive_packets because: {:?}", err));
+            match paused {
+                true => { selbst.audio_source.skip_new_audio() }
+                false => { selbst.audio_source.gather_new_audio(ring_buffer, &mut selbst.audio_encoder, &mut selbst.frame, &mut selbst.silent_frame) }
+            }
         }
 
+        // taken now, a restart after this thread got stuck retires it before the new recorder starts
+        let ring_buffer = RingBufferWriter::new(&self.ring_buffer);
         thread::spawn(move || -> Result<()> {
-            self.audio_source.init().unwrap_or_else(|err| panic!("Failed to init VideoRecorder: {:?}", err));
+            self.audio_source.init()?;
 
-            if let Some(stop_capturing_callback) = stop_capturing_callback {
-                while stop_capturing_callback.load(Ordering::Relaxed) {
-                    help(&mut self);
-                }
-                Ok(())
-            } else {
-                loop {
-                    help(&mut self);
+            let running = stop_capturing_callback.unwrap_or_else(|| Arc::new(AtomicBool::new(true)));
+            while running.load(Ordering::Relaxed) {
+                help(&mut self, &ring_buffer, paused.as_ref().is_some_and(|paused| paused.load(Ordering::Relaxed)))?;
+                if let Some(heartbeat) = &heartbeat {
+                    heartbeat.beat();
                 }
             }
+
+            let selbst = &mut *self;
+            selbst.audio_source.finish(&ring_buffer, &mut selbst.audio_encoder, &mut selbst.frame, &mut selbst.silent_frame)?;
+            drain_encoder(&ring_buffer, &mut selbst.audio_encoder, selbst.frame.samples() as i64)
         })
     }
 }
\ No newline at end of file
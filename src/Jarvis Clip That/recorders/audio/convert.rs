@@ -0,0 +1,354 @@
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleEncoding {
+    I16,
+    I24, // packed into 3 bytes
+    I32, // also 24 valid bits in a 32 bit container, those are left-justified
+    F32,
+}
+
+impl SampleEncoding {
+    pub fn bytes(&self) -> usize {
+        match self {
+            SampleEncoding::I16 => 2,
+            SampleEncoding::I24 => 3,
+            SampleEncoding::I32 | SampleEncoding::F32 => 4,
+        }
+    }
+}
+
+// Speaker positions as used by WAVEFORMATEXTENSIBLE's dwChannelMask (ffmpeg's native channel order uses the same bits)
+pub const SPEAKER_FRONT_LEFT: u32 = 0x1;
+pub const SPEAKER_FRONT_RIGHT: u32 = 0x2;
+pub const SPEAKER_FRONT_CENTER: u32 = 0x4;
+pub const SPEAKER_LOW_FREQUENCY: u32 = 0x8;
+pub const SPEAKER_BACK_LEFT: u32 = 0x10;
+pub const SPEAKER_BACK_RIGHT: u32 = 0x20;
+pub const SPEAKER_FRONT_LEFT_OF_CENTER: u32 = 0x40;
+pub const SPEAKER_FRONT_RIGHT_OF_CENTER: u32 = 0x80;
+pub const SPEAKER_SIDE_LEFT: u32 = 0x200;
+pub const SPEAKER_SIDE_RIGHT: u32 = 0x400;
+pub const SPEAKER_TOP_FRONT_LEFT: u32 = 0x1000;
+pub const SPEAKER_TOP_FRONT_RIGHT: u32 = 0x4000;
+pub const SPEAKER_TOP_BACK_LEFT: u32 = 0x8000;
+pub const SPEAKER_TOP_BACK_RIGHT: u32 = 0x20000;
+
+const LEFT_SPEAKERS: u32 = SPEAKER_FRONT_LEFT_OF_CENTER | SPEAKER_BACK_LEFT | SPEAKER_SIDE_LEFT | SPEAKER_TOP_FRONT_LEFT | SPEAKER_TOP_BACK_LEFT;
+const RIGHT_SPEAKERS: u32 = SPEAKER_FRONT_RIGHT_OF_CENTER | SPEAKER_BACK_RIGHT | SPEAKER_SIDE_RIGHT | SPEAKER_TOP_FRONT_RIGHT | SPEAKER_TOP_BACK_RIGHT;
+const MINUS_3_DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+// What a source delivers, independent of the API it comes from
+#[derive(Clone, Copy, Debug)]
+pub struct InputFormat {
+    pub encoding: SampleEncoding,
+    pub channels: u16,
+    pub channel_mask: u32,
+    pub sample_rate: u32,
+}
+
+impl InputFormat {
+    pub fn block_align(&self) -> usize {
+        self.encoding.bytes() * self.channels as usize
+    }
+
+    pub fn default_channel_mask(channels: u16) -> u32 {
+        match channels {
+            1 => SPEAKER_FRONT_CENTER,
+            ch if ch <= 18 => (1 << ch) - 1,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct AudioFormatSettings {
+    pub downmix_to_stereo: bool, // sources with more than 2 channels (5.1, 7.1, ...) are mixed down before encoding
+    pub sample_rate: Option<u32>, // resample every source to this rate, None keeps each source's own rate
+}
+
+// Turns raw interleaved samples of any InputFormat into interleaved f32 at the output channel count and rate
+pub struct AudioConverter {
+    input: InputFormat,
+    output_rate: u32,
+
+    downmix: Option<[Vec<f32>; 2]>, // per output channel, the weight of every input channel
+    resampler: Option<LinearResampler>,
+}
+
+impl AudioConverter {
+    pub fn new(
+        input: InputFormat,
+        settings: &AudioFormatSettings,
+    ) -> Self {
+        let output_rate = settings.sample_rate.unwrap_or(input.sample_rate);
+        let mut converter = Self {
+            input,
+            output_rate,
+
+            downmix: None,
+            resampler: None,
+        };
+        if settings.downmix_to_stereo {
+            converter.downmix_to_stereo();
+        }
+        converter.resampler = (output_rate != input.sample_rate).then(|| LinearResampler::new(input.sample_rate, output_rate, converter.output_channels()));
+        converter
+    }
+
+    pub fn downmix_to_stereo(&mut self) {
+        if self.input.channels <= 2 || self.downmix.is_some() {
+            return;
+        }
+        self.downmix = Some(stereo_downmix_matrix(self.input.channels, self.input.channel_mask));
+        if let Some(resampler) = &mut self.resampler {
+            *resampler = LinearResampler::new(self.input.sample_rate, self.output_rate, 2);
+        }
+    }
+
+    pub fn resample_to(&mut self, sample_rate: u32) {
+        self.output_rate = sample_rate;
+        self.resampler = (sample_rate != self.input.sample_rate).then(|| LinearResampler::new(self.input.sample_rate, sample_rate, self.output_channels()));
+    }
+
+    pub fn input(&self) -> &InputFormat {
+        &self.input
+    }
+
+    pub fn output_channels(&self) -> usize {
+        match self.downmix {
+            Some(_) => 2,
+            None => self.input.channels as usize,
+        }
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn convert(&mut self, bytes: &[u8]) -> Vec<f32> {
+        let samples: Vec<f32> = bytes.chunks_exact(self.input.encoding.bytes()).map(|bytes| decode_sample(self.input.encoding, bytes)).collect();
+
+        let samples = match &self.downmix {
+            Some(matrix) => {
+                let channels = self.input.channels as usize;
+                samples.chunks_exact(channels).flat_map(|frame| {
+                    matrix.iter().map(move |weights| frame.iter().zip(weights).map(|(sample, weight)| sample * weight).sum::<f32>())
+                }).collect()
+            }
+            None => samples,
+        };
+
+        match &mut self.resampler {
+            Some(resampler) => resampler.process(&samples),
+            None => samples,
+        }
+    }
+}
+
+pub fn decode_sample(encoding: SampleEncoding, bytes: &[u8]) -> f32 {
+    match encoding {
+        SampleEncoding::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.,
+        SampleEncoding::I24 => (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8388608.,
+        SampleEncoding::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 2147483648.,
+        SampleEncoding::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    }
+}
+
+// ITU style: centers at -3 dB into both sides, surrounds at -3 dB into their side, LFE dropped.
+// Normalized afterwards, so a full scale signal on every speaker can't clip
+fn stereo_downmix_matrix(
+    channels: u16,
+    channel_mask: u32,
+) -> [Vec<f32>; 2] {
+    let mut speakers = (0..32).map(|bit| 1u32 << bit).filter(|speaker| channel_mask & speaker != 0);
+
+    let mut left = Vec::with_capacity(channels as usize);
+    let mut right = Vec::with_capacity(channels as usize);
+    for _ in 0..channels {
+        let (l, r) = match speakers.next() {
+            Some(SPEAKER_FRONT_LEFT) => (1., 0.),
+            Some(SPEAKER_FRONT_RIGHT) => (0., 1.),
+            Some(SPEAKER_LOW_FREQUENCY) => (0., 0.),
+            Some(speaker) if speaker & LEFT_SPEAKERS != 0 => (MINUS_3_DB, 0.),
+            Some(speaker) if speaker & RIGHT_SPEAKERS != 0 => (0., MINUS_3_DB),
+            _ => (MINUS_3_DB, MINUS_3_DB), // centers and channels without a position
+        };
+        left.push(l);
+        right.push(r);
+    }
+
+    let loudest = left.iter().sum::<f32>().max(right.iter().sum::<f32>()).max(1.);
+    left.iter_mut().chain(right.iter_mut()).for_each(|weight| *weight /= loudest);
+    [left, right]
+}
+
+// Streaming linear interpolation, good enough for the 44.1k <-> 48k conversions between devices
+pub struct LinearResampler {
+    channels: usize,
+    step: f64, // input frames per output frame
+    position: f64, // of the next output frame, relative to `last_frame`
+    last_frame: Option<Vec<f32>>,
+}
+
+impl LinearResampler {
+    pub fn new(
+        input_rate: u32,
+        output_rate: u32,
+        channels: usize,
+    ) -> Self {
+        Self {
+            channels,
+            step: input_rate as f64 / output_rate as f64,
+            position: 0.,
+            last_frame: None,
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        let channels = self.channels;
+        let mut frames: Vec<f32> = self.last_frame.take().unwrap_or_default();
+        frames.extend_from_slice(samples);
+
+        let frame_count = frames.len() / channels;
+        if frame_count == 0 {
+            return Vec::new();
+        }
+
+        let mut output = Vec::with_capacity(((frame_count as f64 / self.step) as usize + 1) * channels);
+        while self.position + 1. < frame_count as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let (current, next) = (&frames[index * channels..(index + 1) * channels], &frames[(index + 1) * channels..(index + 2) * channels]);
+            output.extend(current.iter().zip(next).map(|(a, b)| a + (b - a) * fraction));
+            self.position += self.step;
+        }
+
+        // keep the last frame around to interpolate towards the next chunk
+        self.position -= (frame_count - 1) as f64;
+        self.last_frame = Some(frames[(frame_count - 1) * channels..frame_count * channels].to_vec());
+        output
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn decodes_every_encoding_to_full_scale() {
+        for (value, expected) in [(i16::MIN, -1.), (0, 0.), (16384, 0.5), (i16::MAX, 32767. / 32768.)] {
+            assert!(close(decode_sample(SampleEncoding::I16, &value.to_le_bytes()), expected), "i16 {value}");
+        }
+        for (value, expected) in [(-0x800000i32, -1.), (0x400000, 0.5), (-0x400000, -0.5), (1, 1. / 8388608.)] {
+            let bytes = value.to_le_bytes();
+            assert!(close(decode_sample(SampleEncoding::I24, &bytes[..3]), expected), "i24 {value}");
+        }
+        // 24 valid bits are left-justified, so their lowest bit is 0x100
+        for (value, expected) in [(i32::MIN, -1.), (0x40000000, 0.5), (-0x40000000, -0.5), (0x100, 1. / 8388608.)] {
+            assert!(close(decode_sample(SampleEncoding::I32, &value.to_le_bytes()), expected), "i32 {value}");
+        }
+        for value in [-1f32, -0.25, 0., 0.75, 1.5] {
+            assert_eq!(decode_sample(SampleEncoding::F32, &value.to_le_bytes()), value);
+        }
+    }
+
+    #[test]
+    fn converts_interleaved_bytes_without_changes() {
+        let input = InputFormat { encoding: SampleEncoding::I16, channels: 2, channel_mask: 0x3, sample_rate: 48_000 };
+        let mut converter = AudioConverter::new(input, &AudioFormatSettings::default());
+        let bytes: Vec<u8> = [16384i16, -16384, 0, 8192].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+
+        assert_eq!(converter.convert(&bytes), vec![0.5, -0.5, 0., 0.25]);
+        assert_eq!(converter.output_channels(), 2);
+        assert_eq!(converter.output_rate(), 48_000);
+    }
+
+    #[test]
+    fn downmixes_5_1_with_centers_and_surrounds_at_minus_3_db() {
+        // FL FR FC LFE BL BR
+        let [left, right] = stereo_downmix_matrix(6, 0x3F);
+        let loudest = 1. + 2. * MINUS_3_DB;
+        let expected_left = [1. / loudest, 0., MINUS_3_DB / loudest, 0., MINUS_3_DB / loudest, 0.];
+        let expected_right = [0., 1. / loudest, MINUS_3_DB / loudest, 0., 0., MINUS_3_DB / loudest];
+
+        assert!(left.iter().zip(expected_left).all(|(weight, expected)| close(*weight, expected)), "{left:?}");
+        assert!(right.iter().zip(expected_right).all(|(weight, expected)| close(*weight, expected)), "{right:?}");
+        // full scale on every speaker reaches full scale on both sides, no more
+        assert!(close(left.iter().sum(), 1.) && close(right.iter().sum(), 1.));
+    }
+
+    #[test]
+    fn downmixes_7_1_sides_to_their_side() {
+        // FL FR FC LFE BL BR SL SR
+        let [left, right] = stereo_downmix_matrix(8, 0x63F);
+
+        assert!(left[6] > 0. && close(right[6], 0.));
+        assert!(right[7] > 0. && close(left[7], 0.));
+        assert!(close(left[3], 0.) && close(right[3], 0.));
+        assert!(close(left.iter().sum(), 1.) && close(right.iter().sum(), 1.));
+    }
+
+    #[test]
+    fn channels_without_a_position_go_to_both_sides() {
+        let [left, right] = stereo_downmix_matrix(4, 0);
+
+        assert_eq!(left, right);
+        assert!(close(left.iter().sum(), 1.));
+    }
+
+    #[test]
+    fn only_more_than_two_channels_are_downmixed() {
+        let settings = AudioFormatSettings { downmix_to_stereo: true, sample_rate: None };
+        let stereo = InputFormat { encoding: SampleEncoding::F32, channels: 2, channel_mask: 0x3, sample_rate: 48_000 };
+        let surround = InputFormat { channels: 6, channel_mask: 0x3F, ..stereo };
+
+        assert_eq!(AudioConverter::new(stereo, &settings).output_channels(), 2);
+        let mut converter = AudioConverter::new(surround, &settings);
+        assert_eq!(converter.output_channels(), 2);
+
+        // a full scale front left only reaches the left side
+        let bytes: Vec<u8> = [1f32, 0., 0., 0., 0., 0.].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        let output = converter.convert(&bytes);
+        assert_eq!(output.len(), 2);
+        assert!(close(output[0], 1. / (1. + 2. * MINUS_3_DB)) && close(output[1], 0.));
+    }
+
+    #[test]
+    fn resampler_output_length_follows_the_rate() {
+        for (input_rate, output_rate) in [(48_000, 44_100), (44_100, 48_000), (48_000, 16_000), (16_000, 48_000)] {
+            let mut resampler = LinearResampler::new(input_rate, output_rate, 2);
+            let input_frames = input_rate as usize; // a second
+            let output_frames: usize = (0..100).map(|_| resampler.process(&vec![0.; input_frames / 100 * 2]).len() / 2).sum();
+            // the last input frame waits for the next chunk
+            let expected = output_rate as f64;
+            assert!((output_frames as f64 - expected).abs() <= output_rate as f64 / input_rate as f64 + 1., "{input_rate} -> {output_rate}: {output_frames}");
+        }
+    }
+
+    #[test]
+    fn resampler_chunks_dont_change_the_output() {
+        let ramp: Vec<f32> = (0..4800).map(|i| (i as f32 * 0.01).sin()).collect();
+        let whole = LinearResampler::new(48_000, 44_100, 1).process(&ramp);
+
+        let mut resampler = LinearResampler::new(48_000, 44_100, 1);
+        let chunked: Vec<f32> = ramp.chunks(441).flat_map(|chunk| resampler.process(chunk)).collect();
+
+        assert_eq!(whole.len(), chunked.len());
+        assert!(whole.iter().zip(chunked.iter()).all(|(a, b)| (a - b).abs() < 1e-4));
+    }
+
+    #[test]
+    fn resampler_interpolates_between_frames() {
+        let mut resampler = LinearResampler::new(24_000, 48_000, 2);
+
+        assert_eq!(resampler.process(&[0., 1., 1., 0., 2., -1.]), vec![0., 1., 0.5, 0.5, 1., 0., 1.5, -0.5]);
+        assert_eq!(resampler.process(&[]), Vec::<f32>::new());
+    }
+}
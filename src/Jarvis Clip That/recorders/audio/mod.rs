@@ -1,2 +1,4 @@
 pub mod audio_recorder;
-pub mod sources;
\ No newline at end of file
+pub mod sources;
+pub mod convert;
+pub mod tap;
\ No newline at end of file
@@ -1,9 +1,21 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
 pub enum AudioCodec {
+    #[serde(rename = "aac")]
     AAC,
+    Opus,
+    Flac,
 }
 
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "type")]
 pub enum AudioSourceType {
+    #[serde(rename = "default_output")]
     WasApiDefaultSys,
+    #[serde(rename = "process")]
     WasApiProcess { process_id: u32, include_tree: bool },
+    #[serde(rename = "default_input")]
     WasApiDefaultInput,
 }
\ No newline at end of file
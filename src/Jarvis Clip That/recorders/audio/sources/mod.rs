@@ -1,3 +1,4 @@
 pub mod traits;
 pub mod enums;
-pub mod wasapi;
\ No newline at end of file
+pub mod wasapi;
+pub mod process_rules;
\ No newline at end of file
@@ -0,0 +1,327 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+
+#[derive(Clone, Debug)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub parent_pid: Option<u32>,
+    pub name: String,
+}
+
+pub trait ProcessTable {
+    fn process(&self, pid: u32) -> Option<&ProcessInfo>;
+}
+
+impl ProcessTable for HashMap<u32, ProcessInfo> {
+    fn process(&self, pid: u32) -> Option<&ProcessInfo> {
+        self.get(&pid)
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessGrouping {
+    #[default]
+    None, // one track per PID
+    Executable, // one track per exe name
+    ProcessTree, // one track per topmost ancestor with the same exe name (browser/electron child processes)
+}
+
+#[derive(Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ProcessRules {
+    // exe names, case-insensitive. An empty allow list allows everything, deny always wins
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub grouping: ProcessGrouping,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TrackKey {
+    Process(u32),
+    Executable(String),
+    ProcessTree(u32),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RuleDecision {
+    Ignore,
+    Record { key: TrackKey, name: String },
+}
+
+pub const UNKNOWN_PROCESS_NAME: &str = "UNKNOWN???";
+
+impl ProcessRules {
+    pub fn decide<T: ProcessTable>(
+        &self,
+        pid: u32,
+        table: &T,
+    ) -> RuleDecision {
+        let info = table.process(pid);
+        let name = info.map_or(UNKNOWN_PROCESS_NAME, |info| info.name.as_str());
+
+        if Self::matches(&self.deny, name) || (!self.allow.is_empty() && !Self::matches(&self.allow, name)) {
+            return RuleDecision::Ignore;
+        }
+
+        let key = match (self.grouping, info) {
+            (ProcessGrouping::None, _) | (_, None) => TrackKey::Process(pid),
+            (ProcessGrouping::Executable, Some(info)) => TrackKey::Executable(info.name.to_ascii_lowercase()),
+            (ProcessGrouping::ProcessTree, Some(info)) => TrackKey::ProcessTree(Self::tree_root(info, table)),
+        };
+
+        RuleDecision::Record { key, name: name.to_string() }
+    }
+
+    pub fn groups_processes(&self) -> bool {
+        self.grouping != ProcessGrouping::None
+    }
+
+    fn matches(list: &[String], name: &str) -> bool {
+        list.iter().any(|entry| entry.eq_ignore_ascii_case(name))
+    }
+
+    fn tree_root<T: ProcessTable>(
+        info: &ProcessInfo,
+        table: &T,
+    ) -> u32 {
+        let mut root = info;
+        let mut visited = HashSet::from([root.pid]);
+        while let Some(parent) = root.parent_pid.and_then(|parent_pid| table.process(parent_pid)) {
+            // pids get reused, so a parent chain can loop
+            if !parent.name.eq_ignore_ascii_case(&info.name) || !visited.insert(parent.pid) {
+                break;
+            }
+            root = parent;
+        }
+        root.pid
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionChange {
+    Ignored,
+    AlreadyKnown,
+    NewTrack { key: TrackKey, name: String },
+    JoinedTrack { key: TrackKey },
+}
+
+// Bookkeeping of which PIDs feed which track, driven by audio session notifications
+#[derive(Default)]
+pub struct ProcessTracks {
+    rules: ProcessRules,
+    tracks: HashMap<TrackKey, HashSet<u32>>,
+    pid_to_key: HashMap<u32, TrackKey>,
+}
+
+impl ProcessTracks {
+    pub fn new(rules: ProcessRules) -> Self {
+        Self {
+            rules,
+            tracks: HashMap::new(),
+            pid_to_key: HashMap::new(),
+        }
+    }
+
+    pub fn rules(&self) -> &ProcessRules {
+        &self.rules
+    }
+
+    pub fn session_added<T: ProcessTable>(
+        &mut self,
+        pid: u32,
+        table: &T,
+    ) -> SessionChange {
+        if self.pid_to_key.contains_key(&pid) {
+            return SessionChange::AlreadyKnown;
+        }
+
+        let RuleDecision::Record { key, name } = self.rules.decide(pid, table) else {
+            return SessionChange::Ignored;
+        };
+
+        self.pid_to_key.insert(pid, key.clone());
+        match self.tracks.get_mut(&key) {
+            Some(members) => {
+                members.insert(pid);
+                SessionChange::JoinedTrack { key }
+            }
+            None => {
+                self.tracks.insert(key.clone(), HashSet::from([pid]));
+                SessionChange::NewTrack { key, name }
+            }
+        }
+    }
+
+    // Returns the track the PID belonged to and whether that track has no members left.
+    // Empty tracks are kept until forget_track, so a PID rejoining in the meantime finds its old track
+    pub fn session_removed(
+        &mut self,
+        pid: u32,
+    ) -> Option<(TrackKey, bool)> {
+        let key = self.pid_to_key.remove(&pid)?;
+        let members = self.tracks.get_mut(&key)?;
+        members.remove(&pid);
+        Some((key, members.is_empty()))
+    }
+
+    // The PIDs feeding the track right now, lowest first
+    pub fn members(&self, key: &TrackKey) -> Vec<u32> {
+        let mut members: Vec<u32> = self.tracks.get(key).map(|members| members.iter().copied().collect()).unwrap_or_default();
+        members.sort();
+        members
+    }
+
+    pub fn is_empty_track(&self, key: &TrackKey) -> bool {
+        self.tracks.get(key).is_none_or(|members| members.is_empty())
+    }
+
+    pub fn forget_track(&mut self, key: &TrackKey) {
+        if self.is_empty_track(key) {
+            self.tracks.remove(key);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(processes: &[(u32, Option<u32>, &str)]) -> HashMap<u32, ProcessInfo> {
+        processes.iter().map(|(pid, parent_pid, name)| (*pid, ProcessInfo { pid: *pid, parent_pid: *parent_pid, name: name.to_string() })).collect()
+    }
+
+    fn rules(
+        allow: &[&str],
+        deny: &[&str],
+        grouping: ProcessGrouping,
+    ) -> ProcessRules {
+        ProcessRules {
+            allow: allow.iter().map(|name| name.to_string()).collect(),
+            deny: deny.iter().map(|name| name.to_string()).collect(),
+            grouping,
+        }
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let table = table(&[(1, None, "Discord.exe"), (2, None, "game.exe")]);
+        let rules = rules(&["discord.exe", "game.exe"], &["DISCORD.EXE"], ProcessGrouping::None);
+
+        assert_eq!(rules.decide(1, &table), RuleDecision::Ignore);
+        assert_eq!(rules.decide(2, &table), RuleDecision::Record { key: TrackKey::Process(2), name: "game.exe".to_string() });
+    }
+
+    #[test]
+    fn allow_list_only_records_listed_processes() {
+        let table = table(&[(1, None, "game.exe"), (2, None, "spotify.exe")]);
+        let rules = rules(&["Game.exe"], &[], ProcessGrouping::None);
+
+        assert_eq!(rules.decide(1, &table), RuleDecision::Record { key: TrackKey::Process(1), name: "game.exe".to_string() });
+        assert_eq!(rules.decide(2, &table), RuleDecision::Ignore);
+        // not in the table, so it can't be on the allow list
+        assert_eq!(rules.decide(3, &table), RuleDecision::Ignore);
+    }
+
+    #[test]
+    fn unknown_processes_get_their_own_track() {
+        let rules = rules(&[], &[], ProcessGrouping::Executable);
+
+        assert_eq!(rules.decide(7, &table(&[])), RuleDecision::Record { key: TrackKey::Process(7), name: UNKNOWN_PROCESS_NAME.to_string() });
+    }
+
+    #[test]
+    fn grouping_by_executable() {
+        let table = table(&[(1, None, "chrome.exe"), (2, None, "Chrome.exe"), (3, None, "game.exe")]);
+        let mut tracks = ProcessTracks::new(rules(&[], &[], ProcessGrouping::Executable));
+        let chrome = TrackKey::Executable("chrome.exe".to_string());
+
+        assert_eq!(tracks.session_added(1, &table), SessionChange::NewTrack { key: chrome.clone(), name: "chrome.exe".to_string() });
+        assert_eq!(tracks.session_added(2, &table), SessionChange::JoinedTrack { key: chrome.clone() });
+        assert_eq!(tracks.session_added(2, &table), SessionChange::AlreadyKnown);
+        assert_eq!(tracks.session_added(3, &table), SessionChange::NewTrack { key: TrackKey::Executable("game.exe".to_string()), name: "game.exe".to_string() });
+
+        assert_eq!(tracks.session_removed(1), Some((chrome.clone(), false)));
+        assert_eq!(tracks.session_removed(2), Some((chrome.clone(), true)));
+        assert_eq!(tracks.session_removed(2), None);
+    }
+
+    #[test]
+    fn grouping_by_process_tree() {
+        // two browser instances with child processes, the launcher is another exe and not part of the trees
+        let table = table(&[
+            (1, None, "launcher.exe"),
+            (10, Some(1), "browser.exe"),
+            (11, Some(10), "browser.exe"),
+            (12, Some(11), "browser.exe"),
+            (20, Some(1), "browser.exe"),
+            (21, Some(20), "Browser.exe"),
+        ]);
+        let rules = rules(&[], &[], ProcessGrouping::ProcessTree);
+
+        for (pid, root) in [(10, 10), (11, 10), (12, 10), (20, 20), (21, 20), (1, 1)] {
+            let RuleDecision::Record { key, .. } = rules.decide(pid, &table) else {
+                panic!("{pid} was ignored");
+            };
+            assert_eq!(key, TrackKey::ProcessTree(root), "root of {pid}");
+        }
+    }
+
+    #[test]
+    fn process_tree_survives_looping_parents() {
+        // reused pids can make two processes each other's parent
+        let table = table(&[(1, Some(2), "app.exe"), (2, Some(1), "app.exe")]);
+        let rules = rules(&[], &[], ProcessGrouping::ProcessTree);
+
+        assert_eq!(rules.decide(1, &table), RuleDecision::Record { key: TrackKey::ProcessTree(2), name: "app.exe".to_string() });
+    }
+
+    #[test]
+    fn reappearing_process_rejoins_its_track_until_forgotten() {
+        let table = table(&[(1, None, "game.exe")]);
+        let mut tracks = ProcessTracks::new(rules(&[], &[], ProcessGrouping::None));
+        let key = TrackKey::Process(1);
+
+        assert_eq!(tracks.session_added(1, &table), SessionChange::NewTrack { key: key.clone(), name: "game.exe".to_string() });
+        assert_eq!(tracks.session_removed(1), Some((key.clone(), true)));
+        assert!(tracks.is_empty_track(&key));
+
+        // back before the track was given up
+        assert_eq!(tracks.session_added(1, &table), SessionChange::JoinedTrack { key: key.clone() });
+        assert!(!tracks.is_empty_track(&key));
+        // forgetting only works on empty tracks
+        tracks.forget_track(&key);
+        assert_eq!(tracks.session_removed(1), Some((key.clone(), true)));
+
+        tracks.forget_track(&key);
+        assert_eq!(tracks.session_added(1, &table), SessionChange::NewTrack { key, name: "game.exe".to_string() });
+    }
+
+    #[test]
+    fn members_follow_the_sessions() {
+        let table = table(&[(3, None, "game.exe"), (1, None, "game.exe"), (2, None, "other.exe")]);
+        let mut tracks = ProcessTracks::new(rules(&[], &[], ProcessGrouping::Executable));
+        let key = TrackKey::Executable("game.exe".to_string());
+
+        for pid in [3, 1, 2] {
+            tracks.session_added(pid, &table);
+        }
+        assert_eq!(tracks.members(&key), vec![1, 3]);
+        tracks.session_removed(3);
+        assert_eq!(tracks.members(&key), vec![1]);
+        tracks.session_removed(1);
+        assert!(tracks.members(&key).is_empty());
+        assert!(tracks.members(&TrackKey::Process(9)).is_empty());
+    }
+
+    #[test]
+    fn ignored_processes_are_not_tracked() {
+        let table = table(&[(1, None, "Discord.exe")]);
+        let mut tracks = ProcessTracks::new(rules(&[], &["discord.exe"], ProcessGrouping::Executable));
+
+        assert_eq!(tracks.session_added(1, &table), SessionChange::Ignored);
+        assert_eq!(tracks.session_removed(1), None);
+    }
+}
@@ -1,11 +1,22 @@
-use std::sync::{Arc, Mutex};
 use ffmpeg_next::util::frame::audio::Audio;
 use ffmpeg_next::encoder::audio::Encoder;
+use crate::recorders::traits::RingBufferWriter;
 use crate::ring_buffer::traits::PacketRingBuffer;
 use crate::types::Result;
 
 pub trait AudioSource {
     fn init(&mut self) -> Result<()>;
     fn await_new_audio(&mut self);
-    fn gather_new_audio<PRB: PacketRingBuffer>(&mut self, ring_buffer: &Arc<Mutex<PRB>>, encoder: &mut Encoder, frame: &mut Audio, silent_frame: &mut Audio) -> Result<()>;
+    fn gather_new_audio<PRB: PacketRingBuffer>(&mut self, ring_buffer: &RingBufferWriter<PRB>, encoder: &mut Encoder, frame: &mut Audio, silent_frame: &mut Audio) -> Result<()>;
+    // while paused: drops what was captured, the next gathered audio continues at the current time without filling the gap
+    fn skip_new_audio(&mut self) -> Result<()>;
+    // stops capturing and hands whatever is still buffered to the encoder, the encoder itself is drained by the recorder
+    fn finish<PRB: PacketRingBuffer>(&mut self, ring_buffer: &RingBufferWriter<PRB>, encoder: &mut Encoder, frame: &mut Audio, silent_frame: &mut Audio) -> Result<()>;
+
+    // what the source hands to the encoder, after conversion
+    fn channels(&self) -> usize;
+    fn sample_rate(&self) -> u32;
+    // for encoders that can't take the source's channel layout or rate
+    fn downmix_to_stereo(&mut self);
+    fn resample_to(&mut self, sample_rate: u32);
 }
\ No newline at end of file
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use ffmpeg_next::encoder::audio::Encoder;
+use ffmpeg_next::frame::Audio;
+use crate::recorders::audio::sources::wasapi::traits::WasapiEncoderCtx;
+use crate::recorders::audio::tap::AudioTap;
+use crate::recorders::frame::copy_into_audio_frame;
+use crate::recorders::traits::{RingBufferWriter, send_frame_and_receive_packets};
+use crate::ring_buffer::traits::PacketRingBuffer;
+use crate::types::Result;
+
+// Frame size used for encoders that report a frame_size of 0 (variable frame size encoders)
+pub const VARIABLE_FRAME_SIZE: usize = 1024;
+
+// Collects samples into frames of the encoder's frame_size and fills gaps in the capture with silence.
+// Encoders with a variable frame size get whatever is buffered right away, up to VARIABLE_FRAME_SIZE per frame
+pub struct FrameAccumulator {
+    gain: f32,
+    taps: Vec<AudioTap>, // get every captured sample, before the gain
+}
+
+impl FrameAccumulator {
+    pub fn new(
+        gain: f32,
+        taps: Vec<AudioTap>,
+    ) -> Self {
+        Self {
+            gain,
+            taps,
+        }
+    }
+}
+
+impl WasapiEncoderCtx for FrameAccumulator {
+    fn process_audio<PRB: PacketRingBuffer>(
+        &mut self,
+        ring_buffer: &RingBufferWriter<PRB>,
+        mut encoder: &mut Encoder,
+        mut frame: &mut Audio,
+        silent_frame: &mut Audio,
+        samples: &[f32],
+        new_pts: i64,
+        channels: usize,
+        pts_counter: &mut i64,
+        mut audio_buffer: &mut VecDeque<f32>,
+    ) -> Result<()> {
+        let variable_frame_size = encoder.frame_size() == 0;
+        let frame_size = match variable_frame_size {
+            true => { VARIABLE_FRAME_SIZE }
+            false => { encoder.frame_size() as usize }
+        };
+
+        if !samples.is_empty() {
+            for tap in self.taps.iter() {
+                tap.send(samples, channels, encoder.rate(), new_pts);
+            }
+
+            let diff = (new_pts - *pts_counter - (audio_buffer.len() / channels) as i64).max(0);
+            if diff >= frame_size as i64 || (variable_frame_size && diff > 0) {
+                flush_and_silence(&mut audio_buffer, diff, pts_counter, frame, silent_frame, channels, variable_frame_size, ring_buffer, encoder)?;
+            }
+
+            assert_eq!(samples.len() % channels, 0);
+            audio_buffer.extend(samples.iter().map(|sample| sample * self.gain));
+        }
+
+        loop {
+            let sample_frames = match variable_frame_size {
+                true => { (audio_buffer.len() / channels).min(frame_size) }
+                false if audio_buffer.len() >= frame_size * channels => { frame_size }
+                false => { 0 }
+            };
+            if sample_frames == 0 {
+                break;
+            }
+
+            let buffer: Vec<f32> = audio_buffer.drain(..sample_frames * channels).collect();
+
+            frame.set_samples(sample_frames);
+            unsafe { copy_into_audio_frame(&mut frame, &buffer); }
+            frame.set_pts(Some(*pts_counter));
+            send_frame_and_receive_packets(&ring_buffer, &mut encoder, &frame, sample_frames as i64)?;
+
+            *pts_counter += sample_frames as i64;
+        }
+        Ok(())
+    }
+
+    fn flush<PRB: PacketRingBuffer>(
+        &mut self,
+        ring_buffer: &RingBufferWriter<PRB>,
+        encoder: &mut Encoder,
+        frame: &mut Audio,
+        silent_frame: &mut Audio,
+        channels: usize,
+        pts_counter: &mut i64,
+        audio_buffer: &mut VecDeque<f32>,
+    ) -> Result<()> {
+        if audio_buffer.is_empty() {
+            return Ok(());
+        }
+        let variable_frame_size = encoder.frame_size() == 0;
+        flush_and_silence(audio_buffer, 0, pts_counter, frame, silent_frame, channels, variable_frame_size, ring_buffer, encoder)
+    }
+}
+
+// Sends what's buffered (padded with silence up to a whole frame for fixed frame sizes), then silence until
+// `frames_of_silence` after the buffered samples is covered as far as whole frames allow
+fn flush_and_silence<PRB: PacketRingBuffer>(
+    vec_to_be_flushed: &mut VecDeque<f32>,
+    mut frames_of_silence: i64,
+    start_pts: &mut i64,
+    mut frame: &mut Audio,
+    silent_frame: &mut Audio,
+    channels: usize,
+    variable_frame_size: bool,
+    ring_buffer: &RingBufferWriter<PRB>,
+    mut encoder: &mut Encoder,
+) -> Result<()> {
+    let frame_size = match variable_frame_size {
+        true => { VARIABLE_FRAME_SIZE }
+        false => { encoder.frame_size() as usize }
+    };
+
+    // flush
+    let buffered = vec_to_be_flushed.len() / channels;
+    let flushed_size = match variable_frame_size {
+        true => { buffered }
+        false => { frame_size }
+    };
+    if flushed_size > 0 {
+        let mut buffer = vec![0.; flushed_size * channels];
+        assert!(buffer.len() >= vec_to_be_flushed.len());
+        frames_of_silence -= (flushed_size - buffered) as i64;
+
+        let (first, second) = vec_to_be_flushed.as_slices();
+        buffer[..first.len()].copy_from_slice(first);
+        buffer[first.len()..vec_to_be_flushed.len()].copy_from_slice(second);
+        // sent below, they must not go out a second time with the next frame
+        vec_to_be_flushed.clear();
+
+        frame.set_samples(flushed_size);
+        unsafe { copy_into_audio_frame(&mut frame, &buffer); }
+        frame.set_pts(Some(*start_pts));
+        send_frame_and_receive_packets(&ring_buffer, &mut encoder, &frame, flushed_size as i64)?;
+        *start_pts += flushed_size as i64;
+    }
+
+    // empty frames
+    while frames_of_silence > 0 {
+        let silent_size = match variable_frame_size {
+            true => { (frames_of_silence as usize).min(frame_size) }
+            false if frames_of_silence >= frame_size as i64 => { frame_size }
+            false => { break; }
+        };
+
+        silent_frame.set_samples(silent_size);
+        silent_frame.set_pts(Some(*start_pts));
+        send_frame_and_receive_packets(&ring_buffer, &mut encoder, &silent_frame, silent_size as i64)?;
+        *start_pts += silent_size as i64;
+        frames_of_silence -= silent_size as i64;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use windows::Win32::Media::Audio::{WAVE_FORMAT_PCM, WAVEFORMATEX, WAVEFORMATEXTENSIBLE};
+use windows::Win32::Media::KernelStreaming::{KSDATAFORMAT_SUBTYPE_PCM, WAVE_FORMAT_EXTENSIBLE};
+use windows::Win32::Media::Multimedia::{KSDATAFORMAT_SUBTYPE_IEEE_FLOAT, WAVE_FORMAT_IEEE_FLOAT};
+
+use crate::error::Error;
+use crate::recorders::audio::convert::{InputFormat, SampleEncoding};
+use crate::types::Result;
+
+// `format` has to point to a whole WAVEFORMATEXTENSIBLE if its tag says so, like the ones GetMixFormat hands out
+pub unsafe fn input_format(format: *const WAVEFORMATEX) -> Result<InputFormat> {
+    let base = *format;
+
+    let (is_float, channel_mask) = match base.wFormatTag as u32 {
+        WAVE_FORMAT_PCM => (false, None),
+        WAVE_FORMAT_IEEE_FLOAT => (true, None),
+        WAVE_FORMAT_EXTENSIBLE => {
+            let extensible = *(format as *const WAVEFORMATEXTENSIBLE);
+            let sub_format = extensible.SubFormat;
+            let is_float = if sub_format == KSDATAFORMAT_SUBTYPE_IEEE_FLOAT {
+                true
+            } else if sub_format == KSDATAFORMAT_SUBTYPE_PCM {
+                false
+            } else {
+                return Err(Error::NonExistentParameterCombination.into());
+            };
+            (is_float, Some(extensible.dwChannelMask))
+        }
+        _ => return Err(Error::NonExistentParameterCombination.into()),
+    };
+
+    let encoding = match (is_float, base.wBitsPerSample) {
+        (true, 32) => SampleEncoding::F32,
+        (false, 16) => SampleEncoding::I16,
+        (false, 24) => SampleEncoding::I24,
+        (false, 32) => SampleEncoding::I32,
+        _ => return Err(Error::NonExistentParameterCombination.into()),
+    };
+
+    Ok(InputFormat {
+        encoding,
+        channels: base.nChannels,
+        channel_mask: channel_mask.filter(|mask| *mask != 0).unwrap_or(InputFormat::default_channel_mask(base.nChannels)),
+        sample_rate: base.nSamplesPerSec,
+    })
+}
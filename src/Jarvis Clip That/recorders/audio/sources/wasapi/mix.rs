@@ -0,0 +1,332 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use ffmpeg_next::encoder::audio::Encoder;
+use ffmpeg_next::frame::Audio;
+use windows::Win32::Foundation::CloseHandle;
+use windows::Win32::Media::Audio::{AUDCLNT_BUFFERFLAGS_SILENT, IAudioCaptureClient, IAudioClient};
+use windows::Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency};
+use windows::Win32::System::Threading::{CreateEventW, WaitForMultipleObjects};
+
+use crate::debug_println;
+use crate::recorders::audio::convert::{AudioConverter, AudioFormatSettings, InputFormat};
+use crate::recorders::audio::sources::traits::AudioSource;
+use crate::recorders::audio::sources::wasapi::source::create_process_iaudioclient;
+use crate::recorders::audio::tap::AudioTap;
+use crate::recorders::frame::copy_into_audio_frame;
+use crate::recorders::traits::{RingBufferWriter, send_frame_and_receive_packets};
+use crate::ring_buffer::traits::PacketRingBuffer;
+use crate::types::Result;
+use crate::wrappers::{MaybeSafeComWrapper, MaybeSafeHANDLEWrapper};
+
+const MIX_POLL_MS: u32 = 20;
+// how long to wait for late members before a mixed frame is encoded
+const MIX_LATENCY_SECS: f64 = 0.1;
+
+pub enum MixCommand {
+    Add(u32),
+    Remove(u32),
+}
+
+struct MixMember {
+    p_id: u32,
+    converter: AudioConverter,
+    client: MaybeSafeComWrapper<IAudioClient>,
+    capture_client: MaybeSafeComWrapper<IAudioCaptureClient>,
+    event: MaybeSafeHANDLEWrapper,
+}
+
+impl MixMember {
+    fn new(
+        p_id: u32,
+        include_tree: bool,
+        format_settings: &AudioFormatSettings,
+    ) -> Result<Self> {
+        // the grouping decides which PIDs belong together, include_tree still applies to each of them like to an ungrouped track
+        let (client, format) = create_process_iaudioclient(p_id, include_tree)?;
+        let converter = AudioConverter::new(format, format_settings);
+
+        let event;
+        let capture_client: IAudioCaptureClient;
+        unsafe {
+            event = CreateEventW(None, false, false, None)?;
+            client.SetEventHandle(event)?;
+            capture_client = client.GetService()?;
+            client.Start()?;
+        }
+
+        Ok(Self {
+            p_id,
+            converter,
+            client: MaybeSafeComWrapper(client),
+            capture_client: MaybeSafeComWrapper(capture_client),
+            event: MaybeSafeHANDLEWrapper(event),
+        })
+    }
+}
+
+impl Drop for MixMember {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.client.Stop();
+            let _ = CloseHandle(*self.event);
+        }
+    }
+}
+
+// Records several processes into one track by summing their process loopback captures before encoding
+pub struct AudioSourceWasapiMix {
+    format: InputFormat,
+    format_settings: AudioFormatSettings,
+    include_tree: bool,
+
+    members: Vec<MixMember>,
+    commands: Receiver<MixCommand>,
+
+    frequency: i64,
+    start_time: i64,
+    mix_start_pts: i64,
+    mix_buffer: VecDeque<f32>,
+    gain: f32,
+    taps: Vec<AudioTap>, // get the mix before the gain
+}
+
+impl AudioSourceWasapiMix {
+    pub fn new(
+        format: InputFormat,
+        format_settings: &AudioFormatSettings,
+        process_ids: &[u32],
+        include_tree: bool,
+        gain: f32,
+        taps: Vec<AudioTap>,
+    ) -> (Self, Sender<MixCommand>) {
+        let (tx, commands) = channel();
+        for p_id in process_ids {
+            let _ = tx.send(MixCommand::Add(*p_id));
+        }
+
+        let source = Self {
+            format,
+            format_settings: format_settings.clone(),
+            include_tree,
+
+            members: Vec::new(),
+            commands,
+
+            frequency: 0,
+            start_time: 0,
+            mix_start_pts: 0,
+            mix_buffer: VecDeque::new(),
+            gain,
+            taps,
+        };
+        (source, tx)
+    }
+
+    fn apply_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                MixCommand::Add(p_id) => {
+                    if self.members.iter().any(|member| member.p_id == p_id) {
+                        continue;
+                    }
+                    match MixMember::new(p_id, self.include_tree, &self.format_settings) {
+                        // the default device may have changed in the meantime
+                        Ok(member) if member.converter.output_channels() != self.channels() || member.converter.output_rate() != self.sample_rate() => {
+                            eprintln!("Couldn't add PID {p_id} to mix: its format doesn't match the mix anymore");
+                        }
+                        Ok(member) => self.members.push(member),
+                        Err(err) => eprintln!("Couldn't add PID {p_id} to mix: {:?}", err),
+                    }
+                }
+                MixCommand::Remove(p_id) => {
+                    self.members.retain(|member| member.p_id != p_id);
+                    debug_println!("removed PID {p_id} from mix");
+                }
+            }
+        }
+    }
+
+    fn qpc_to_pts(&self, qpc: i64) -> i64 {
+        ((qpc - self.start_time).max(0) as u64 * self.sample_rate() as u64 / self.frequency as u64) as i64
+    }
+
+    // The next `size` mixed samples, as they're encoded
+    fn take_frame(&mut self, size: usize, channels: usize, sample_rate: u32) -> Vec<f32> {
+        let buffer: Vec<f32> = self.mix_buffer.drain(..size).collect();
+        for tap in self.taps.iter() {
+            tap.send(&buffer, channels, sample_rate, self.mix_start_pts);
+        }
+        let gain = self.gain;
+        buffer.into_iter().map(|sample| sample * gain).collect()
+    }
+
+    fn mix_in(&mut self, pts: i64, samples: &[f32]) {
+        let channels = self.channels();
+        let offset = pts - self.mix_start_pts;
+
+        // whatever lies before mix_start_pts was already encoded
+        let skip = ((-offset).max(0) as usize * channels).min(samples.len());
+        let start = offset.max(0) as usize * channels;
+        let samples = &samples[skip..];
+
+        if self.mix_buffer.len() < start + samples.len() {
+            self.mix_buffer.resize(start + samples.len(), 0.);
+        }
+        for (i, sample) in samples.iter().enumerate() {
+            self.mix_buffer[start + i] += sample;
+        }
+    }
+}
+
+impl AudioSource for AudioSourceWasapiMix {
+    fn init(&mut self) -> Result<()> {
+        unsafe {
+            QueryPerformanceFrequency(&mut self.frequency)?;
+            QueryPerformanceCounter(&mut self.start_time)?;
+        }
+        self.apply_commands();
+        Ok(())
+    }
+
+    fn await_new_audio(&mut self) {
+        let events: Vec<_> = self.members.iter().map(|member| *member.event).collect();
+        if events.is_empty() {
+            std::thread::sleep(Duration::from_millis(MIX_POLL_MS as u64));
+        } else {
+            unsafe { WaitForMultipleObjects(&events, false, MIX_POLL_MS); }
+        }
+    }
+
+    fn gather_new_audio<PRB: PacketRingBuffer>(
+        &mut self,
+        ring_buffer: &RingBufferWriter<PRB>,
+        encoder: &mut Encoder,
+        frame: &mut Audio,
+        _silent_frame: &mut Audio,
+    ) -> Result<()> {
+        self.apply_commands();
+
+        let channels = self.channels();
+
+        let mut captured = Vec::new();
+        for member in &mut self.members {
+            while unsafe { member.capture_client.GetNextPacketSize()? } > 0 {
+                let mut packet_length = 0;
+                let mut data = std::ptr::null_mut();
+                let mut flags = 0;
+                let mut qpc_pos = 0;
+                unsafe {
+                    member.capture_client.GetBuffer(
+                        &mut data,
+                        &mut packet_length,
+                        &mut flags,
+                        None,
+                        Some(&mut qpc_pos),
+                    )?;
+                }
+
+                if flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 == 0 {
+                    let bytes = unsafe { std::slice::from_raw_parts(data as *const u8, packet_length as usize * member.converter.input().block_align()) };
+                    captured.push((qpc_pos as i64, member.converter.convert(bytes)));
+                }
+
+                unsafe { member.capture_client.ReleaseBuffer(packet_length)? }
+            }
+        }
+        for (qpc_pos, samples) in captured {
+            self.mix_in(self.qpc_to_pts(qpc_pos), &samples);
+        }
+
+        let mut now = 0;
+        unsafe { QueryPerformanceCounter(&mut now)?; }
+        let ready_pts = self.qpc_to_pts(now) - (MIX_LATENCY_SECS * self.sample_rate() as f64) as i64;
+
+        let frame_size = frame.samples();
+        let size = frame_size * channels;
+        while self.mix_start_pts + frame_size as i64 <= ready_pts {
+            // no member delivered anything for this stretch, so it's silence
+            if self.mix_buffer.len() < size {
+                self.mix_buffer.resize(size, 0.);
+            }
+            let buffer = self.take_frame(size, channels, encoder.rate());
+
+            unsafe { copy_into_audio_frame(frame, &buffer); }
+            frame.set_pts(Some(self.mix_start_pts));
+            send_frame_and_receive_packets(ring_buffer, encoder, frame, frame_size as i64)?;
+
+            self.mix_start_pts += frame_size as i64;
+        }
+
+        Ok(())
+    }
+
+    fn skip_new_audio(&mut self) -> Result<()> {
+        self.apply_commands();
+
+        for member in &mut self.members {
+            while unsafe { member.capture_client.GetNextPacketSize()? } > 0 {
+                let mut data = std::ptr::null_mut();
+                let mut packet_length = 0;
+                let mut flags = 0;
+                unsafe {
+                    member.capture_client.GetBuffer(&mut data, &mut packet_length, &mut flags, None, None)?;
+                    member.capture_client.ReleaseBuffer(packet_length)?;
+                }
+            }
+        }
+
+        let mut now = 0;
+        unsafe { QueryPerformanceCounter(&mut now)?; }
+        self.mix_buffer.clear();
+        self.mix_start_pts = self.qpc_to_pts(now);
+        Ok(())
+    }
+
+    fn finish<PRB: PacketRingBuffer>(
+        &mut self,
+        ring_buffer: &RingBufferWriter<PRB>,
+        encoder: &mut Encoder,
+        frame: &mut Audio,
+        silent_frame: &mut Audio,
+    ) -> Result<()> {
+        self.gather_new_audio(ring_buffer, encoder, frame, silent_frame)?;
+        self.members.clear(); // stops their clients
+
+        // no late members to wait for anymore, the rest of the mix goes out padded to a whole frame
+        if self.mix_buffer.is_empty() {
+            return Ok(());
+        }
+        let frame_size = frame.samples();
+        let channels = self.channels();
+        let size = frame_size * channels;
+        self.mix_buffer.resize(self.mix_buffer.len().div_ceil(size) * size, 0.);
+        while !self.mix_buffer.is_empty() {
+            let buffer = self.take_frame(size, channels, encoder.rate());
+
+            unsafe { copy_into_audio_frame(frame, &buffer); }
+            frame.set_pts(Some(self.mix_start_pts));
+            send_frame_and_receive_packets(ring_buffer, encoder, frame, frame_size as i64)?;
+
+            self.mix_start_pts += frame_size as i64;
+        }
+        Ok(())
+    }
+
+    fn channels(&self) -> usize {
+        AudioConverter::new(self.format, &self.format_settings).output_channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        AudioConverter::new(self.format, &self.format_settings).output_rate()
+    }
+
+    fn downmix_to_stereo(&mut self) {
+        self.format_settings.downmix_to_stereo = true;
+    }
+
+    fn resample_to(&mut self, sample_rate: u32) {
+        self.format_settings.sample_rate = Some(sample_rate);
+    }
+}
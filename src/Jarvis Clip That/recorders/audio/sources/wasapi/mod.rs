@@ -1,3 +1,5 @@
 pub mod source;
-pub mod aac;
-pub mod traits;
\ No newline at end of file
+pub mod accumulator;
+pub mod traits;
+pub mod mix;
+pub mod format;
\ No newline at end of file
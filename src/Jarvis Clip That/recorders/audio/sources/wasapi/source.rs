@@ -2,10 +2,10 @@ use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
 use ffmpeg_next::encoder::audio::Encoder;
 use ffmpeg_next::frame::Audio;
-use windows::Win32::Media::Audio::{AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_EVENTCALLBACK, AUDCLNT_STREAMFLAGS_LOOPBACK, PROCESS_LOOPBACK_MODE_EXCLUDE_TARGET_PROCESS_TREE, PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE, AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS, AUDIOCLIENT_ACTIVATION_PARAMS_0, AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK, AUDIOCLIENT_ACTIVATION_PARAMS, eConsole, eRender, IAudioCaptureClient, IAudioClient, IMMDeviceEnumerator, MMDeviceEnumerator, WAVEFORMATEX, IAudioSessionManager2, eMultimedia, IAudioSessionControl2, WAVEFORMATEXTENSIBLE, WAVEFORMATEXTENSIBLE_0, AudioSessionState, AudioSessionDisconnectReason, AudioSessionStateExpired, eCapture};
-use windows::Win32::System::Com::{BLOB, CLSCTX_ALL, CoCreateInstance};
+use windows::Win32::Media::Audio::{AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_EVENTCALLBACK, AUDCLNT_STREAMFLAGS_LOOPBACK, PROCESS_LOOPBACK_MODE_EXCLUDE_TARGET_PROCESS_TREE, PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE, AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS, AUDIOCLIENT_ACTIVATION_PARAMS_0, AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK, AUDIOCLIENT_ACTIVATION_PARAMS, eConsole, eRender, AUDCLNT_BUFFERFLAGS_SILENT, IAudioCaptureClient, IAudioClient, IMMDeviceEnumerator, MMDeviceEnumerator, WAVEFORMATEX, IAudioSessionManager2, eMultimedia, IAudioSessionControl2, WAVEFORMATEXTENSIBLE, WAVEFORMATEXTENSIBLE_0, AudioSessionState, AudioSessionDisconnectReason, AudioSessionStateExpired, eCapture};
+use windows::Win32::System::Com::{BLOB, CLSCTX_ALL, CoCreateInstance, CoTaskMemFree};
 use windows::Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency};
-use windows::Win32::System::Threading::{CreateEventW, INFINITE, WaitForSingleObject};
+use windows::Win32::System::Threading::{CreateEventW, WaitForSingleObject};
 use windows::Win32::System::Com::StructuredStorage::{PROPVARIANT, PROPVARIANT_0, PROPVARIANT_0_0, PROPVARIANT_0_0_0};
 
 use std::{
@@ -14,7 +14,7 @@ use std::{
     sync::Condvar,
 };
 use std::ops::Deref;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::time::{Duration, Instant};
 use windows::Win32::Media::Audio as WinAudio;
 use windows::Win32::System::Variant::VT_BLOB;
@@ -22,18 +22,28 @@ use windows::core::{Interface, HRESULT, IUnknown};
 use windows_core::{BOOL, GUID, PCWSTR};
 use crate::debug_println;
 use crate::error::{CustomError, Error};
+use crate::events::{Event, Events};
+use crate::recorders::audio::convert::{AudioConverter, AudioFormatSettings, InputFormat};
 use crate::recorders::audio::sources::enums::{AudioCodec, AudioSourceType};
-
+use crate::recorders::audio::sources::process_rules::{ProcessInfo, ProcessRules, ProcessTracks, SessionChange, TrackKey};
 use crate::recorders::audio::sources::traits::AudioSource;
+use crate::recorders::audio::sources::wasapi::format::input_format;
+use crate::recorders::audio::sources::wasapi::mix::MixCommand;
 use crate::recorders::audio::sources::wasapi::traits::WasapiEncoderCtx;
-use crate::recorders::recorder::{create_audio_recorder, Recorder};
+use crate::recorders::audio::tap::{AudioTap, TrackTaps};
+use crate::recorders::recorder::{create_audio_recorder, create_process_group_recorder, Recorder};
+use crate::recorders::supervisor::{Backoff, Heartbeat};
+use crate::recorders::track_settings::{gain_of, TrackSettings};
+use crate::recorders::traits::RingBufferWriter;
 use crate::ring_buffer::traits::PacketRingBuffer;
 use crate::wrappers::{MaybeSafeComWrapper, MaybeSafeHANDLEWrapper};
-use crate::types::Result;
+use crate::types::{RecorderJoinHandle, Result};
+
+const AUDIO_WAIT_MS: u32 = 100;
 
 pub struct AudioSourceWasapi<E: WasapiEncoderCtx> {
     client: MaybeSafeComWrapper<IAudioClient>,
-    pub format: WAVEFORMATEX,
+    converter: AudioConverter,
 
     capture_client: MaybeSafeComWrapper<IAudioCaptureClient>,
 
@@ -42,7 +52,7 @@ pub struct AudioSourceWasapi<E: WasapiEncoderCtx> {
     frequency: i64,
     start_time: i64,
     pts_counter: i64,
-    audio_buffer: VecDeque<u8>,
+    audio_buffer: VecDeque<f32>,
 
     context_encoder: E,
 }
@@ -51,9 +61,11 @@ impl<E: WasapiEncoderCtx> AudioSourceWasapi<E> {
     fn new(
         context_encoder: E,
         client: IAudioClient,
-        format: WAVEFORMATEX,
+        format: InputFormat,
+        format_settings: &AudioFormatSettings,
     ) -> Result<Self> {
         let client = MaybeSafeComWrapper(client);
+        let converter = AudioConverter::new(format, format_settings);
 
         let event;
         unsafe {
@@ -67,7 +79,7 @@ impl<E: WasapiEncoderCtx> AudioSourceWasapi<E> {
 
         Ok(Self {
             client,
-            format,
+            converter,
 
             capture_client,
 
@@ -85,19 +97,22 @@ impl<E: WasapiEncoderCtx> AudioSourceWasapi<E> {
     pub fn new_default(
         context_encoder: E,
         render_else_capture: bool,
+        format_settings: &AudioFormatSettings,
     ) -> Result<Self> {
         let (client, format) = create_default_iaudioclient(render_else_capture)?;
-        Self::new(context_encoder, client, format)
+        Self::new(context_encoder, client, format, format_settings)
     }
 
     pub fn new_process(
         context_encoder: E,
         process_id: u32,
         include_tree: bool,
+        format_settings: &AudioFormatSettings,
     ) -> Result<Self> {
         let (client, format) = create_process_iaudioclient(process_id, include_tree)?;
-        Self::new(context_encoder, client, format)
+        Self::new(context_encoder, client, format, format_settings)
     }
+
 }
 
 impl<E: WasapiEncoderCtx> AudioSource for AudioSourceWasapi<E> {
@@ -111,12 +126,13 @@ impl<E: WasapiEncoderCtx> AudioSource for AudioSourceWasapi<E> {
     }
 
     fn await_new_audio(&mut self) {
-        unsafe { WaitForSingleObject(*self.event, INFINITE); }
+        // not INFINITE, the recorder has to notice when it's asked to stop even if nothing is playing
+        unsafe { WaitForSingleObject(*self.event, AUDIO_WAIT_MS); }
     }
 
     fn gather_new_audio<PRB: PacketRingBuffer>(
         &mut self,
-        ring_buffer: &Arc<Mutex<PRB>>,
+        ring_buffer: &RingBufferWriter<PRB>,
         encoder: &mut Encoder,
         frame: &mut Audio,
         silent_frame: &mut Audio,
@@ -137,12 +153,76 @@ impl<E: WasapiEncoderCtx> AudioSource for AudioSourceWasapi<E> {
             )?;
         }
 
-        self.context_encoder.process_audio(ring_buffer, encoder, frame, silent_frame, packet_length, data, qpc_pos, self.start_time, self.frequency, &self.format, &mut self.pts_counter, &mut self.audio_buffer, &self.capture_client)
+        if packet_length == 0 {
+            return Ok(());
+        }
+
+        let bytes = unsafe { std::slice::from_raw_parts(data as *const u8, packet_length as usize * self.converter.input().block_align()) };
+        let mut samples = self.converter.convert(bytes);
+        if flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 != 0 {
+            samples.fill(0.);
+        }
+        unsafe { self.capture_client.ReleaseBuffer(packet_length)? }
+
+        let new_pts = ((qpc_pos as i64 - self.start_time).max(0) as u64 * self.converter.output_rate() as u64 / self.frequency as u64) as i64;
+        let channels = self.converter.output_channels();
+        self.context_encoder.process_audio(ring_buffer, encoder, frame, silent_frame, &samples, new_pts, channels, &mut self.pts_counter, &mut self.audio_buffer)
+    }
+
+    fn skip_new_audio(&mut self) -> Result<()> {
+        while unsafe { self.capture_client.GetNextPacketSize()? } > 0 {
+            let mut data = std::ptr::null_mut();
+            let mut packet_length = 0;
+            let mut flags = 0;
+            unsafe {
+                self.capture_client.GetBuffer(&mut data, &mut packet_length, &mut flags, None, None)?;
+                self.capture_client.ReleaseBuffer(packet_length)?;
+            }
+        }
+
+        let mut now = 0;
+        unsafe { QueryPerformanceCounter(&mut now)?; }
+        self.audio_buffer.clear();
+        self.pts_counter = ((now - self.start_time).max(0) as u64 * self.converter.output_rate() as u64 / self.frequency as u64) as i64;
+        Ok(())
+    }
+
+    fn finish<PRB: PacketRingBuffer>(
+        &mut self,
+        ring_buffer: &RingBufferWriter<PRB>,
+        encoder: &mut Encoder,
+        frame: &mut Audio,
+        silent_frame: &mut Audio,
+    ) -> Result<()> {
+        // whatever is still in the capture buffer goes in first
+        while unsafe { self.capture_client.GetNextPacketSize()? } > 0 {
+            self.gather_new_audio(ring_buffer, encoder, frame, silent_frame)?;
+        }
+        unsafe { self.client.Stop()? }
+
+        let channels = self.converter.output_channels();
+        self.context_encoder.flush(ring_buffer, encoder, frame, silent_frame, channels, &mut self.pts_counter, &mut self.audio_buffer)
+    }
+
+    fn channels(&self) -> usize {
+        self.converter.output_channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.converter.output_rate()
+    }
+
+    fn downmix_to_stereo(&mut self) {
+        self.converter.downmix_to_stereo();
+    }
+
+    fn resample_to(&mut self, sample_rate: u32) {
+        self.converter.resample_to(sample_rate);
     }
 }
 
 
-pub fn create_default_iaudioclient(render_else_capture: bool) -> Result<(IAudioClient, WAVEFORMATEX)> {
+pub fn create_default_iaudioclient(render_else_capture: bool) -> Result<(IAudioClient, InputFormat)> {
     let try_init = unsafe {
         windows::Win32::System::Com::CoInitializeEx(None, windows::Win32::System::Com::COINIT_MULTITHREADED)
     };
@@ -192,30 +272,18 @@ pub fn create_default_iaudioclient(render_else_capture: bool) -> Result<(IAudioC
         )?;
     }
 
-    let format = unsafe { *format };
-
-    let f1 = format.wFormatTag;
-    debug_println!("{}", f1);
-    let f1 = format.nChannels;
-    debug_println!("{}", f1);
-    let f1 = format.nSamplesPerSec;
-    debug_println!("{}", f1);
-    let f1 = format.nAvgBytesPerSec;
-    debug_println!("{}", f1);
-    let f1 = format.nBlockAlign;
-    debug_println!("{}", f1);
-    let f1 = format.wBitsPerSample;
-    debug_println!("{}", f1);
-    let f1 = format.cbSize;
-    debug_println!("{}", f1);
+    let input_format = unsafe { input_format(format) };
+    unsafe { CoTaskMemFree(Some(format as *const _)); }
+    let format = input_format?;
+    debug_println!("{:?}", format);
 
     Ok((client, format))
 }
 
-fn create_process_iaudioclient(
+pub fn create_process_iaudioclient(
     process_id: u32,
     include_tree: bool,
-) -> Result<(IAudioClient, WAVEFORMATEX)> {
+) -> Result<(IAudioClient, InputFormat)> {
     let _try_init = unsafe {
         windows::Win32::System::Com::CoInitializeEx(None, windows::Win32::System::Com::COINIT_MULTITHREADED)
     };
@@ -293,7 +361,7 @@ fn create_process_iaudioclient(
     // Downcast to IAudioClient
     let client: IAudioClient = unknown.ok_or(CustomError::CUSTOM(Error::Unknown))?.cast()?;
 
-    let format = new_waveformatextensible(32, 32, 44100, 2, None);
+    let format = process_loopback_format();
 
     unsafe {
         client.Initialize(
@@ -307,27 +375,301 @@ fn create_process_iaudioclient(
         )?;
     }
 
-    Ok((client, format.Format))
+    Ok((client, unsafe { input_format(&format.Format)? }))
+}
+
+// Friendly name of the default endpoint ("Microphone (USB Audio)"), used to match track settings
+pub fn default_device_name(render_else_capture: bool) -> Result<String> {
+    let _ = unsafe { windows::Win32::System::Com::CoInitializeEx(None, windows::Win32::System::Com::COINIT_MULTITHREADED) };
+    let enumerator: IMMDeviceEnumerator = unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+
+    let dataflow = match render_else_capture {
+        true => { eRender }
+        false => { eCapture }
+    };
+    let device = unsafe { enumerator.GetDefaultAudioEndpoint(dataflow, eConsole)? };
+    device_friendly_name(&device)
+}
+
+fn device_friendly_name(device: &WinAudio::IMMDevice) -> Result<String> {
+    unsafe {
+        let property_store = device.OpenPropertyStore(windows::Win32::System::Com::STGM_READ)?;
+        let value = property_store.GetValue(&windows::Win32::Devices::FunctionDiscovery::PKEY_Device_FriendlyName)?;
+        let name = windows::Win32::System::Com::StructuredStorage::PropVariantToStringAlloc(&value)?;
+        let string = name.to_string();
+        windows::Win32::System::Com::CoTaskMemFree(Some(name.0 as *const _));
+        Ok(string.map_err(|_| Error::Unknown)?)
+    }
+}
+
+// What --list-sources shows
+pub struct AudioDeviceInfo {
+    pub name: String,
+    pub render: bool, // an output, else an input
+    pub default: bool,
+}
+
+pub struct AudioSessionInfo {
+    pub process_id: u32,
+    pub process_name: String,
+    pub active: bool, // currently playing something
+}
+
+// The active endpoints, outputs first
+pub fn list_audio_devices() -> Result<Vec<AudioDeviceInfo>> {
+    let _ = unsafe { windows::Win32::System::Com::CoInitializeEx(None, windows::Win32::System::Com::COINIT_MULTITHREADED) };
+    let enumerator: IMMDeviceEnumerator = unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+
+    let mut devices = Vec::new();
+    for render in [true, false] {
+        let dataflow = match render {
+            true => { eRender }
+            false => { eCapture }
+        };
+        let default_name = default_device_name(render).ok();
+        let collection = unsafe { enumerator.EnumAudioEndpoints(dataflow, WinAudio::DEVICE_STATE_ACTIVE)? };
+        for i in 0..unsafe { collection.GetCount()? } {
+            let device = unsafe { collection.Item(i)? };
+            let name = device_friendly_name(&device)?;
+            devices.push(AudioDeviceInfo { default: default_name.as_deref() == Some(name.as_str()), name, render });
+        }
+    }
+    Ok(devices)
+}
+
+// The sessions of the default output, the ones AudioProcessWatcher records
+pub fn list_audio_sessions() -> Result<Vec<AudioSessionInfo>> {
+    let _ = unsafe { windows::Win32::System::Com::CoInitializeEx(None, windows::Win32::System::Com::COINIT_MULTITHREADED) };
+    let enumerator: IMMDeviceEnumerator = unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+    let device = unsafe { enumerator.GetDefaultAudioEndpoint(eRender, eMultimedia)? };
+    let session_manager: IAudioSessionManager2 = unsafe { device.Activate(CLSCTX_ALL, None)? };
+
+    let processes = snapshot_processes().unwrap_or_default();
+    let session_enum = unsafe { session_manager.GetSessionEnumerator()? };
+    let mut sessions = Vec::new();
+    for i in 0..unsafe { session_enum.GetCount()? } {
+        let session_control: IAudioSessionControl2 = unsafe { session_enum.GetSession(i)?.cast()? };
+        let process_id = unsafe { session_control.GetProcessId()? };
+        // the system sounds session
+        if process_id == 0 {
+            continue;
+        }
+        let active = unsafe { session_control.GetState()? } == WinAudio::AudioSessionStateActive;
+        let process_name = processes.get(&process_id).map(|process| process.name.clone()).unwrap_or_default();
+        sessions.push(AudioSessionInfo { process_id, process_name, active });
+    }
+    Ok(sessions)
+}
+
+// Process loopback takes whatever format it's given, so capture in the default render device's layout and rate.
+// Always float, the conversion to the encoder happens later anyway
+pub fn process_loopback_format() -> WAVEFORMATEXTENSIBLE {
+    match default_render_format() {
+        Ok(format) => new_waveformatextensible(32, 32, format.sample_rate as usize, format.channels as usize, Some(format.channel_mask)),
+        Err(err) => {
+            eprintln!("Couldn't get the default render format, falling back to 44.1 kHz stereo: {:?}", err);
+            new_waveformatextensible(32, 32, 44100, 2, None)
+        }
+    }
+}
+
+fn default_render_format() -> Result<InputFormat> {
+    let _ = unsafe { windows::Win32::System::Com::CoInitializeEx(None, windows::Win32::System::Com::COINIT_MULTITHREADED) };
+    let enumerator: IMMDeviceEnumerator = unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+    unsafe {
+        let device = enumerator.GetDefaultAudioEndpoint(eRender, eConsole)?;
+        let client: IAudioClient = device.Activate(CLSCTX_ALL, None)?;
+        let format = client.GetMixFormat()?;
+        let input_format = input_format(format);
+        CoTaskMemFree(Some(format as *const _));
+        input_format
+    }
+}
+
+
+const ORPHAN_REAP_INTERVAL: Duration = Duration::from_secs(1);
+// how long a track whose processes all left waits for one of them to come back, e.g. a game restarting its audio
+const REJOIN_GRACE: Duration = Duration::from_secs(5);
+
+// Builds a track's recorder for the given processes, into the given ring buffer or a new one, with the given start delay
+type ProcessTrackFactory<PRB> = Box<dyn FnMut(&[u32], Option<Arc<Mutex<PRB>>>, f64) -> Result<(Recorder<PRB>, Option<std::sync::mpsc::Sender<MixCommand>>)> + Send>;
+
+pub struct ProcessTrack<PRB: PacketRingBuffer> {
+    pub recorder: Recorder<PRB>,
+    pub name: String,
+    pub settings: Option<TrackSettings>,
+    factory: ProcessTrackFactory<PRB>,
+    running: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    mix_commands: Option<std::sync::mpsc::Sender<MixCommand>>,
+    finished_at: Option<Instant>,
+    handle: Option<RecorderJoinHandle>,
+
+    // supervised like a Supervisor group, see AudioProcessWatcher::check
+    heartbeat: Arc<Heartbeat>,
+    started_at: Instant,
+    backoff: Backoff,
 }
 
+impl<PRB: PacketRingBuffer + 'static> ProcessTrack<PRB> {
+    fn new(
+        recorder: Recorder<PRB>,
+        name: String,
+        settings: Option<TrackSettings>,
+        factory: ProcessTrackFactory<PRB>,
+        mix_commands: Option<std::sync::mpsc::Sender<MixCommand>>,
+        paused: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            recorder,
+            name,
+            settings,
+            factory,
+            running: Arc::new(AtomicBool::new(true)),
+            paused,
+            mix_commands,
+            finished_at: None,
+            handle: None,
+
+            heartbeat: Arc::new(Heartbeat::new()),
+            started_at: Instant::now(),
+            backoff: Backoff::new(),
+        }
+    }
+
+    fn start(&mut self) {
+        let running = Arc::new(AtomicBool::new(true));
+        let heartbeat = Arc::new(Heartbeat::new());
+        heartbeat.beat();
+        // already running, its flags have to stay the ones its thread watches
+        let Some(handle) = self.recorder.start_recording(Some(running.clone()), Some(heartbeat.clone()), Some(self.paused.clone())) else {
+            return;
+        };
+        self.running = running;
+        self.heartbeat = heartbeat;
+        self.started_at = Instant::now();
+        self.handle = Some(handle);
+    }
+
+    fn schedule_restart(&mut self) {
+        let backoff = self.backoff.schedule(self.started_at);
+        eprintln!("Restarting {} in {:?}", self.name, backoff);
+    }
+
+    // A new recorder for `process_ids` writing into the old ring buffer, so the track's history survives
+    fn restart(&mut self, process_ids: &[u32]) -> Result<()> {
+        let elapsed_secs = self.started_at.elapsed().as_secs_f64();
+        let ring_buffer = self.recorder.ring_buffer.clone();
+        let (recorder, mix_commands) = (self.factory)(process_ids, Some(ring_buffer), self.recorder.start_delay_secs + elapsed_secs)?;
+
+        // the new pts 0 lies `elapsed_secs` after the old one
+        let sample_rate = unsafe { *self.recorder.parameters.as_ptr() }.sample_rate;
+        self.recorder.ring_buffer.lock().unwrap().offset_pts(-(elapsed_secs * sample_rate as f64) as i64);
+
+        self.recorder = recorder;
+        self.mix_commands = mix_commands;
+        self.start();
+        Ok(())
+    }
+
+    // Returns what went wrong, if anything. `process_ids` are the track's processes right now
+    fn check(&mut self, process_ids: &[u32]) -> Option<String> {
+        if self.backoff.is_scheduled() {
+            // without any process left the track is orphaned soon, unless one rejoins and it's restarted then
+            if process_ids.is_empty() || !self.backoff.take_due() {
+                return None;
+            }
+            return match self.restart(process_ids) {
+                Ok(()) => {
+                    debug_println!("Restarted {}", self.name);
+                    None
+                }
+                Err(err) => {
+                    let problem = format!("Couldn't restart {}: {:?}", self.name, err);
+                    eprintln!("{}", problem);
+                    self.schedule_restart();
+                    Some(problem)
+                }
+            };
+        }
+
+        let Some(handle) = &self.handle else {
+            return None;
+        };
+        let problem = if handle.is_finished() {
+            match self.handle.take().map(|handle| handle.join()) {
+                Some(Ok(Ok(()))) => { format!("{} stopped on its own", self.name) }
+                Some(Ok(Err(err))) => { format!("{} failed: {:?}", self.name, err) }
+                Some(Err(_)) | None => { format!("{} panicked", self.name) }
+            }
+        } else if self.heartbeat.stalled() {
+            // left behind like a stuck Supervisor group, whatever it inserts if it wakes up again is dropped
+            self.running.store(false, Ordering::Relaxed);
+            self.recorder.ring_buffer.lock().unwrap().retire_writer();
+            self.handle = None;
+            format!("{} stalled for {:?}", self.name, self.heartbeat.since_last_beat())
+        } else {
+            return None;
+        };
+        eprintln!("{}", problem);
+        self.schedule_restart();
+        Some(problem)
+    }
+
+    // An orphan's newest packet is from finished_at, once that is older than the buffer holds, the track has nothing left to contribute
+    fn aged_out(&self) -> bool {
+        let Some(finished_at) = self.finished_at else {
+            return false;
+        };
+        let sample_rate = unsafe { *self.recorder.parameters.as_ptr() }.sample_rate.max(1);
+        let retention_secs = self.recorder.ring_buffer.lock().unwrap().min_frame_amount() as f64 / sample_rate as f64;
+        finished_at.elapsed().as_secs_f64() > retention_secs
+    }
+}
+
+type ProcessTrackMap<PRB> = Arc<tokio::sync::Mutex<HashMap<TrackKey, ProcessTrack<PRB>>>>;
+type OrphanTracks<PRB> = Arc<tokio::sync::Mutex<Vec<ProcessTrack<PRB>>>>;
 
 pub struct AudioProcessWatcher<PRB: PacketRingBuffer> {
-    pub audio_recorders: Arc<tokio::sync::Mutex<HashMap<u32, (Recorder<PRB>, String, Arc<AtomicBool>)>>>,
+    pub audio_recorders: ProcessTrackMap<PRB>,
+    // stopped tracks of exited processes, kept read-only until their audio ages out of the buffer window
+    pub orphan_recorders: OrphanTracks<PRB>,
+    listening: Arc<AtomicBool>,
+    min_secs: Arc<AtomicU32>, // of tracks added from now on, see set_retention
+    process_tracks: Arc<Mutex<ProcessTracks>>,
     _audio_process_watcher: Option<_AudioProcessWatcher<PRB>>,
 }
 
 impl<PRB: PacketRingBuffer + 'static> AudioProcessWatcher<PRB> {
     pub fn new(
         audio_codec: AudioCodec,
+        format_settings: AudioFormatSettings,
         include_tree: bool,
         min_secs: u32,
         start_delay_secs: f64,
+        process_rules: ProcessRules,
+        track_settings: Vec<TrackSettings>,
+        track_taps: TrackTaps,
+        paused: Arc<AtomicBool>,
+        events: Events,
     ) -> Result<Self> {
         let audio_recorders = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+        let orphan_recorders = Arc::new(tokio::sync::Mutex::new(Vec::new()));
+        let listening = Arc::new(AtomicBool::new(true));
+        let min_secs = Arc::new(AtomicU32::new(min_secs));
+        let process_tracks = Arc::new(Mutex::new(ProcessTracks::new(process_rules)));
         let a = audio_recorders.clone();
+        let o = orphan_recorders.clone();
+        let l = listening.clone();
+        let m = min_secs.clone();
+        let p = process_tracks.clone();
         Ok(Self {
             audio_recorders,
-            _audio_process_watcher: Some(_AudioProcessWatcher::new(audio_codec, include_tree, min_secs, a, start_delay_secs, Instant::now())?),
+            orphan_recorders,
+            listening,
+            min_secs,
+            process_tracks,
+            _audio_process_watcher: Some(_AudioProcessWatcher::new(audio_codec, format_settings, include_tree, m, a, o, l, start_delay_secs, Instant::now(), p, track_settings, track_taps, paused, events)?),
         })
     }
 
@@ -337,15 +679,64 @@ impl<PRB: PacketRingBuffer + 'static> AudioProcessWatcher<PRB> {
         }
         Ok(false)
     }
+
+    // Stops taking new processes and stops every track, the tracks stay readable for a last save once their handles are joined
+    pub async fn stop(&self) -> Vec<RecorderJoinHandle> {
+        self.listening.store(false, Ordering::Relaxed);
+
+        let mut audio_recorders = self.audio_recorders.lock().await;
+        let mut orphan_recorders = self.orphan_recorders.lock().await;
+        audio_recorders.values_mut().chain(orphan_recorders.iter_mut()).filter_map(|track| {
+            track.running.store(false, Ordering::Relaxed);
+            track.handle.take()
+        }).collect()
+    }
+
+    // Empties every track, tracks of exited processes are dropped altogether
+    pub async fn clear_buffers(&self) {
+        for track in self.audio_recorders.lock().await.values() {
+            track.recorder.ring_buffer.lock().unwrap().clear();
+        }
+        self.orphan_recorders.lock().await.clear();
+    }
+
+    // Restarts the tracks whose thread died or got stuck, on their ring buffers. Returns a message per problem, like Supervisor::check
+    pub async fn check(&self) -> Vec<String> {
+        let mut audio_recorders = self.audio_recorders.lock().await;
+        // stopped for good
+        if !self.listening.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        audio_recorders.iter_mut().filter_map(|(key, track)| {
+            let process_ids = self.process_tracks.lock().unwrap().members(key);
+            track.check(&process_ids)
+        }).collect()
+    }
+
+    // Keeps `secs` of every track, the running ones included. Shrinking drops old packets right away
+    pub async fn set_retention(&self, secs: u32) {
+        self.min_secs.store(secs, Ordering::Relaxed);
+        for track in self.audio_recorders.lock().await.values().chain(self.orphan_recorders.lock().await.iter()) {
+            track.recorder.set_retention_secs(secs);
+        }
+    }
 }
 
 struct _AudioProcessWatcher<PRB: PacketRingBuffer> {
     session_manager: IAudioSessionManager2,
 
     audio_codec: AudioCodec,
+    format_settings: AudioFormatSettings,
     include_tree: bool,
-    min_secs: u32,
-    audio_recorders: Arc<tokio::sync::Mutex<HashMap<u32, (Recorder<PRB>, String, Arc<AtomicBool>)>>>,
+    min_secs: Arc<AtomicU32>,
+    audio_recorders: ProcessTrackMap<PRB>,
+    orphan_recorders: OrphanTracks<PRB>,
+    listening: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    process_tracks: Arc<Mutex<ProcessTracks>>,
+    track_settings: Vec<TrackSettings>,
+    track_taps: TrackTaps,
+    events: Events, // tracks coming and going
 
     start_delay_secs: f64,
     start_instant: Instant,
@@ -356,11 +747,19 @@ unsafe impl<PRB: PacketRingBuffer> Send for _AudioProcessWatcher<PRB> {}
 impl<PRB: PacketRingBuffer + 'static> _AudioProcessWatcher<PRB> {
     fn new(
         audio_codec: AudioCodec,
+        format_settings: AudioFormatSettings,
         include_tree: bool,
-        min_secs: u32,
-        audio_recorders: Arc<tokio::sync::Mutex<HashMap<u32, (Recorder<PRB>, String, Arc<AtomicBool>)>>>,
+        min_secs: Arc<AtomicU32>,
+        audio_recorders: ProcessTrackMap<PRB>,
+        orphan_recorders: OrphanTracks<PRB>,
+        listening: Arc<AtomicBool>,
         start_delay_secs: f64,
         start_instant: Instant,
+        process_tracks: Arc<Mutex<ProcessTracks>>,
+        track_settings: Vec<TrackSettings>,
+        track_taps: TrackTaps,
+        paused: Arc<AtomicBool>,
+        events: Events,
     ) -> Result<Self> {
         let _ = unsafe { windows::Win32::System::Com::CoInitializeEx(None, windows::Win32::System::Com::COINIT_MULTITHREADED) };
         let device_enumerator: IMMDeviceEnumerator = unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
@@ -372,9 +771,17 @@ impl<PRB: PacketRingBuffer + 'static> _AudioProcessWatcher<PRB> {
             session_manager,
 
             audio_codec,
+            format_settings,
             include_tree,
             min_secs,
             audio_recorders,
+            orphan_recorders,
+            listening,
+            paused,
+            process_tracks,
+            track_settings,
+            track_taps,
+            events,
 
             start_delay_secs,
             start_instant,
@@ -385,21 +792,50 @@ impl<PRB: PacketRingBuffer + 'static> _AudioProcessWatcher<PRB> {
         &mut self,
         p_id: u32,
         start_delay_secs: f64,
-    ) -> Option<()> {
+    ) -> Option<TrackKey> {
+        let processes = snapshot_processes().unwrap_or_default();
+        let change = self.process_tracks.lock().unwrap().session_added(p_id, &processes);
+
         let mut audio_recorders = self.audio_recorders.lock().await;
-        if audio_recorders.contains_key(&p_id) {
-            return None;
-        }
-        let recorder = create_audio_recorder(&AudioSourceType::WasApiProcess { process_id: p_id, include_tree: self.include_tree }, &self.audio_codec, self.min_secs, start_delay_secs).ok()?;
+        match change {
+            SessionChange::Ignored => {
+                debug_println!("Ignored by process rules: PID: {p_id}");
+                None
+            }
+            SessionChange::AlreadyKnown => None,
+            SessionChange::JoinedTrack { key } => {
+                if let Some(track) = audio_recorders.get(&key) {
+                    if let Some(mix_commands) = &track.mix_commands {
+                        let _ = mix_commands.send(MixCommand::Add(p_id));
+                    }
+                    /*debug_println!*/eprintln!("Joined: PID: {p_id}, {}", track.name);
+                }
+                None
+            }
+            SessionChange::NewTrack { key, name } => {
+                let settings = TrackSettings::find(&self.track_settings, &[&name]).cloned();
+                let gain = gain_of(settings.as_ref());
+                let audio_codec = settings.as_ref().and_then(|settings| settings.codec).unwrap_or(self.audio_codec);
+                let taps = (self.track_taps)(&[&name]);
 
-        let p_name = Self::get_process_name(p_id).unwrap_or("UNKNOWN???".into());
+                let groups_processes = self.process_tracks.lock().unwrap().rules().groups_processes();
+                let mut factory = process_track_factory(audio_codec, self.format_settings.clone(), self.include_tree, groups_processes, self.min_secs.clone(), gain, taps);
 
-        /*debug_println!*/eprintln!("Added: PID: {p_id}, {p_name}");
+                let Ok((recorder, mix_commands)) = factory(&[p_id], None, start_delay_secs) else {
+                    let mut process_tracks = self.process_tracks.lock().unwrap();
+                    process_tracks.session_removed(p_id);
+                    process_tracks.forget_track(&key);
+                    return None;
+                };
 
-        let boo = Arc::new(AtomicBool::new(true));
-        audio_recorders.insert(p_id, (recorder, p_name, boo));
+                /*debug_println!*/eprintln!("Added: PID: {p_id}, {name}");
+                self.events.publish(Event::TrackAdded { name: name.clone() });
 
-        Some(())
+                audio_recorders.insert(key.clone(), ProcessTrack::new(recorder, name, settings, factory, mix_commands, self.paused.clone()));
+
+                Some(key)
+            }
+        }
     }
 
     pub async fn start_listening(mut self) -> Result<()> {
@@ -422,23 +858,28 @@ impl<PRB: PacketRingBuffer + 'static> _AudioProcessWatcher<PRB> {
             unsafe { session_handle.OnSessionCreated(&session_control)? }
         }
 
-        for (_, (ref mut recorder, _, _)) in self.audio_recorders.lock().await.iter_mut() {
-            recorder.start_recording(None);
+        for (_, track) in self.audio_recorders.lock().await.iter_mut() {
+            track.start();
         }
 
         let _ = unsafe { &self.session_manager.RegisterSessionNotification(&session_handle) };
 
 
         let audio_recorders = self.audio_recorders.clone();
-        let min_secs = self.min_secs;
+        let orphan_recorders = self.orphan_recorders.clone();
+        let process_tracks = self.process_tracks.clone();
+        let events = self.events.clone();
 
         tokio::spawn(async move {
             while let Some(p_id) = add_process_rx.recv().await {
+                if !self.listening.load(Ordering::Relaxed) {
+                    break;
+                }
                 let delay = self.start_instant.elapsed().as_secs_f64();
-                if let Some(_) = unsafe { self.try_add_new_process(p_id, delay + self.start_delay_secs) }.await {
+                if let Some(key) = unsafe { self.try_add_new_process(p_id, delay + self.start_delay_secs) }.await {
                     let mut audio_recorders = self.audio_recorders.lock().await;
-                    if let Some((ref mut recorder, _, boo)) = audio_recorders.get_mut(&p_id) {
-                        recorder.start_recording(Some(boo.clone()));
+                    if let Some(track) = audio_recorders.get_mut(&key) {
+                        track.start();
                     } else {
                         debug_println!("Recorder removed again :(")
                     }
@@ -450,20 +891,66 @@ impl<PRB: PacketRingBuffer + 'static> _AudioProcessWatcher<PRB> {
             let audio_recorders = audio_recorders;
             let r_session_events = r_session_events;
             while let Some(p_id) = remove_process_rx.recv().await {
+                let removed = process_tracks.lock().unwrap().session_removed(p_id);
+                let _ = r_session_events.lock().unwrap().remove(&p_id);
+
+                let Some((key, now_empty)) = removed else {
+                    debug_println!("did NOT removed process {p_id}");
+                    continue;
+                };
+
+                if !now_empty {
+                    if let Some(track) = audio_recorders.lock().await.get(&key) {
+                        if let Some(mix_commands) = &track.mix_commands {
+                            let _ = mix_commands.send(MixCommand::Remove(p_id));
+                        }
+                        /*debug_println!*/eprintln!("removed process {p_id} from {}", track.name);
+                    }
+                    continue;
+                }
+
+                // a process of the track showing up again within REJOIN_GRACE keeps recording into it,
+                // otherwise the track stops and stays readable as an orphan
+                let emptied_at = Instant::now();
                 let audio_recorders = audio_recorders.clone();
-                let r_session_events = r_session_events.clone();
+                let orphan_recorders = orphan_recorders.clone();
+                let process_tracks = process_tracks.clone();
+                let events = events.clone();
                 tokio::spawn(async move {
-                    tokio::time::sleep(Duration::from_secs(min_secs as u64)).await;
+                    tokio::time::sleep(REJOIN_GRACE).await;
                     let mut audio_recorders = audio_recorders.lock().await;
-                    if let Some((_, _, boo)) = audio_recorders.remove(&p_id) {
-                        boo.store(false, Ordering::Relaxed);
-                        /*debug_println!*/eprintln!("removed process {p_id}");
-                    } else {
-                        debug_println!("did NOT removed process {p_id}");
+                    {
+                        let mut process_tracks = process_tracks.lock().unwrap();
+                        if !process_tracks.is_empty_track(&key) {
+                            debug_println!("process {p_id} rejoined {:?}, keeping it", key);
+                            return;
+                        }
+                        process_tracks.forget_track(&key);
                     }
 
-                    let mut r_session_events = r_session_events.lock().unwrap();
-                    let _ = r_session_events.remove(&p_id);
+                    if let Some(mut track) = audio_recorders.remove(&key) {
+                        track.running.store(false, Ordering::Relaxed);
+                        // nothing was heard since the last process left
+                        track.finished_at = Some(emptied_at);
+                        /*debug_println!*/eprintln!("orphaned process {p_id}, {}", track.name);
+                        events.publish(Event::TrackRemoved { name: track.name.clone() });
+                        orphan_recorders.lock().await.push(track);
+                    }
+                });
+            }
+        });
+
+        let orphan_recorders = self.orphan_recorders.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ORPHAN_REAP_INTERVAL);
+            loop {
+                interval.tick().await;
+                orphan_recorders.lock().await.retain(|track| {
+                    let aged_out = track.aged_out();
+                    if aged_out {
+                        /*debug_println!*/eprintln!("removed orphaned track {}", track.name);
+                    }
+                    !aged_out
                 });
             }
         });
@@ -471,27 +958,54 @@ impl<PRB: PacketRingBuffer + 'static> _AudioProcessWatcher<PRB> {
 
         Ok(())
     }
+}
 
-    fn get_process_name(pid: u32) -> Option<String> {
-        unsafe {
-            let snapshot = windows::Win32::System::Diagnostics::ToolHelp::CreateToolhelp32Snapshot(windows::Win32::System::Diagnostics::ToolHelp::TH32CS_SNAPPROCESS, 0).ok()?;
-            let mut entry = windows::Win32::System::Diagnostics::ToolHelp::PROCESSENTRY32::default();
-            entry.dwSize = size_of::<windows::Win32::System::Diagnostics::ToolHelp::PROCESSENTRY32>() as u32;
-
-            if let Ok(_) = windows::Win32::System::Diagnostics::ToolHelp::Process32First(snapshot, &mut entry) {
-                loop {
-                    if entry.th32ProcessID == pid {
-                        // Convert [i8] to CStr, then to Rust String
-                        let cstr = core::ffi::CStr::from_ptr(entry.szExeFile.as_ptr());
-                        return Some(cstr.to_string_lossy().into_owned());
-                    }
-                    if let Err(_) = windows::Win32::System::Diagnostics::ToolHelp::Process32Next(snapshot, &mut entry) {
-                        break;
-                    }
+fn process_track_factory<PRB: PacketRingBuffer + 'static>(
+    audio_codec: AudioCodec,
+    format_settings: AudioFormatSettings,
+    include_tree: bool,
+    groups_processes: bool,
+    min_secs: Arc<AtomicU32>,
+    gain: f32,
+    taps: Vec<AudioTap>,
+) -> ProcessTrackFactory<PRB> {
+    Box::new(move |process_ids, ring_buffer, start_delay_secs| {
+        let min_secs = min_secs.load(Ordering::Relaxed);
+        match groups_processes {
+            true => {
+                create_process_group_recorder(process_ids, include_tree, &audio_codec, &format_settings, min_secs, start_delay_secs, gain, &taps, ring_buffer).map(|(recorder, mix_commands)| (recorder, Some(mix_commands)))
+            }
+            false => {
+                let process_id = process_ids.first().copied().ok_or(CustomError::CUSTOM(Error::Unknown))?;
+                create_audio_recorder(&AudioSourceType::WasApiProcess { process_id, include_tree }, &audio_codec, &format_settings, min_secs, start_delay_secs, gain, &taps, ring_buffer).map(|recorder| (recorder, None))
+            }
+        }
+    })
+}
+
+fn snapshot_processes() -> Option<HashMap<u32, ProcessInfo>> {
+    unsafe {
+        let snapshot = windows::Win32::System::Diagnostics::ToolHelp::CreateToolhelp32Snapshot(windows::Win32::System::Diagnostics::ToolHelp::TH32CS_SNAPPROCESS, 0).ok()?;
+        let mut entry = windows::Win32::System::Diagnostics::ToolHelp::PROCESSENTRY32::default();
+        entry.dwSize = size_of::<windows::Win32::System::Diagnostics::ToolHelp::PROCESSENTRY32>() as u32;
+
+        let mut processes = HashMap::new();
+        if let Ok(_) = windows::Win32::System::Diagnostics::ToolHelp::Process32First(snapshot, &mut entry) {
+            loop {
+                // Convert [i8] to CStr, then to Rust String
+                let cstr = core::ffi::CStr::from_ptr(entry.szExeFile.as_ptr());
+                processes.insert(entry.th32ProcessID, ProcessInfo {
+                    pid: entry.th32ProcessID,
+                    parent_pid: (entry.th32ParentProcessID != 0).then_some(entry.th32ParentProcessID),
+                    name: cstr.to_string_lossy().into_owned(),
+                });
+                if let Err(_) = windows::Win32::System::Diagnostics::ToolHelp::Process32Next(snapshot, &mut entry) {
+                    break;
                 }
             }
-            None
         }
+        let _ = windows::Win32::Foundation::CloseHandle(snapshot);
+        Some(processes)
     }
 }
 
@@ -1,5 +1,4 @@
 use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
 
 use ffmpeg_next::{ChannelLayout, Codec};
 use ffmpeg_next::codec::Flags;
@@ -7,31 +6,40 @@ use ffmpeg_next::encoder::audio;
 use ffmpeg_next::encoder::audio::Encoder;
 use ffmpeg_next::format::Sample;
 use ffmpeg_next::frame::Audio;
-use windows::Win32::Media::Audio::{IAudioCaptureClient, WAVEFORMATEX};
 
+use crate::recorders::traits::RingBufferWriter;
 use crate::ring_buffer::traits::PacketRingBuffer;
 use crate::types::Result;
 
 pub trait WasapiEncoderCtx {
+    // `samples` are already converted to interleaved f32 at the encoder's channel count and rate, `new_pts` is the pts of the first one
     fn process_audio<PRB: PacketRingBuffer>(
         &mut self,
-        ring_buffer: &Arc<Mutex<PRB>>,
+        ring_buffer: &RingBufferWriter<PRB>,
         encoder: &mut Encoder,
         frame: &mut Audio,
         silent_frame: &mut Audio,
-        packet_length: u32,
-        data: *mut u8,
-        qpc_pos: u64,
-        start_time: i64,
-        frequency: i64,
-        format: &WAVEFORMATEX,
+        samples: &[f32],
+        new_pts: i64,
+        channels: usize,
         pts_counter: &mut i64,
-        audio_buffer: &mut VecDeque<u8>,
-        capture_client: &IAudioCaptureClient,
+        audio_buffer: &mut VecDeque<f32>,
+    ) -> Result<()>;
+
+    // encodes what's left in `audio_buffer`, padded with silence to a whole frame
+    fn flush<PRB: PacketRingBuffer>(
+        &mut self,
+        ring_buffer: &RingBufferWriter<PRB>,
+        encoder: &mut Encoder,
+        frame: &mut Audio,
+        silent_frame: &mut Audio,
+        channels: usize,
+        pts_counter: &mut i64,
+        audio_buffer: &mut VecDeque<f32>,
     ) -> Result<()>;
 }
 
-pub fn new_audio_encoder_aac(
+pub fn new_audio_encoder(
     mut enc: audio::Audio,
     codec: Codec, rate: i32,
     channel_layout: ChannelLayout,
@@ -43,6 +51,6 @@ pub fn new_audio_encoder_aac(
     enc.set_time_base((1, rate));
     enc.set_flags(Flags::GLOBAL_HEADER);
 
-    let audio_encoder = enc.open_as(codec).unwrap();
+    let audio_encoder = enc.open_as(codec)?;
     Ok(audio_encoder)
 }
\ No newline at end of file
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, sync_channel, SyncSender};
+
+// chunks an analysis can fall behind by before new ones are dropped, the capture thread never waits for it
+const TAP_BACKLOG: usize = 256;
+
+// The taps for a new track, by its names (title, device or process name) as TrackSettings matches them
+pub type TrackTaps = Arc<dyn Fn(&[&str]) -> Vec<AudioTap> + Send + Sync>;
+
+// Mono copy of what a recorder captured, before its gain
+pub struct AudioChunk {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub start_secs: f64, // of the first sample, on the recorders' shared clock
+}
+
+// Lets an analysis (see triggers) listen to a track without touching its encoding
+#[derive(Clone)]
+pub struct AudioTap {
+    tx: SyncSender<AudioChunk>,
+    start_delay_secs: f64,
+}
+
+impl AudioTap {
+    pub fn new() -> (Self, Receiver<AudioChunk>) {
+        let (tx, rx) = sync_channel(TAP_BACKLOG);
+        let tap = Self {
+            tx,
+            start_delay_secs: 0.,
+        };
+        (tap, rx)
+    }
+
+    // The same tap for a recorder (re)started `start_delay_secs` after the shared time origin, its pts start at 0 again
+    pub fn with_start_delay(&self, start_delay_secs: f64) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            start_delay_secs,
+        }
+    }
+
+    // `samples` interleaved, `pts` of the first one in 1/`sample_rate`
+    pub fn send(&self, samples: &[f32], channels: usize, sample_rate: u32, pts: i64) {
+        let samples = samples.chunks_exact(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32).collect();
+        let _ = self.tx.try_send(AudioChunk {
+            samples,
+            sample_rate,
+            start_secs: pts as f64 / sample_rate as f64 + self.start_delay_secs,
+        });
+    }
+}
\ No newline at end of file
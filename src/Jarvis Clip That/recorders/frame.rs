@@ -2,7 +2,7 @@ use ffmpeg_next::ChannelLayout;
 use ffmpeg_next::format::Sample;
 use ffmpeg_next::frame::Audio;
 
-use ffmpeg_next::sys::{av_buffer_ref, av_frame_alloc, av_hwframe_get_buffer, AVBufferRef, AVFrame, AVPixelFormat};
+use ffmpeg_next::sys::{av_buffer_ref, av_frame_alloc, av_frame_free, av_frame_get_buffer, av_hwframe_get_buffer, AVBufferRef, AVFrame, AVPixelFormat};
 use crate::debug_println;
 use crate::error::{CustomError, Error};
 
@@ -39,6 +39,28 @@ pub fn create_av_frame(
     Ok(av_frame)
 }
 
+// System memory frame for encoders fed through CpuUploadAdapter
+pub fn create_sw_av_frame(
+    format: AVPixelFormat,
+    width: i32,
+    height: i32,
+) -> Result<*mut AVFrame> {
+    unsafe {
+        let mut av_frame = av_frame_alloc();
+        if av_frame.is_null() {
+            return Err(CustomError::CUSTOM(Error::Unknown));
+        }
+        (*av_frame).format = format as i32;
+        (*av_frame).width = width;
+        (*av_frame).height = height;
+        if av_frame_get_buffer(av_frame, 0) < 0 {
+            av_frame_free(&mut av_frame);
+            return Err(CustomError::CUSTOM(Error::Unknown));
+        }
+        Ok(av_frame)
+    }
+}
+
 pub fn create_audio_frames(
     format: Sample,
     size: usize,
@@ -54,54 +76,56 @@ pub fn create_audio_frames(
         size,
         layout,
     );
-    let buf = vec![0u8; size * silent_frame.format().bytes() * silent_frame.channels() as usize];
-    debug_println!("SIZE: {}",size * silent_frame.format().bytes() * silent_frame.channels() as usize);
-    unsafe { copy_into_audio_frame(&mut silent_frame, buf); }
+    let buf = vec![0f32; size * silent_frame.channels() as usize];
+    debug_println!("SIZE: {}", size * silent_frame.format().bytes() * silent_frame.channels() as usize);
+    unsafe { copy_into_audio_frame(&mut silent_frame, &buf); }
 
     (frame, silent_frame)
 }
 
+// `samples` are interleaved f32 with the frame's channel count, they get converted into whatever sample format the frame has
 pub unsafe fn copy_into_audio_frame(
     frame: &mut Audio,
-    buffer: Vec<u8>,
-) { // ONLY FOR 4 byte stereo
-    let bytes_per_sample = frame.format().bytes();
+    samples: &[f32],
+) {
+    let format = frame.format();
+    let bytes_per_sample = format.bytes();
     let num_channels = frame.channels() as usize;
-
+    let is_planar = format.is_planar();
 
     let linesize = unsafe { (*frame.as_ptr()).linesize[0] as usize };
 
-    let planes: Vec<*mut u8> = (0..num_channels).map(|i| (*frame.as_ptr()).extended_data.add(i).read()).collect();
+    let plane_count = if is_planar { num_channels } else { 1 };
+    let planes: Vec<*mut u8> = (0..plane_count).map(|i| (*frame.as_ptr()).extended_data.add(i).read()).collect();
 
-    let frame_size = num_channels * bytes_per_sample;
-    let samples = buffer.len() / frame_size;
+    let sample_count = (samples.len() / num_channels).min(frame.samples());
 
-    for i in 0..samples {
-        let frame_offset = i * frame_size;
+    for i in 0..sample_count {
         for channel_index in 0..num_channels {
-            let chan_ptr = planes[channel_index];
-            let dst = std::slice::from_raw_parts_mut(chan_ptr, linesize);
-
-            let src_start = frame_offset + channel_index * bytes_per_sample;
-            let src_end = src_start + bytes_per_sample;
-            let dst_start = i * bytes_per_sample;
-            let dst_end = dst_start + bytes_per_sample;
-
-            dst[dst_start..dst_end].copy_from_slice(&buffer[src_start..src_end]);
+            let (plane, dst_start) = match is_planar {
+                true => { (planes[channel_index], i * bytes_per_sample) }
+                false => { (planes[0], (i * num_channels + channel_index) * bytes_per_sample) }
+            };
+            let dst = std::slice::from_raw_parts_mut(plane, linesize);
+            write_sample(format, samples[i * num_channels + channel_index], &mut dst[dst_start..dst_start + bytes_per_sample]);
         }
     }
+}
 
-
-    /*let ptr0 = unsafe { (*frame.as_ptr()).extended_data.offset(0).read() };
-    let ptr1 = unsafe { (*frame.as_ptr()).extended_data.offset(1).read() };
-    // get mutable slices to the destination planes
-    let left_plane = unsafe { std::slice::from_raw_parts_mut(ptr0, linesize) };
-    let right_plane = unsafe { std::slice::from_raw_parts_mut(ptr1, linesize) };
-
-    // process buffer directly into planes
-    for (i, chunk) in buffer.chunks(8).enumerate() {
-        let offset = i * 4;
-        left_plane[offset..offset + 4].copy_from_slice(&chunk[0..4]);
-        right_plane[offset..offset + 4].copy_from_slice(&chunk[4..8]);
-    }*/
-}
\ No newline at end of file
+fn write_sample(
+    format: Sample,
+    sample: f32,
+    dst: &mut [u8],
+) {
+    // float formats may go above full scale, integer ones can't
+    let clamped = sample.clamp(-1., 1.);
+    match format {
+        Sample::U8(_) => { dst[0] = ((clamped * 127.) + 128.) as u8 }
+        Sample::I16(_) => { dst.copy_from_slice(&((clamped * i16::MAX as f32) as i16).to_ne_bytes()) }
+        Sample::I32(_) => { dst.copy_from_slice(&((clamped as f64 * i32::MAX as f64) as i32).to_ne_bytes()) }
+        Sample::I64(_) => { dst.copy_from_slice(&((clamped as f64 * i64::MAX as f64) as i64).to_ne_bytes()) }
+        Sample::F32(_) => { dst.copy_from_slice(&sample.to_ne_bytes()) }
+        Sample::F64(_) => { dst.copy_from_slice(&(sample as f64).to_ne_bytes()) }
+        Sample::None => {}
+    }
+}
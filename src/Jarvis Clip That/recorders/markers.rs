@@ -0,0 +1,38 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+// far more than fit in a replay, a save only writes the ones inside its clip
+const MAX_MARKERS: usize = 256;
+
+// A moment marked while recording, see Markers
+#[derive(Clone, Debug)]
+pub struct Marker {
+    pub secs: f64, // since the recorders' time origin
+    pub label: Option<String>,
+}
+
+// Bookmarks kept next to the ring buffers. Nothing is saved when one is set, clips saved later get the ones they contain as chapters
+pub struct Markers {
+    origin: Instant,
+    markers: VecDeque<Marker>, // oldest first
+}
+
+impl Markers {
+    pub fn new(origin: Instant) -> Self {
+        Self {
+            origin,
+            markers: VecDeque::new(),
+        }
+    }
+
+    pub fn add(&mut self, label: Option<String>) {
+        self.markers.push_back(Marker { secs: self.origin.elapsed().as_secs_f64(), label });
+        if self.markers.len() > MAX_MARKERS {
+            self.markers.pop_front();
+        }
+    }
+
+    pub fn all(&self) -> Vec<Marker> {
+        self.markers.iter().cloned().collect()
+    }
+}
\ No newline at end of file
@@ -3,4 +3,8 @@ pub mod recorder;
 pub mod audio;
 pub mod video;
 pub mod frame;
-pub mod save;
\ No newline at end of file
+pub mod save;
+pub mod track_settings;
+pub mod supervisor;
+pub mod pause;
+pub mod markers;
\ No newline at end of file
@@ -0,0 +1,60 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+// Privacy mode. While paused the recorders keep reading their sources but nothing reaches the encoders,
+// so the paused time is missing from the ring buffers instead of showing up as frozen frames
+pub struct Pause {
+    paused: Arc<AtomicBool>,
+    intervals: Mutex<Vec<(Instant, Option<Instant>)>>, // (paused at, resumed at), oldest first
+}
+
+impl Pause {
+    pub fn new() -> Self {
+        Self {
+            paused: Arc::new(AtomicBool::new(false)),
+            intervals: Mutex::new(Vec::new()),
+        }
+    }
+
+    // What the recorder threads check before every frame
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        self.paused.clone()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    // Returns whether the state changed
+    pub fn set_paused(&self, paused: bool) -> bool {
+        let mut intervals = self.intervals.lock().unwrap();
+        if self.paused.swap(paused, Ordering::Relaxed) == paused {
+            return false;
+        }
+        match paused {
+            true => { intervals.push((Instant::now(), None)); }
+            false => {
+                if let Some((_, resumed_at)) = intervals.last_mut() {
+                    *resumed_at = Some(Instant::now());
+                }
+            }
+        }
+        true
+    }
+
+    // Returns the new state
+    pub fn toggle(&self) -> bool {
+        let paused = !self.is_paused();
+        self.set_paused(paused);
+        paused
+    }
+
+    // The paused stretches in seconds since `origin`, an ongoing pause ends now
+    pub fn gaps_since(&self, origin: Instant) -> Vec<(f64, f64)> {
+        let secs_since_origin = |instant: Instant| instant.saturating_duration_since(origin).as_secs_f64();
+        self.intervals.lock().unwrap().iter()
+            .map(|(paused_at, resumed_at)| (secs_since_origin(*paused_at), secs_since_origin(resumed_at.unwrap_or(Instant::now()))))
+            .collect()
+    }
+}
\ No newline at end of file
@@ -1,28 +1,39 @@
 use std::hint::unreachable_unchecked;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::AtomicBool;
+use std::time::Instant;
 
-use ffmpeg_next::ChannelLayout;
+use ffmpeg_next::{ChannelLayout, Codec};
 use ffmpeg_next::codec::Parameters;
 use ffmpeg_next::encoder::find_by_name;
-use ffmpeg_next::format::Sample;
+use ffmpeg_next::format::{Pixel, Sample};
 use ffmpeg_next::format::sample::Type;
-use ffmpeg_next::sys::AVPixelFormat::{AV_PIX_FMT_D3D11, AV_PIX_FMT_QSV};
+use ffmpeg_next::sys::AVPixelFormat::AV_PIX_FMT_D3D11;
 
+use crate::debug_println;
+use crate::error::{CustomError, Error};
 use crate::error::Error::Unknown;
 use crate::recorders::audio::audio_recorder::AudioRecorder;
+use crate::recorders::audio::convert::AudioFormatSettings;
 use crate::recorders::audio::sources::enums::{AudioCodec, AudioSourceType};
-use crate::recorders::audio::sources::wasapi::aac::{AAC_FRAME_SIZE, AacContext};
-use crate::recorders::audio::sources::wasapi::source::AudioSourceWasapi;
-use crate::recorders::audio::sources::wasapi::traits::new_audio_encoder_aac;
-use crate::recorders::frame::{create_audio_frames, create_av_frame};
+use crate::recorders::audio::sources::traits::AudioSource;
+use crate::recorders::audio::sources::wasapi::accumulator::{FrameAccumulator, VARIABLE_FRAME_SIZE};
+use crate::recorders::audio::sources::wasapi::format::input_format;
+use crate::recorders::audio::sources::wasapi::mix::{AudioSourceWasapiMix, MixCommand};
+use crate::recorders::audio::sources::wasapi::source::{AudioSourceWasapi, process_loopback_format};
+use crate::recorders::audio::sources::wasapi::traits::new_audio_encoder;
+use crate::recorders::audio::tap::AudioTap;
+use crate::recorders::frame::{create_audio_frames, create_av_frame, create_sw_av_frame};
+use crate::recorders::supervisor::Heartbeat;
 use crate::recorders::traits::TRecorder;
 use crate::recorders::video::sources::d3d111::d3d11av::D3d11vaAdapter;
-use crate::recorders::video::sources::d3d111::qsv::QsvAdapter;
+use crate::recorders::video::sources::d3d111::cpu::CpuUploadAdapter;
 use crate::recorders::video::sources::d3d111::source::VideoSourceD3d11;
-use crate::recorders::video::sources::d3d111::traits::{create_encoder_d3d11, D3d11EncoderHwContext};
+use crate::recorders::video::sources::d3d111::traits::{create_encoder_d3d11, create_encoder_software, D3d11EncoderHwContext};
 use crate::recorders::video::sources::enums::{VideoCodec, VideoSourceType};
-use crate::recorders::video::video_recorder::VideoRecorder;
+use crate::recorders::video::encoder_settings::VideoEncoderSettings;
+use crate::recorders::video::transform::{ScalingBackend, VideoTransformSettings};
+use crate::recorders::video::video_recorder::{VideoOutput, VideoRecorder};
 use crate::ring_buffer::traits::PacketRingBuffer;
 use crate::types::{RecorderJoinHandle, Result};
 use crate::wrappers::MaybeSafeFFIPtrWrapper;
@@ -45,6 +56,20 @@ impl<PRB: PacketRingBuffer> Recorder<PRB> {
         Self::from_boxed_recorder(recorder, ring_buffer, parameters, start_delay_secs)
     }
 
+    // Shares the capture thread of another recorder, see create_video_recorders
+    fn without_recorder(
+        ring_buffer: Arc<Mutex<PRB>>,
+        parameters: Parameters,
+        start_delay_secs: f64,
+    ) -> Self {
+        Self {
+            recorder: None,
+            ring_buffer,
+            parameters,
+            start_delay_secs,
+        }
+    }
+
     fn from_boxed_recorder(
         recorder: Box<dyn TRecorder<PRB> + Send>,
         ring_buffer: Arc<Mutex<PRB>>,
@@ -63,80 +88,156 @@ impl<PRB: PacketRingBuffer> Recorder<PRB> {
     pub fn start_recording(
         &mut self,
         stop_capturing_callback: Option<Arc<AtomicBool>>,
+        heartbeat: Option<Arc<Heartbeat>>,
+        paused: Option<Arc<AtomicBool>>,
     ) -> Option<RecorderJoinHandle> {
         self.recorder.take().map(|recorder| {
-            recorder.start_capturing(stop_capturing_callback)
+            recorder.start_capturing(stop_capturing_callback, heartbeat, paused)
         })
     }
+
+    // Ring buffers count frames for video and samples for audio
+    fn frames_per_sec(&self) -> f64 {
+        let parameters = unsafe { *self.parameters.as_ptr() };
+        match parameters.sample_rate > 0 {
+            true => { parameters.sample_rate as f64 }
+            false => { parameters.framerate.num as f64 / parameters.framerate.den.max(1) as f64 }
+        }
+    }
+
+    pub fn retention_secs(&self) -> f64 {
+        self.ring_buffer.lock().unwrap().min_frame_amount() as f64 / self.frames_per_sec().max(1.)
+    }
+
+    // How much the ring buffer currently holds
+    pub fn buffered_secs(&self) -> f64 {
+        self.ring_buffer.lock().unwrap().frame_amount() as f64 / self.frames_per_sec().max(1.)
+    }
+
+    pub fn set_retention_secs(&self, secs: u32) {
+        let min_frame_amount = (secs as f64 * self.frames_per_sec()).round() as u32;
+        self.ring_buffer.lock().unwrap().set_min_frame_amount(min_frame_amount);
+    }
 }
 
 
-pub fn create_video_recorder<PRB: PacketRingBuffer + 'static>(
+// One capture feeding an encoder and ring buffer per tier (min_secs, settings), e.g. a short high quality and a long low quality replay.
+// The first returned recorder runs the capture for all of them, the others only hold their ring buffers.
+// Tiers with an entry in `ring_buffers` keep writing into it instead of a new one (see Supervisor)
+pub fn create_video_recorders<PRB: PacketRingBuffer + 'static>(
     video_source_type: &VideoSourceType,
     video_codec: &VideoCodec,
-    min_secs: u32,
+    tiers: &[(u32, &VideoEncoderSettings)],
     width: u32,
     height: u32,
     fps: i32,
     start_delay_secs: f64,
-) -> Result<Recorder<PRB>> {
-    let ring_buffer = PRB::new(min_secs * fps as u32);
-    let arc_ring_buffer = Arc::new(Mutex::new(ring_buffer));
+    transform: &VideoTransformSettings,
+    time_origin: Instant,
+    ring_buffers: &[Arc<Mutex<PRB>>],
+) -> Result<Vec<Recorder<PRB>>> {
+    let codec = find_by_name(video_codec.encoder_name()).ok_or(ffmpeg_next::Error::EncoderNotFound)?;
+    let new_encoder_ctx = || ffmpeg_next::codec::context::Context::new_with_codec(codec).encoder().video();
+    let new_ring_buffer = |tier: usize, min_secs: u32| ring_buffers.get(tier).cloned().unwrap_or_else(|| Arc::new(Mutex::new(PRB::new(min_secs * fps as u32))));
 
-    let codec = match video_codec {
-        VideoCodec::Amf => { find_by_name("hevc_amf").ok_or(ffmpeg_next::Error::EncoderNotFound)? }
-        VideoCodec::Qsv => { return Err(ffmpeg_next::Error::EncoderNotFound.into()); }
-    };
-    let ctx = ffmpeg_next::codec::context::Context::new_with_codec(codec);
-    let enc = ctx.encoder().video()?;
+    let mut outputs = Vec::with_capacity(tiers.len());
+    let mut handles = Vec::with_capacity(tiers.len());
 
-    let idk = match video_source_type {
+    let video_recorder: Box<dyn TRecorder<PRB> + Send> = match video_source_type {
         VideoSourceType::D3d11 { monitor_id } => {
             match video_codec {
-                VideoCodec::Amf => {
-                    let d3d11_vs = VideoSourceD3d11::new(*monitor_id, D3d11vaAdapter)?;
+                VideoCodec::Amf | VideoCodec::Nvenc => {
+                    let d3d11_vs = VideoSourceD3d11::new(*monitor_id, D3d11vaAdapter, Some(transform.clone()))?;
                     let (hw_device_ctx, hw_frame_ctx) = d3d11_vs.encoder_hw_ctx.setup_hw_and_frame_ctx(&d3d11_vs.device, width as i32, height as i32)?;
-                    let encoder = create_encoder_d3d11(enc, codec, (hw_device_ctx, hw_frame_ctx), width, height, fps)?;
-                    let parameters = Parameters::from(&encoder);
-                    let av_frame = create_av_frame(AV_PIX_FMT_D3D11, width as i32, height as i32, hw_frame_ctx)?;
-                    let recorder = VideoRecorder::new(arc_ring_buffer.clone(), d3d11_vs, encoder, MaybeSafeFFIPtrWrapper(av_frame), width, height, fps as f64);
-                    Recorder::from_recorder(recorder, arc_ring_buffer, parameters, start_delay_secs)
+                    for (tier, (min_secs, encoder_settings)) in tiers.iter().enumerate() {
+                        let encoder = create_encoder_d3d11(new_encoder_ctx()?, codec, (hw_device_ctx, hw_frame_ctx), width, height, fps, video_codec, encoder_settings)?;
+                        let av_frame = create_av_frame(AV_PIX_FMT_D3D11, width as i32, height as i32, hw_frame_ctx)?;
+                        let ring_buffer = new_ring_buffer(tier, *min_secs);
+                        handles.push((ring_buffer.clone(), Parameters::from(&encoder)));
+                        outputs.push(VideoOutput::new(ring_buffer, encoder, MaybeSafeFFIPtrWrapper(av_frame)));
+                    }
+                    Box::new(VideoRecorder::new(d3d11_vs, outputs, width, height, fps as f64, time_origin))
                 }
-                VideoCodec::Qsv => {
-                    let d3d11_vs = VideoSourceD3d11::new(*monitor_id, QsvAdapter)?;
-                    let (hw_device_ctx, hw_frame_ctx) = d3d11_vs.encoder_hw_ctx.setup_hw_and_frame_ctx(&d3d11_vs.device, width as i32, height as i32)?;
-                    let encoder = create_encoder_d3d11(enc, codec, (hw_device_ctx, hw_frame_ctx), width, height, fps)?;
-                    let parameters = Parameters::from(&encoder);
-                    let av_frame = create_av_frame(AV_PIX_FMT_QSV, width as i32, height as i32, hw_frame_ctx)?;
-                    let recorder = VideoRecorder::new(arc_ring_buffer.clone(), d3d11_vs, encoder, MaybeSafeFFIPtrWrapper(av_frame), width, height, fps as f64);
-                    Recorder::from_recorder(recorder, arc_ring_buffer, parameters, start_delay_secs)
+                // QsvAdapter can't hand over frames yet, so QSV gets system memory frames like the software encoders
+                VideoCodec::Qsv | VideoCodec::X264 | VideoCodec::X265 | VideoCodec::SvtAv1 => {
+                    let format = software_pixel_format(&codec)?;
+                    let (gpu_transform, cpu_transform) = match transform.backend {
+                        ScalingBackend::Gpu => { (Some(transform.clone()), None) }
+                        ScalingBackend::Cpu => { (None, Some(transform.clone())) }
+                    };
+                    let d3d11_vs = VideoSourceD3d11::new(*monitor_id, CpuUploadAdapter::new(format, cpu_transform)?, gpu_transform)?;
+                    for (tier, (min_secs, encoder_settings)) in tiers.iter().enumerate() {
+                        let encoder = create_encoder_software(new_encoder_ctx()?, codec, format, width, height, fps, video_codec, encoder_settings)?;
+                        let av_frame = create_sw_av_frame(format.into(), width as i32, height as i32)?;
+                        let ring_buffer = new_ring_buffer(tier, *min_secs);
+                        handles.push((ring_buffer.clone(), Parameters::from(&encoder)));
+                        outputs.push(VideoOutput::new(ring_buffer, encoder, MaybeSafeFFIPtrWrapper(av_frame)));
+                    }
+                    Box::new(VideoRecorder::new(d3d11_vs, outputs, width, height, fps as f64, time_origin))
                 }
             }
         }
     };
-    Ok(idk)
+
+    let mut video_recorder = Some(video_recorder);
+    let recorders = handles.into_iter().map(|(ring_buffer, parameters)| {
+        match video_recorder.take() {
+            Some(video_recorder) => { Recorder::from_boxed_recorder(video_recorder, ring_buffer, parameters, start_delay_secs) }
+            None => { Recorder::without_recorder(ring_buffer, parameters, start_delay_secs) }
+        }
+    }).collect();
+    Ok(recorders)
 }
 
+// Tries every codec in order and returns the recorders of the first one whose encoders all open
+pub fn create_video_recorders_with_fallback<PRB: PacketRingBuffer + 'static>(
+    video_source_type: &VideoSourceType,
+    video_codecs: &[VideoCodec],
+    tiers: &[(u32, &VideoEncoderSettings)],
+    width: u32,
+    height: u32,
+    fps: i32,
+    start_delay_secs: f64,
+    transform: &VideoTransformSettings,
+    time_origin: Instant,
+) -> Result<(Vec<Recorder<PRB>>, VideoCodec)> {
+    let mut last_err = CustomError::CUSTOM(Error::NonExistentParameterCombination);
+    for video_codec in video_codecs {
+        match create_video_recorders::<PRB>(video_source_type, video_codec, tiers, width, height, fps, start_delay_secs, transform, time_origin, &[]) {
+            Ok(recorders) => {
+                debug_println!("Recording video with {}", video_codec.encoder_name());
+                return Ok((recorders, *video_codec));
+            }
+            Err(err) => {
+                eprintln!("Video encoder {} unavailable: {:?}", video_codec.encoder_name(), err);
+                last_err = err;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+fn software_pixel_format(codec: &Codec) -> Result<Pixel> {
+    let formats: Vec<Pixel> = codec.video()?.formats().map(|formats| formats.collect()).unwrap_or_default();
+    match formats.is_empty() || formats.contains(&Pixel::NV12) {
+        true => { Ok(Pixel::NV12) }
+        false if formats.contains(&Pixel::YUV420P) => { Ok(Pixel::YUV420P) }
+        false => { Err(Error::NonExistentParameterCombination.into()) }
+    }
+}
 
 pub fn create_audio_recorder<PRB: PacketRingBuffer + 'static>(
     audio_source_type: &AudioSourceType,
     audio_code_c: &AudioCodec,
+    format_settings: &AudioFormatSettings,
     min_secs: u32,
     start_delay_secs: f64,
+    gain: f32,
+    taps: &[AudioTap],
+    ring_buffer: Option<Arc<Mutex<PRB>>>,
 ) -> Result<Recorder<PRB>> {
-    let create_ring_buffer = |rate| -> Arc<Mutex<PRB>>{
-        let ring_buffer = PRB::new(min_secs * rate);
-        Arc::new(Mutex::new(ring_buffer))
-    };
-
-    let codec = match audio_code_c {
-        AudioCodec::AAC => { ffmpeg_next::codec::encoder::find(ffmpeg_next::codec::Id::AAC).ok_or(ffmpeg_next::Error::EncoderNotFound)? }
-        //AudioCodec::Test => { return Err(ffmpeg_next::Error::EncoderNotFound.into()); }
-    };
-    let ctx = ffmpeg_next::codec::context::Context::new_with_codec(codec);
-    let enc = ctx.encoder().audio()?;
-
-    let idk = match audio_source_type {
+    let taps = taps.iter().map(|tap| tap.with_start_delay(start_delay_secs)).collect();
+    match audio_source_type {
         AudioSourceType::WasApiDefaultSys | AudioSourceType::WasApiDefaultInput => {
             let render_else_capture = match audio_source_type {
                 AudioSourceType::WasApiDefaultSys => { true }
@@ -144,44 +245,115 @@ pub fn create_audio_recorder<PRB: PacketRingBuffer + 'static>(
                 _ => { unsafe { unreachable_unchecked() } }
             };
 
-            match audio_code_c {
-                AudioCodec::AAC => {
-                    let sample = Sample::F32(Type::Planar);
-                    let aac_vs = AudioSourceWasapi::new_default(AacContext, render_else_capture)?;
-                    let channel_layout = match aac_vs.format.nChannels {
-                        1 => ChannelLayout::MONO,
-                        2 => ChannelLayout::STEREO,
-                        _ => return Err(Unknown.into())
-                    };
-                    let (frame, silent_frame) = create_audio_frames(sample, AAC_FRAME_SIZE, channel_layout);
-                    let encoder = new_audio_encoder_aac(enc, codec, aac_vs.format.nSamplesPerSec as i32, channel_layout, sample)?;
-                    let parameters = Parameters::from(&encoder);
-                    let arc_ring_buffer = create_ring_buffer(aac_vs.format.nSamplesPerSec);
-                    let recorder = AudioRecorder::new(arc_ring_buffer.clone(), aac_vs, encoder, frame, silent_frame);
-                    Recorder::from_recorder(recorder, arc_ring_buffer, parameters, start_delay_secs)
-                }
-            }
+            let audio_source = AudioSourceWasapi::new_default(FrameAccumulator::new(gain, taps), render_else_capture, format_settings)?;
+            create_audio_recorder_from_source(audio_source, audio_code_c, min_secs, start_delay_secs, ring_buffer)
         }
         AudioSourceType::WasApiProcess { process_id, include_tree } => {
-            match audio_code_c {
-                AudioCodec::AAC => {
-                    let sample = Sample::F32(Type::Planar);
-                    let aac_vs = AudioSourceWasapi::new_process(AacContext, *process_id, *include_tree)?;
-                    let channel_layout = match aac_vs.format.nChannels {
-                        1 => ChannelLayout::MONO,
-                        2 => ChannelLayout::STEREO,
-                        _ => return Err(Unknown.into())
-                    };
-                    let (frame, silent_frame) = create_audio_frames(sample, AAC_FRAME_SIZE, channel_layout);
-                    let encoder = new_audio_encoder_aac(enc, codec, aac_vs.format.nSamplesPerSec as i32, channel_layout, sample)?;
-                    let parameters = Parameters::from(&encoder);
-                    let arc_ring_buffer = create_ring_buffer(aac_vs.format.nSamplesPerSec);
-                    let recorder = AudioRecorder::new(arc_ring_buffer.clone(), aac_vs, encoder, frame, silent_frame);
-                    Recorder::from_recorder(recorder, arc_ring_buffer, parameters, start_delay_secs)
-                }
-            }
+            let audio_source = AudioSourceWasapi::new_process(FrameAccumulator::new(gain, taps), *process_id, *include_tree, format_settings)?;
+            create_audio_recorder_from_source(audio_source, audio_code_c, min_secs, start_delay_secs, ring_buffer)
+        }
+    }
+}
+
+pub fn create_process_group_recorder<PRB: PacketRingBuffer + 'static>(
+    process_ids: &[u32],
+    include_tree: bool,
+    audio_code_c: &AudioCodec,
+    format_settings: &AudioFormatSettings,
+    min_secs: u32,
+    start_delay_secs: f64,
+    gain: f32,
+    taps: &[AudioTap],
+    ring_buffer: Option<Arc<Mutex<PRB>>>,
+) -> Result<(Recorder<PRB>, std::sync::mpsc::Sender<MixCommand>)> {
+    let loopback_format = unsafe { input_format(&process_loopback_format().Format)? };
+    let taps = taps.iter().map(|tap| tap.with_start_delay(start_delay_secs)).collect();
+    let (mix_vs, mix_commands) = AudioSourceWasapiMix::new(loopback_format, format_settings, process_ids, include_tree, gain, taps);
+    let recorder = create_audio_recorder_from_source(mix_vs, audio_code_c, min_secs, start_delay_secs, ring_buffer)?;
+    Ok((recorder, mix_commands))
+}
+
+// The source follows the encoder: it downmixes or resamples if the encoder can't take its layout or rate
+fn create_audio_recorder_from_source<PRB: PacketRingBuffer + 'static, AS: AudioSource + Send + 'static>(
+    mut audio_source: AS,
+    audio_code_c: &AudioCodec,
+    min_secs: u32,
+    start_delay_secs: f64,
+    ring_buffer: Option<Arc<Mutex<PRB>>>,
+) -> Result<Recorder<PRB>> {
+    let codec = match audio_code_c {
+        AudioCodec::AAC => { ffmpeg_next::codec::encoder::find(ffmpeg_next::codec::Id::AAC) }
+        AudioCodec::Opus => { find_by_name("libopus") }
+        AudioCodec::Flac => { ffmpeg_next::codec::encoder::find(ffmpeg_next::codec::Id::FLAC) }
+    }.ok_or(ffmpeg_next::Error::EncoderNotFound)?;
+    let ctx = ffmpeg_next::codec::context::Context::new_with_codec(codec);
+    let enc = ctx.encoder().audio()?;
+
+    let channel_layout = match encoder_channel_layout(&codec, audio_source.channels()) {
+        Some(channel_layout) => channel_layout,
+        None => {
+            audio_source.downmix_to_stereo();
+            encoder_channel_layout(&codec, audio_source.channels()).ok_or(Unknown)?
         }
     };
+    let rate = encoder_sample_rate(&codec, audio_source.sample_rate());
+    if rate != audio_source.sample_rate() {
+        audio_source.resample_to(rate);
+    }
+    let sample = encoder_sample_format(&codec);
 
-    Ok(idk)
-}
\ No newline at end of file
+    let encoder = new_audio_encoder(enc, codec, rate as i32, channel_layout, sample)?;
+    let frame_size = match encoder.frame_size() {
+        0 => VARIABLE_FRAME_SIZE,
+        frame_size => frame_size as usize,
+    };
+    let (frame, silent_frame) = create_audio_frames(sample, frame_size, channel_layout);
+    let parameters = Parameters::from(&encoder);
+    let arc_ring_buffer = ring_buffer.unwrap_or_else(|| Arc::new(Mutex::new(PRB::new(min_secs * rate))));
+    let recorder = AudioRecorder::new(arc_ring_buffer.clone(), audio_source, encoder, frame, silent_frame);
+    Ok(Recorder::from_recorder(recorder, arc_ring_buffer, parameters, start_delay_secs))
+}
+
+// ffmpeg's default layout for the channel count, or one with the same count if the encoder only lists others.
+// None if the encoder can't take that many channels at all
+fn encoder_channel_layout(
+    codec: &Codec,
+    channels: usize,
+) -> Option<ChannelLayout> {
+    let default_layout = ChannelLayout::default(channels as i32);
+    let Some(layouts) = codec.audio().ok().and_then(|audio| audio.channel_layouts()) else {
+        return Some(default_layout);
+    };
+
+    let layouts: Vec<ChannelLayout> = layouts.collect();
+    if layouts.contains(&default_layout) {
+        return Some(default_layout);
+    }
+    layouts.into_iter().find(|layout| layout.channels() == channels as i32)
+}
+
+// The source's rate if the encoder takes it, otherwise the next higher one it does (Opus only takes 8k to 48k)
+fn encoder_sample_rate(
+    codec: &Codec,
+    rate: u32,
+) -> u32 {
+    let Some(rates) = codec.audio().ok().and_then(|audio| audio.rates()) else {
+        return rate;
+    };
+
+    let rates: Vec<u32> = rates.map(|rate| rate as u32).collect();
+    if rates.is_empty() || rates.contains(&rate) {
+        return rate;
+    }
+    rates.iter().copied().filter(|supported| *supported > rate).min().or(rates.iter().copied().max()).unwrap_or(rate)
+}
+
+// float if the encoder takes it, the conversion layer works in float anyway
+fn encoder_sample_format(codec: &Codec) -> Sample {
+    let formats: Vec<Sample> = codec.audio().ok().and_then(|audio| audio.formats()).map(|formats| formats.collect()).unwrap_or_default();
+    [Sample::F32(Type::Planar), Sample::F32(Type::Packed)]
+        .into_iter()
+        .find(|sample| formats.contains(sample))
+        .or(formats.first().copied())
+        .unwrap_or(Sample::F32(Type::Planar))
+}
@@ -35,6 +35,11 @@ impl KeyListener {
         self.shortcuts.lock().unwrap().push(shortcut);
     }
 
+    // Also works while the listener is running, to swap in new shortcuts
+    pub fn clear_shortcuts(&mut self) {
+        self.shortcuts.lock().unwrap().clear();
+    }
+
     pub fn reset_keys(&self) {
         self.pressed_keys.lock().unwrap().clear();
     }
@@ -0,0 +1,94 @@
+use ffmpeg_next::codec::Parameters;
+use ffmpeg_next::format::Sample;
+use ffmpeg_next::format::sample::Type;
+use ffmpeg_next::frame::Audio;
+
+use crate::types::{Packet, Result};
+
+#[derive(Clone)]
+pub struct SilenceFilter {
+    min_level_db: f32,
+    always_keep: Vec<String>,
+}
+
+impl SilenceFilter {
+    pub fn new(
+        min_level_db: f32,
+        always_keep: Vec<String>,
+    ) -> Self {
+        Self {
+            min_level_db,
+            always_keep,
+        }
+    }
+
+    pub fn always_keeps(&self, title: &str) -> bool {
+        self.always_keep.iter().any(|keep| keep.eq_ignore_ascii_case(title))
+    }
+
+    pub fn is_audible(&self, packets: &[Packet], parameters: &Parameters) -> Result<bool> {
+        is_above_level(packets, parameters, self.min_level_db)
+    }
+}
+
+// Decodes the packets until one sample peaks above `min_level_db` (dBFS), so loud tracks return early
+pub fn is_above_level(
+    packets: &[Packet],
+    parameters: &Parameters,
+    min_level_db: f32,
+) -> Result<bool> {
+    let min_amplitude = db_to_amplitude(min_level_db);
+
+    let ctx = ffmpeg_next::codec::context::Context::from_parameters(parameters.clone())?;
+    let mut decoder = ctx.decoder().audio()?;
+    let mut frame = Audio::empty();
+
+    for packet in packets {
+        decoder.send_packet(packet)?;
+        while decoder.receive_frame(&mut frame).is_ok() {
+            if frame_peak(&frame) > min_amplitude {
+                return Ok(true);
+            }
+        }
+    }
+
+    decoder.send_eof()?;
+    while decoder.receive_frame(&mut frame).is_ok() {
+        if frame_peak(&frame) > min_amplitude {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn frame_peak(frame: &Audio) -> f32 {
+    let channels = frame.channels() as usize;
+    let samples = frame.samples();
+
+    match frame.format() {
+        Sample::F32(Type::Planar) => {
+            (0..frame.planes()).flat_map(|i| frame.plane::<f32>(i).iter()).fold(0f32, |peak, sample| peak.max(sample.abs()))
+        }
+        Sample::F32(Type::Packed) => {
+            frame.data(0)[..samples * channels * 4].chunks_exact(4).map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).fold(0f32, |peak, sample| peak.max(sample.abs()))
+        }
+        Sample::I16(Type::Planar) => {
+            (0..frame.planes()).flat_map(|i| frame.plane::<i16>(i).iter()).fold(0f32, |peak, sample| peak.max((*sample as f32 / i16::MAX as f32).abs()))
+        }
+        Sample::I16(Type::Packed) => {
+            frame.data(0)[..samples * channels * 2].chunks_exact(2).map(|bytes| i16::from_ne_bytes([bytes[0], bytes[1]])).fold(0f32, |peak, sample| peak.max((sample as f32 / i16::MAX as f32).abs()))
+        }
+        Sample::I32(Type::Planar) => {
+            (0..frame.planes()).flat_map(|i| frame.plane::<i32>(i).iter()).fold(0f32, |peak, sample| peak.max((*sample as f32 / i32::MAX as f32).abs()))
+        }
+        Sample::I32(Type::Packed) => {
+            frame.data(0)[..samples * channels * 4].chunks_exact(4).map(|bytes| i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).fold(0f32, |peak, sample| peak.max((sample as f32 / i32::MAX as f32).abs()))
+        }
+        _ => f32::INFINITY, // unknown layout, never treat it as silent
+    }
+}
+
+pub fn db_to_amplitude(db: f32) -> f32 {
+    10f32.powf(db / 20.)
+}
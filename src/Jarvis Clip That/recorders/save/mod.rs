@@ -1,2 +1,3 @@
 pub mod key_listener;
-pub mod saver;
\ No newline at end of file
+pub mod saver;
+pub mod level;
\ No newline at end of file
@@ -5,24 +5,52 @@ use std::sync::{Arc, Mutex};
 use chrono::Local;
 use ffmpeg_next::codec::Parameters;
 use ffmpeg_next::format::context;
+use ffmpeg_next::format::stream::Disposition;
 use rodio::Decoder;
 use crate::debug_println;
 use crate::error::{CustomError, Error};
+use crate::recorders::frame::create_audio_frames;
+use crate::recorders::markers::Marker;
+use crate::recorders::recorder::Recorder;
+use crate::recorders::save::level::SilenceFilter;
+use crate::recorders::track_settings::TrackSettings;
 
 use crate::ring_buffer::traits::PacketRingBuffer;
 use crate::types::{Packet, Result};
 
+struct SaveStream {
+    packets: Vec<Packet>,
+    parameters: Parameters,
+    time_base: (i32, i32),
+    start_delay_secs: f64, // where pts 0 of this stream lies relative to the other streams
+    name: Option<String>, // as added, title is what the track settings made of it
+    silenced_secs: Vec<(f64, f64)>,
+    drop_if_silent: bool, // see drop_silent_streams
+
+    title: Option<String>,
+    language: Option<String>,
+    disposition: Disposition,
+    order: i32,
+}
+
 pub struct Save {
+    file_name: String,
     o_ctx: context::Output,
-    streams: Vec<(Vec<Packet>, (i32, i32))>,
+    streams: Vec<SaveStream>,
+    max_duration_secs: Option<f64>,
+    time_range_secs: (Option<f64>, Option<f64>),
+    gaps_secs: Vec<(f64, f64)>,
+    markers: Vec<Marker>,
 
     save_sound_decoder: Option<Decoder<Cursor<Vec<u8>>>>,
+    silence_filter: Option<SilenceFilter>,
 }
 
 impl Save {
     fn new(
         file_name: String,
         save_sound_file: Option<Vec<u8>>,
+        silence_filter: Option<SilenceFilter>,
     ) -> Result<Self> {
         let o_ctx = ffmpeg_next::format::output_as(&file_name, "mp4")?;
         let streams = Vec::new();
@@ -30,79 +58,329 @@ impl Save {
         let save_sound_decoder = save_sound_file.and_then(|save_sound_file| Some(Decoder::new(Cursor::new(save_sound_file)).ok()?));
 
         Ok(Self {
+            file_name,
             o_ctx,
             streams,
+            max_duration_secs: None,
+            time_range_secs: (None, None),
+            gaps_secs: Vec::new(),
+            markers: Vec::new(),
             save_sound_decoder,
+            silence_filter,
         })
     }
 
     pub fn add_stream<PRB: PacketRingBuffer>(
         &mut self,
-        ring_buffer: &Arc<Mutex<PRB>>,
-        parameters: &Parameters,
+        recorder: &Recorder<PRB>,
         is_video_else_audio: bool,
         title: Option<&str>,
+        settings: Option<&TrackSettings>,
     ) -> Result<()> {
-        let ost = &mut self.o_ctx.add_stream(parameters.id())?;
-        ost.set_parameters(parameters.clone());
-        if let Some(title) = title {
-            let mut dict = ffmpeg_next::Dictionary::new();
-            dict.set("title", title);
-            ost.set_metadata(dict)
-        }
-
-        let tb = match is_video_else_audio {
-            true => { (unsafe { *parameters.as_ptr() }.framerate.den, unsafe { *parameters.as_ptr() }.framerate.num) }
-            false => { (1, unsafe { *parameters.as_ptr() }.sample_rate) }
-        };
-        ost.set_time_base(tb);
+        let packets = Self::copy_out(&recorder.ring_buffer);
+        self.push_stream(packets, recorder, is_video_else_audio, title, settings, false);
+        Ok(())
+    }
 
+    // Like add_stream (audio only), but the track is left out of the clip if it stays below the SilenceFilter level
+    // in the part that is saved, see drop_silent_streams
+    pub fn add_stream_if_audible<PRB: PacketRingBuffer>(
+        &mut self,
+        recorder: &Recorder<PRB>,
+        title: &str,
+        settings: Option<&TrackSettings>,
+    ) -> Result<()> {
+        let packets = Self::copy_out(&recorder.ring_buffer);
+        let drop_if_silent = self.silence_filter.as_ref().is_some_and(|silence_filter| !silence_filter.always_keeps(title));
+        self.push_stream(packets, recorder, false, Some(title), settings, drop_if_silent);
+        Ok(())
+    }
 
+    fn copy_out<PRB: PacketRingBuffer>(ring_buffer: &Arc<Mutex<PRB>>) -> Vec<Packet> {
         let ring_buffer = ring_buffer.lock().unwrap();
         let packets = ring_buffer.copy_out(None);
         drop(ring_buffer);
+        packets
+    }
+
+    fn push_stream<PRB: PacketRingBuffer>(
+        &mut self,
+        packets: Vec<Packet>,
+        recorder: &Recorder<PRB>,
+        is_video_else_audio: bool,
+        title: Option<&str>,
+        settings: Option<&TrackSettings>,
+        drop_if_silent: bool,
+    ) {
+        let parameters = &recorder.parameters;
+        let time_base = match is_video_else_audio {
+            true => { (unsafe { *parameters.as_ptr() }.framerate.den, unsafe { *parameters.as_ptr() }.framerate.num) }
+            false => { (1, unsafe { *parameters.as_ptr() }.sample_rate) }
+        };
 
         let packets_pts: Vec<_> = packets.iter().map(|packet| packet.pts()).collect();
         debug_println!("NEW STREAM PTS: {:?}", packets_pts);
         debug_println!("-------------------------------------------------------------------------------------------------------------------------------");
 
-        self.streams.push((packets, tb));
+        let muted = settings.is_some_and(|settings| settings.muted);
+        self.streams.push(SaveStream {
+            packets,
+            parameters: parameters.clone(),
+            time_base,
+            start_delay_secs: recorder.start_delay_secs,
+            name: title.map(str::to_string),
+            silenced_secs: Vec::new(),
+            drop_if_silent,
+
+            title: settings.and_then(|settings| settings.title.clone()).or(title.map(str::to_string)),
+            language: settings.and_then(|settings| settings.language.clone()),
+            disposition: if muted { Disposition::empty() } else { Disposition::DEFAULT },
+            order: settings.map_or(0, |settings| settings.order),
+        });
+    }
 
-        Ok(())
+    pub fn file_name(&self) -> &str {
+        &self.file_name
     }
 
-    pub fn finalize_and_save(mut self) -> Result<()> {
-        let min_pts_in_base_1_sec = self.streams
-            .iter()
-            .filter_map(|(packets, tb)|
-                packets.first()
-                    .and_then(|p| p.pts().map(|pts| pts as f64 / (tb.1 as f64 / tb.0 as f64)))
-            )
-            .reduce(f64::min)
-            .unwrap_or(0.0);
-        let min_dts_in_base_1_sec = self.streams
+    // Streams are cut so none of them reaches further back than `max_duration_secs` before the newest packet
+    pub fn set_max_duration(&mut self, max_duration_secs: Option<f64>) {
+        self.max_duration_secs = max_duration_secs;
+    }
+
+    // Only keeps packets from `from_secs` up to `until_secs` on the streams' shared clock (pts secs plus start delay),
+    // used to split a clip where an encoder was reconfigured
+    pub fn set_time_range(&mut self, from_secs: Option<f64>, until_secs: Option<f64>) {
+        self.time_range_secs = (from_secs, until_secs);
+    }
+
+    // Stretches on the shared clock where nothing was recorded (see Pause), cut out so the clip plays straight through them
+    pub fn set_gaps(&mut self, gaps_secs: Vec<(f64, f64)>) {
+        self.gaps_secs = gaps_secs;
+    }
+
+    // Stretches on the shared clock left out of the audio streams added as `name`, e.g. a spoken voice command on the mic
+    pub fn set_silenced(&mut self, name: &str, ranges_secs: Vec<(f64, f64)>) {
+        for stream in self.streams.iter_mut().filter(|stream| stream.name.as_deref() == Some(name)) {
+            stream.silenced_secs = ranges_secs.clone();
+        }
+    }
+
+    // Swaps the packets in the silenced stretches for encoded silence with the same timestamps.
+    // Left out, the MP4 muxer would stretch the packet before each hole over it and the track would run ahead of the video
+    fn silence(&mut self) {
+        for stream in self.streams.iter_mut() {
+            if stream.silenced_secs.is_empty() || stream.parameters.medium() != ffmpeg_next::media::Type::Audio {
+                continue;
+            }
+            let silenced_secs = std::mem::take(&mut stream.silenced_secs);
+            let silenced: Vec<usize> = stream.packets.iter().enumerate()
+                .filter(|(_, packet)| Self::secs_of(stream, packet).is_some_and(|secs| silenced_secs.iter().any(|(start, end)| secs >= *start && secs < *end)))
+                .map(|(i, _)| i)
+                .collect();
+            if silenced.is_empty() {
+                continue;
+            }
+
+            let durations: Vec<i64> = silenced.iter().map(|i| stream.packets[*i].duration()).collect();
+            let silent_packets = match encode_silence(&stream.parameters, stream.time_base, &durations) {
+                Ok(silent_packets) => silent_packets,
+                Err(err) => {
+                    eprintln!("Couldn't silence {:?}, it's saved as recorded: {:?}", stream.name, err);
+                    continue;
+                }
+            };
+            for (i, mut silent_packet) in silenced.iter().zip(silent_packets) {
+                let packet = &stream.packets[*i];
+                silent_packet.set_pts(packet.pts());
+                silent_packet.set_dts(packet.dts());
+                silent_packet.set_duration(packet.duration());
+                stream.packets[*i] = silent_packet;
+            }
+            debug_println!("silenced {} packets of {:?}", silenced.len(), stream.name);
+        }
+    }
+
+    // Only what's left after trimming counts, a track that was loud minutes ago but is quiet in the clip is left out
+    fn drop_silent_streams(&mut self) {
+        let Some(silence_filter) = &self.silence_filter else {
+            return;
+        };
+        self.streams.retain(|stream| {
+            if !stream.drop_if_silent {
+                return true;
+            }
+            match silence_filter.is_audible(&stream.packets, &stream.parameters) {
+                Ok(true) => { true }
+                Ok(false) => {
+                    debug_println!("skipped silent stream: {:?}", stream.name);
+                    false
+                }
+                Err(err) => {
+                    eprintln!("Couldn't analyse level of {:?}, keeping it: {:?}", stream.name, err);
+                    true
+                }
+            }
+        });
+    }
+
+    fn close_gaps(&mut self) {
+        if self.gaps_secs.is_empty() {
+            return;
+        }
+
+        for stream in self.streams.iter_mut() {
+            let rate = stream.time_base.1 as f64 / stream.time_base.0 as f64;
+            let start_delay_secs = stream.start_delay_secs;
+            let is_video = stream.parameters.medium() == ffmpeg_next::media::Type::Video;
+            let shift_of = |secs: f64| gap_shift(&self.gaps_secs, secs);
+
+            // audio from the moment the pause began is dropped, a video frame can't be without breaking the ones referencing it
+            stream.packets.retain(|packet| is_video || !packet.pts().is_some_and(|pts| shift_of(pts as f64 / rate + start_delay_secs).1));
+            for packet in stream.packets.iter_mut() {
+                let Some(pts) = packet.pts() else {
+                    continue;
+                };
+                let shift = (shift_of(pts as f64 / rate + start_delay_secs).0 * rate).round() as i64;
+                packet.set_pts(Some(pts - shift));
+                packet.set_dts(packet.dts().map(|dts| dts - shift));
+            }
+        }
+    }
+
+    // Written as chapters if they lie inside the clip, on the shared clock like set_gaps
+    pub fn set_markers(&mut self, markers: Vec<Marker>) {
+        self.markers = markers;
+    }
+
+    // (start, end, title) in milliseconds of the clip starting at `start_secs` on the shared clock, after close_gaps.
+    // A chapter lasts until the next marker or the end of the clip
+    fn chapters(&self, start_secs: f64) -> Vec<(i64, i64, String)> {
+        let Some(end_secs) = self.streams.iter().filter_map(|stream| stream.packets.last().and_then(|packet| Self::secs_of(stream, packet))).reduce(f64::max) else {
+            return Vec::new();
+        };
+        let ms_of = |secs: f64| ((secs - start_secs) * 1000.).round() as i64;
+        let starts: Vec<(i64, String)> = self.markers.iter()
+            // a marker set while paused ends up where the pause was cut out
+            .map(|marker| (marker.secs - gap_shift(&self.gaps_secs, marker.secs).0, marker.label.as_ref()))
+            .filter(|(secs, _)| *secs >= start_secs && *secs <= end_secs)
+            .enumerate()
+            .map(|(i, (secs, label))| (ms_of(secs), label.cloned().unwrap_or_else(|| format!("Marker {}", i + 1))))
+            .collect();
+        let ends = starts.iter().skip(1).map(|(start, _)| *start).chain([ms_of(end_secs)]);
+        starts.iter().zip(ends).map(|((start, title), end)| (*start, end, title.clone())).collect()
+    }
+
+    fn secs_of(stream: &SaveStream, packet: &Packet) -> Option<f64> {
+        packet.pts().map(|pts| pts as f64 * stream.time_base.0 as f64 / stream.time_base.1 as f64 + stream.start_delay_secs)
+    }
+
+    fn trim_to_time_range(&mut self) {
+        let (from_secs, until_secs) = self.time_range_secs;
+        if let Some(until_secs) = until_secs {
+            for stream in self.streams.iter_mut() {
+                let end = stream.packets.iter().position(|packet| Self::secs_of(stream, packet).is_some_and(|secs| secs >= until_secs)).unwrap_or(stream.packets.len());
+                stream.packets.truncate(end);
+            }
+        }
+        if let Some(from_secs) = from_secs {
+            self.cut_before(from_secs);
+        }
+    }
+
+    fn trim_to_max_duration(&mut self) {
+        let Some(max_duration_secs) = self.max_duration_secs else {
+            return;
+        };
+
+        let Some(end) = self.streams.iter().filter_map(|stream| stream.packets.last().and_then(|packet| Self::secs_of(stream, packet))).reduce(f64::max) else {
+            return;
+        };
+        self.cut_before(end - max_duration_secs);
+    }
+
+    fn cut_before(&mut self, cutoff: f64) {
+        for stream in self.streams.iter_mut() {
+            // the last keyframe at or before the cutoff, so video still starts decodable
+            let first = stream.packets.iter()
+                .rposition(|packet| packet.is_key() && Self::secs_of(stream, packet).is_some_and(|secs| secs <= cutoff))
+                .unwrap_or(0);
+            stream.packets.drain(..first);
+        }
+    }
+
+    // Where the earliest packet of all streams lies on the shared clock, by pts or dts
+    fn first_secs(&self, timestamp: fn(&Packet) -> Option<i64>) -> f64 {
+        self.streams
             .iter()
-            .filter_map(|(packets, tb)|
-                packets.first()
-                    .and_then(|p| p.dts().map(|dts| dts as f64 / (tb.1 as f64 / tb.0 as f64)))
+            .filter_map(|stream|
+                stream.packets.first()
+                    .and_then(|p| timestamp(p).map(|ts| ts as f64 * stream.time_base.0 as f64 / stream.time_base.1 as f64 + stream.start_delay_secs))
             )
             .reduce(f64::min)
-            .unwrap_or(0.0);
+            .unwrap_or(0.0)
+    }
+
+    // Moves every stream to where it was recorded on the shared clock and starts the clip at the earliest packet.
+    // Streams whose pts 0 isn't the program start (process tracks that showed up later, orphans, restarted recorders)
+    // would otherwise all begin at the start of the clip
+    fn align_to_shared_clock(&mut self) {
+        let min_pts_in_base_1_sec = self.first_secs(Packet::pts);
+        let min_dts_in_base_1_sec = self.first_secs(Packet::dts);
 
         debug_println!("min pts: {}, min dts: {}", min_pts_in_base_1_sec, min_dts_in_base_1_sec);
 
-        self.streams.iter_mut().for_each(|(packets, tb)| packets.iter_mut().for_each(|packet| {
-            packet.set_pts(packet.pts().map(|pts| (pts as f64 - (tb.1 as f64 / tb.0 as f64) * min_pts_in_base_1_sec) as i64));
-            packet.set_dts(packet.dts().map(|pts| (pts as f64 - (tb.1 as f64 / tb.0 as f64) * min_dts_in_base_1_sec) as i64));
-        }));
+        self.streams.iter_mut().for_each(|stream| {
+            let rate = stream.time_base.1 as f64 / stream.time_base.0 as f64;
+            let start_delay_secs = stream.start_delay_secs;
+            stream.packets.iter_mut().for_each(|packet| {
+                packet.set_pts(packet.pts().map(|pts| (pts as f64 - rate * (min_pts_in_base_1_sec - start_delay_secs)) as i64));
+                packet.set_dts(packet.dts().map(|pts| (pts as f64 - rate * (min_dts_in_base_1_sec - start_delay_secs)) as i64));
+            })
+        });
+    }
+
+    pub fn finalize_and_save(mut self) -> Result<()> {
+        self.trim_to_time_range();
+        self.silence();
+        self.close_gaps();
+        self.trim_to_max_duration();
+        self.drop_silent_streams();
+
+        let chapters = self.chapters(self.first_secs(Packet::pts));
+        self.align_to_shared_clock();
+
+        // stable, so tracks without an order keep the order they were added in
+        self.streams.sort_by_key(|stream| stream.order);
+
+        for stream in &self.streams {
+            let mut ost = self.o_ctx.add_stream(stream.parameters.id())?;
+            ost.set_parameters(stream.parameters.clone());
+
+            let mut dict = ffmpeg_next::Dictionary::new();
+            if let Some(title) = &stream.title {
+                dict.set("title", title);
+            }
+            if let Some(language) = &stream.language {
+                dict.set("language", language);
+            }
+            ost.set_metadata(dict);
+            unsafe { (*ost.as_mut_ptr()).disposition = stream.disposition.bits(); }
+
+            ost.set_time_base(stream.time_base);
+        }
 
+        // the muxer only picks up chapters that exist before the header
+        for (i, (start, end, title)) in chapters.iter().enumerate() {
+            self.o_ctx.add_chapter(i as i64, (1, 1000), *start, *end, title)?;
+        }
         self.o_ctx.write_header()?;
 
         let time_bases = self.o_ctx.streams().map(|stream| stream.time_base()).collect::<Vec<_>>().into_iter();
-        for (i, (time_base, (packets, tb))) in time_bases.zip(self.streams.into_iter()).enumerate() {
-            for mut packet in packets {
+        for (i, (time_base, stream)) in time_bases.zip(self.streams.into_iter()).enumerate() {
+            for mut packet in stream.packets {
                 packet.set_stream(i);
-                packet.rescale_ts(tb, time_base);
+                packet.rescale_ts(stream.time_base, time_base);
                 packet.write_interleaved(&mut self.o_ctx)?;
             }
         }
@@ -124,12 +402,83 @@ impl Save {
     }
 }
 
+// A packet of silence per entry of `durations` (in `time_base`), from an encoder set up like the one of `parameters`
+fn encode_silence(
+    parameters: &Parameters,
+    time_base: (i32, i32),
+    durations: &[i64],
+) -> Result<Vec<Packet>> {
+    let codec = match parameters.id() {
+        // like the recorders, ffmpeg's own Opus encoder is experimental
+        ffmpeg_next::codec::Id::OPUS => { ffmpeg_next::codec::encoder::find_by_name("libopus") }
+        id => { ffmpeg_next::codec::encoder::find(id) }
+    }.ok_or(ffmpeg_next::Error::EncoderNotFound)?;
+    let mut enc = ffmpeg_next::codec::context::Context::from_parameters(parameters.clone())?.encoder().audio()?;
+    let rate = enc.rate() as i32;
+    enc.set_time_base((1, rate));
+    let durations_samples: Vec<i64> = durations.iter()
+        .map(|duration| (*duration as f64 * time_base.0 as f64 / time_base.1 as f64 * rate as f64).round() as i64)
+        .collect();
+    enc.set_flags(ffmpeg_next::codec::Flags::GLOBAL_HEADER);
+    let mut encoder = enc.open_as(codec)?;
+
+    let mut packets = Vec::with_capacity(durations.len());
+    let mut packet = Packet::empty();
+    let mut pts = 0;
+    // encoders hold a few frames back, so silence is fed until enough came out
+    let frame_durations = durations_samples.iter().copied().chain(std::iter::repeat(durations_samples.last().copied().unwrap_or_default()).take(16));
+    for duration in frame_durations {
+        if packets.len() >= durations.len() {
+            break;
+        }
+        let frame_size = match encoder.frame_size() {
+            0 => { if duration > 0 { duration as usize } else { 1024 } }
+            frame_size => { frame_size as usize }
+        };
+        let (_, mut silent_frame) = create_audio_frames(encoder.format(), frame_size, encoder.channel_layout());
+        silent_frame.set_rate(rate as u32);
+        silent_frame.set_pts(Some(pts));
+        pts += frame_size as i64;
+        encoder.send_frame(&silent_frame)?;
+        while encoder.receive_packet(&mut packet).is_ok() {
+            packets.push(packet.clone());
+        }
+    }
+    if packets.len() < durations.len() {
+        encoder.send_eof()?;
+        while encoder.receive_packet(&mut packet).is_ok() {
+            packets.push(packet.clone());
+        }
+    }
+    if packets.len() < durations.len() {
+        return Err(Error::Unknown.into());
+    }
+    packets.truncate(durations.len());
+    Ok(packets)
+}
+
+// How much of the gaps lies before `secs`, and whether `secs` is inside one
+fn gap_shift(gaps_secs: &[(f64, f64)], secs: f64) -> (f64, bool) {
+    let mut shift = 0.;
+    let mut inside = false;
+    for (start, end) in gaps_secs.iter() {
+        if secs >= *end {
+            shift += end - start;
+        } else if secs > *start {
+            shift += secs - start;
+            inside = true;
+        }
+    }
+    (shift, inside)
+}
+
 #[derive(Clone)]
 pub struct SaverEnv {
     out_dir_path: String,
     base_file_name: String,
 
     preferred_sound_file: Option<Vec<u8>>,
+    silence_filter: Option<SilenceFilter>,
 }
 
 impl SaverEnv {
@@ -152,30 +501,59 @@ impl SaverEnv {
             out_dir_path,
             base_file_name,
             preferred_sound_file,
+            silence_filter: None,
         }
     }
 
+    pub fn out_dir(&self) -> &str {
+        &self.out_dir_path
+    }
+
+    pub fn with_silence_filter(
+        mut self,
+        silence_filter: Option<SilenceFilter>,
+    ) -> Self {
+        self.silence_filter = silence_filter;
+        self
+    }
+
     pub fn new_save<S: Into<String>>(
         &self,
         file_name: Option<S>,
     ) -> Result<Save> {
         let file_name = match file_name {
-            None => { self.get_file_name("mp4").ok_or(CustomError::CUSTOM(Error::Unknown))? }
+            None => { self.get_file_name(None, "mp4").ok_or(CustomError::CUSTOM(Error::Unknown))? }
             Some(file_name) => { file_name.into() }
         };
 
         let save_sound_file = self.preferred_sound_file.as_ref().map(|preferred_sound_file| preferred_sound_file.clone());
 
-        Save::new(file_name, save_sound_file)
+        Save::new(file_name, save_sound_file, self.silence_filter.clone())
+    }
+
+    // A save in the out dir named `name` instead of the base file name and time, taken counts get a number appended
+    pub fn new_named_save(
+        &self,
+        name: &str,
+    ) -> Result<Save> {
+        // only the last path component, so a name can't point outside the out dir
+        let name = Path::new(name).file_stem().and_then(|stem| stem.to_str()).filter(|stem| !stem.is_empty())
+            .ok_or(CustomError::CUSTOM(Error::NonExistentParameterCombination))?;
+        let file_name = self.get_file_name(Some(name), "mp4").ok_or(CustomError::CUSTOM(Error::Unknown))?;
+        self.new_save(Some(file_name))
     }
 
     fn get_file_name<S: Into<String>>(
         &self,
+        name: Option<&str>,
         extension: S,
     ) -> Option<String> {
         let extension = extension.into();
 
-        let default_name = format!("{}/{}_{}", self.out_dir_path, self.base_file_name, Local::now().format("%Y%m%d_%H%M%S").to_string());
+        let default_name = match name {
+            None => { format!("{}/{}_{}", self.out_dir_path, self.base_file_name, Local::now().format("%Y%m%d_%H%M%S").to_string()) }
+            Some(name) => { format!("{}/{}", self.out_dir_path, name) }
+        };
 
         let first_try = format!("{}.{}", default_name, extension);
         if !Path::new(&first_try).exists() {
@@ -0,0 +1,373 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::debug_println;
+use crate::error::{CustomError, Error};
+use crate::recorders::recorder::Recorder;
+use crate::ring_buffer::traits::PacketRingBuffer;
+use crate::types::{RecorderJoinHandle, Result};
+
+// A recorder thread that hasn't made progress for this long counts as stuck
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// a recorder that ran this long before dying starts over at MIN_BACKOFF
+const HEALTHY_AFTER: Duration = Duration::from_secs(30);
+
+pub const SUPERVISE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Bumped by a recorder thread on every pass of its loop
+pub struct Heartbeat {
+    origin: Instant,
+    last_beat_ms: AtomicU64,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            last_beat_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn beat(&self) {
+        self.last_beat_ms.store(self.origin.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn since_last_beat(&self) -> Duration {
+        self.origin.elapsed().saturating_sub(Duration::from_millis(self.last_beat_ms.load(Ordering::Relaxed)))
+    }
+
+    pub fn stalled(&self) -> bool {
+        self.since_last_beat() > STALL_TIMEOUT
+    }
+}
+
+// When to start a died recorder thread again, waiting twice as long every time it dies soon after starting
+#[derive(Default)]
+pub struct Backoff {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self {
+            failures: 0,
+            retry_at: None,
+        }
+    }
+
+    // For a thread started at `started_at` that just died, returns how long the restart waits
+    pub fn schedule(&mut self, started_at: Instant) -> Duration {
+        if started_at.elapsed() > HEALTHY_AFTER {
+            self.failures = 0;
+        }
+        let backoff = MIN_BACKOFF.saturating_mul(2u32.saturating_pow(self.failures)).min(MAX_BACKOFF);
+        self.failures += 1;
+        self.retry_at = Some(Instant::now() + backoff);
+        backoff
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.retry_at.is_some()
+    }
+
+    // True once the scheduled restart is due, it's up to the caller from then on
+    pub fn take_due(&mut self) -> bool {
+        let due = self.retry_at.is_some_and(|retry_at| Instant::now() >= retry_at);
+        if due {
+            self.retry_at = None;
+        }
+        due
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.retry_at = None;
+    }
+}
+
+// Builds the recorders again on top of the given ring buffers, with the given start delay
+pub type RecorderFactory<PRB> = Box<dyn FnMut(&[Arc<Mutex<PRB>>], f64) -> Result<Vec<Recorder<PRB>>> + Send>;
+
+// Recorders of an earlier configuration, kept readable until their packets are older than their buffers reach back
+struct Generation<PRB: PacketRingBuffer> {
+    recorders: Vec<Recorder<PRB>>,
+    handle: Option<RecorderJoinHandle>, // still draining its encoders
+    ended_at: Instant,
+}
+
+impl<PRB: PacketRingBuffer> Generation<PRB> {
+    fn aged_out(&self) -> bool {
+        let retention_secs = self.recorders.iter().map(|recorder| recorder.retention_secs()).reduce(f64::max).unwrap_or(0.);
+        self.ended_at.elapsed().as_secs_f64() > retention_secs
+    }
+}
+
+// Recorders sharing one capture thread, e.g. all tiers of a video source
+struct SupervisedRecorders<PRB: PacketRingBuffer> {
+    name: String,
+    recorders: Vec<Recorder<PRB>>,
+    factory: RecorderFactory<PRB>,
+    continuous_pts: bool, // video counts frames from the shared time origin, audio starts at 0 again
+    previous: Vec<Generation<PRB>>, // oldest first
+
+    running: Arc<AtomicBool>,
+    heartbeat: Arc<Heartbeat>,
+    paused: Arc<AtomicBool>,
+    handle: Option<RecorderJoinHandle>,
+    started_at: Instant,
+    backoff: Backoff,
+}
+
+impl<PRB: PacketRingBuffer + 'static> SupervisedRecorders<PRB> {
+    fn start(&mut self) {
+        self.running = Arc::new(AtomicBool::new(true));
+        self.heartbeat = Arc::new(Heartbeat::new());
+        self.heartbeat.beat();
+        self.started_at = Instant::now();
+        self.handle = self.recorders.iter_mut().find_map(|recorder| recorder.start_recording(Some(self.running.clone()), Some(self.heartbeat.clone()), Some(self.paused.clone())));
+    }
+
+    fn schedule_restart(&mut self) {
+        let backoff = self.backoff.schedule(self.started_at);
+        eprintln!("Restarting {} in {:?}", self.name, backoff);
+    }
+
+    // Where pts 0 of recorders started now lies
+    fn next_start_delay_secs(&self) -> f64 {
+        let old_start_delay_secs = self.recorders.first().map_or(0., |recorder| recorder.start_delay_secs);
+        match self.continuous_pts {
+            true => { old_start_delay_secs }
+            false => { old_start_delay_secs + self.started_at.elapsed().as_secs_f64() }
+        }
+    }
+
+    // New recorders writing into the old ring buffers, so the replay history survives
+    fn restart(&mut self) -> Result<()> {
+        let ring_buffers: Vec<Arc<Mutex<PRB>>> = self.recorders.iter().map(|recorder| recorder.ring_buffer.clone()).collect();
+        let elapsed_secs = self.started_at.elapsed().as_secs_f64();
+        let recorders = (self.factory)(&ring_buffers, self.next_start_delay_secs())?;
+
+        if !self.continuous_pts {
+            // the new pts 0 lies `elapsed_secs` after the old one
+            for recorder in self.recorders.iter() {
+                let sample_rate = unsafe { *recorder.parameters.as_ptr() }.sample_rate;
+                recorder.ring_buffer.lock().unwrap().offset_pts(-(elapsed_secs * sample_rate as f64) as i64);
+            }
+        }
+
+        self.recorders = recorders;
+        self.start();
+        Ok(())
+    }
+
+    // New recorders with fresh ring buffers, the current ones become the newest previous generation.
+    // On error nothing changes
+    fn reconfigure(
+        &mut self,
+        mut factory: RecorderFactory<PRB>,
+    ) -> Result<()> {
+        let recorders = factory(&[], self.next_start_delay_secs())?;
+
+        self.running.store(false, Ordering::Relaxed);
+        self.previous.push(Generation {
+            recorders: std::mem::replace(&mut self.recorders, recorders),
+            handle: self.handle.take(),
+            ended_at: Instant::now(),
+        });
+        self.factory = factory;
+        self.backoff.reset();
+        self.start();
+        Ok(())
+    }
+
+    fn reap_generations(&mut self) {
+        for generation in self.previous.iter_mut() {
+            if generation.handle.as_ref().is_some_and(|handle| handle.is_finished()) {
+                if let Some(Ok(Err(err))) = generation.handle.take().map(|handle| handle.join()) {
+                    eprintln!("{} failed while stopping an old configuration: {:?}", self.name, err);
+                }
+            }
+        }
+        self.previous.retain(|generation| generation.handle.is_some() || !generation.aged_out());
+    }
+
+    // Returns what went wrong, if anything
+    fn check(&mut self) -> Option<String> {
+        self.reap_generations();
+
+        if self.backoff.is_scheduled() {
+            if !self.backoff.take_due() {
+                return None;
+            }
+            return match self.restart() {
+                Ok(()) => {
+                    debug_println!("Restarted {}", self.name);
+                    None
+                }
+                Err(err) => {
+                    let problem = format!("Couldn't restart {}: {:?}", self.name, err);
+                    eprintln!("{}", problem);
+                    self.schedule_restart();
+                    Some(problem)
+                }
+            };
+        }
+
+        let Some(handle) = &self.handle else {
+            return None;
+        };
+        let problem = if handle.is_finished() {
+            match self.handle.take().map(|handle| handle.join()) {
+                Some(Ok(Ok(()))) => { format!("{} stopped on its own", self.name) }
+                Some(Ok(Err(err))) => { format!("{} failed: {:?}", self.name, err) }
+                Some(Err(_)) | None => { format!("{} panicked", self.name) }
+            }
+        } else if self.heartbeat.stalled() {
+            // a stuck thread can't be joined, it's told to stop and left behind.
+            // It still holds the ring buffers, whatever it inserts if it wakes up again is dropped (see RingBufferWriter)
+            self.running.store(false, Ordering::Relaxed);
+            for recorder in self.recorders.iter() {
+                recorder.ring_buffer.lock().unwrap().retire_writer();
+            }
+            self.handle = None;
+            format!("{} stalled for {:?}", self.name, self.heartbeat.since_last_beat())
+        } else {
+            return None;
+        };
+        eprintln!("{}", problem);
+        self.schedule_restart();
+        Some(problem)
+    }
+}
+
+// Owns recorders, restarts the ones whose thread died or got stuck
+pub struct Supervisor<PRB: PacketRingBuffer> {
+    groups: Vec<SupervisedRecorders<PRB>>,
+    paused: Arc<AtomicBool>, // see Pause
+}
+
+impl<PRB: PacketRingBuffer + 'static> Supervisor<PRB> {
+    pub fn new(paused: Arc<AtomicBool>) -> Self {
+        Self {
+            groups: Vec::new(),
+            paused,
+        }
+    }
+
+    // Returns the index to get the recorders back with
+    pub fn add<S: Into<String>>(
+        &mut self,
+        name: S,
+        recorders: Vec<Recorder<PRB>>,
+        factory: RecorderFactory<PRB>,
+        continuous_pts: bool,
+    ) -> usize {
+        self.groups.push(SupervisedRecorders {
+            name: name.into(),
+            recorders,
+            factory,
+            continuous_pts,
+            previous: Vec::new(),
+
+            running: Arc::new(AtomicBool::new(true)),
+            heartbeat: Arc::new(Heartbeat::new()),
+            paused: self.paused.clone(),
+            handle: None,
+            started_at: Instant::now(),
+            backoff: Backoff::new(),
+        });
+        self.groups.len() - 1
+    }
+
+    pub fn recorders(&self, index: usize) -> &[Recorder<PRB>] {
+        self.groups.get(index).map(|group| group.recorders.as_slice()).unwrap_or_default()
+    }
+
+    // The recorders that were current at `at`, the oldest kept ones for anything before them
+    pub fn recorders_at(
+        &self,
+        index: usize,
+        at: Instant,
+    ) -> &[Recorder<PRB>] {
+        let Some(group) = self.groups.get(index) else {
+            return &[];
+        };
+        group.previous.iter()
+            .find(|generation| at < generation.ended_at)
+            .map_or(group.recorders.as_slice(), |generation| generation.recorders.as_slice())
+    }
+
+    // When the kept previous generations were replaced, oldest first
+    pub fn switches(&self, index: usize) -> Vec<Instant> {
+        self.groups.get(index).map(|group| group.previous.iter().map(|generation| generation.ended_at).collect()).unwrap_or_default()
+    }
+
+    // Seconds per tier, a missing tier takes the last given length. Shrinking drops old packets right away
+    pub fn set_retention(
+        &mut self,
+        index: usize,
+        tier_secs: &[u32],
+    ) {
+        let Some(group) = self.groups.get(index) else {
+            return;
+        };
+        for (tier, recorder) in group.recorders.iter().enumerate() {
+            if let Some(secs) = tier_secs.get(tier).or(tier_secs.last()) {
+                recorder.set_retention_secs(*secs);
+            }
+        }
+    }
+
+    // Switches to recorders built by `factory`, e.g. with another fps, resolution or encoder.
+    // Their packets go into new ring buffers, saves spanning the switch are split (see recorders_at)
+    pub fn reconfigure(
+        &mut self,
+        index: usize,
+        factory: RecorderFactory<PRB>,
+    ) -> Result<()> {
+        let group = self.groups.get_mut(index).ok_or(CustomError::CUSTOM(Error::NonExistentParameterCombination))?;
+        group.reconfigure(factory)?;
+        debug_println!("Reconfigured {}", group.name);
+        Ok(())
+    }
+
+    pub fn start(&mut self) {
+        for group in self.groups.iter_mut() {
+            group.start();
+        }
+    }
+
+    // Returns a message per recorder that died, got stuck or couldn't be restarted
+    pub fn check(&mut self) -> Vec<String> {
+        self.groups.iter_mut().filter_map(|group| group.check()).collect()
+    }
+
+    // Empties the ring buffers of every recorder and forgets previous generations
+    pub fn clear_buffers(&mut self) {
+        for group in self.groups.iter_mut() {
+            for recorder in group.recorders.iter() {
+                recorder.ring_buffer.lock().unwrap().clear();
+            }
+            group.previous.retain(|generation| generation.handle.is_some());
+            for generation in group.previous.iter() {
+                for recorder in generation.recorders.iter() {
+                    recorder.ring_buffer.lock().unwrap().clear();
+                }
+            }
+        }
+    }
+
+    // Stops every recorder for good, their ring buffers stay readable once the handles are joined
+    pub fn stop(&mut self) -> Vec<RecorderJoinHandle> {
+        self.groups.iter_mut().flat_map(|group| {
+            group.backoff.reset();
+            group.running.store(false, Ordering::Relaxed);
+            let previous_handles: Vec<RecorderJoinHandle> = group.previous.iter_mut().filter_map(|generation| generation.handle.take()).collect();
+            previous_handles.into_iter().chain(group.handle.take())
+        }).collect()
+    }
+}
\ No newline at end of file
@@ -0,0 +1,120 @@
+use serde::Deserialize;
+
+use crate::recorders::audio::sources::enums::AudioCodec;
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct TrackSettings {
+    // Matched case-insensitively against the process name, the device name or the default title ("Main Audio"), '*' matches anything
+    #[serde(rename = "match")]
+    pub pattern: String,
+
+    pub gain_db: f32,
+    pub muted: bool, // track isn't played by default
+    pub title: Option<String>,
+    pub language: Option<String>, // ISO 639-2, e.g. "eng"
+    pub order: i32,
+    pub codec: Option<AudioCodec>, // audio tracks only, None uses the recorder's audio codec
+}
+
+impl TrackSettings {
+    pub fn find<'a>(
+        settings: &'a [TrackSettings],
+        names: &[&str],
+    ) -> Option<&'a TrackSettings> {
+        settings.iter().find(|settings| names.iter().any(|name| glob_matches(&settings.pattern, name)))
+    }
+
+    pub fn gain(&self) -> f32 {
+        10f32.powf(self.gain_db / 20.)
+    }
+}
+
+pub fn gain_of(settings: Option<&TrackSettings>) -> f32 {
+    settings.map_or(1., TrackSettings::gain)
+}
+
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty(); // no '*' at all
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn without_star_the_whole_name_has_to_match() {
+        assert!(glob_matches("Main Audio", "Main Audio"));
+        assert!(!glob_matches("discord", "discord.exe"));
+        assert!(!glob_matches("cord.exe", "discord.exe"));
+        assert!(!glob_matches("", "discord.exe"));
+        assert!(glob_matches("", ""));
+    }
+
+    #[test]
+    fn star_matches_anything() {
+        assert!(glob_matches("*", "anything.exe"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("discord*", "discord.exe"));
+        assert!(glob_matches("*.exe", "discord.exe"));
+        assert!(glob_matches("*cord*", "discord.exe"));
+        assert!(glob_matches("d*c*.exe", "discord.exe"));
+        assert!(glob_matches("discord*", "discord"));
+        assert!(!glob_matches("*.exe", "discord.dll"));
+        assert!(!glob_matches("chrome*", "discord.exe"));
+    }
+
+    #[test]
+    fn star_parts_are_anchored_and_dont_overlap() {
+        // the first part sticks to the start, the last one to the end
+        assert!(!glob_matches("cord*", "discord.exe"));
+        assert!(!glob_matches("*disc", "discord.exe"));
+        // "a" can't be both the start and the end of a single "a"
+        assert!(!glob_matches("a*a", "a"));
+        assert!(glob_matches("a*a", "aa"));
+        assert!(!glob_matches("ab*bc", "abc"));
+        assert!(glob_matches("a*b*c", "a_b_b_c"));
+    }
+
+    #[test]
+    fn case_is_ignored() {
+        assert!(glob_matches("DISCORD*", "Discord.exe"));
+        assert!(glob_matches("main audio", "Main Audio"));
+        assert!(glob_matches("*Microphone*", "microphone (USB Audio)"));
+    }
+
+    #[test]
+    fn first_matching_settings_win() {
+        let settings = [
+            TrackSettings { pattern: "discord*".to_string(), gain_db: -6., ..Default::default() },
+            TrackSettings { pattern: "*".to_string(), gain_db: 3., ..Default::default() },
+        ];
+
+        assert_eq!(TrackSettings::find(&settings, &["game.exe", "Discord.exe"]).map(|settings| settings.gain_db), Some(-6.));
+        assert_eq!(TrackSettings::find(&settings, &["game.exe"]).map(|settings| settings.gain_db), Some(3.));
+        assert_eq!(TrackSettings::find(&settings[..1], &["game.exe"]), None);
+        assert!((gain_of(Some(&settings[0])) - 0.501).abs() < 0.001);
+        assert_eq!(gain_of(None), 1.);
+    }
+}
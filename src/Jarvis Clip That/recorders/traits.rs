@@ -1,29 +1,73 @@
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::AtomicBool;
 use ffmpeg_next::codec;
+use crate::recorders::supervisor::Heartbeat;
 use crate::ring_buffer::traits::PacketRingBuffer;
 use crate::types::{Packet, Result, RecorderJoinHandle};
 
 pub trait TRecorder<PRB: PacketRingBuffer> {
-    fn start_capturing(self: Box<Self>, stop_capturing_callback: Option<Arc<AtomicBool>>) -> RecorderJoinHandle;
+    // `paused` set means reading the source without encoding anything, see Pause
+    fn start_capturing(self: Box<Self>, stop_capturing_callback: Option<Arc<AtomicBool>>, heartbeat: Option<Arc<Heartbeat>>, paused: Option<Arc<AtomicBool>>) -> RecorderJoinHandle;
+}
+
+// What a recorder thread inserts its packets through, taken when the thread starts.
+// A thread the supervisor left behind keeps its writer, its ring buffer retires it, so late packets with the old pts
+// don't end up between the ones of the recorder that replaced it
+pub struct RingBufferWriter<PRB: PacketRingBuffer> {
+    ring_buffer: Arc<Mutex<PRB>>,
+    writer: u64,
+}
+
+impl<PRB: PacketRingBuffer> RingBufferWriter<PRB> {
+    pub fn new(ring_buffer: &Arc<Mutex<PRB>>) -> Self {
+        let writer = ring_buffer.lock().unwrap().writer();
+        Self {
+            ring_buffer: ring_buffer.clone(),
+            writer,
+        }
+    }
+
+    // Moves the encoder's packets into the ring buffer, the first one stands for `duration`, the others for `next_duration`
+    fn receive_packets(
+        &self,
+        encoder: &mut codec::encoder::Encoder,
+        mut duration: i64,
+        next_duration: i64,
+    ) {
+        let mut packet = Packet::empty();
+        let mut ring_buffer = self.ring_buffer.lock().unwrap();
+        // checked under the lock, once a restart retired the writer nothing of this thread gets in anymore
+        let current = ring_buffer.writer() == self.writer;
+        while encoder.receive_packet(&mut packet).is_ok() {
+            if current {
+                let mut packet_clone = packet.clone();
+                packet_clone.set_duration(duration);
+                ring_buffer.insert(packet_clone);
+            }
+            duration = next_duration;
+        }
+    }
 }
 
 pub fn send_frame_and_receive_packets<PRB: PacketRingBuffer>(
-    ring_buffer: &Arc<Mutex<PRB>>,
+    ring_buffer: &RingBufferWriter<PRB>,
     encoder: &mut codec::encoder::Encoder,
     frame: &ffmpeg_next::Frame,
-    mut duration: i64,
+    duration: i64,
 ) -> Result<()> {
     encoder.send_frame(frame)?;
+    ring_buffer.receive_packets(encoder, duration, 0);
+    Ok(())
+}
 
-    let mut packet = Packet::empty();
-    let mut ring_buffer = ring_buffer.lock().unwrap();
-    while encoder.receive_packet(&mut packet).is_ok() {
-        let mut packet_clone = packet.clone();
-        packet_clone.set_duration(duration);
-        ring_buffer.insert(packet_clone);
-        duration = 0;
-    }
-    drop(ring_buffer);
+// Signals the end of the stream and moves the encoder's remaining packets into the ring buffer,
+// every one of them stands for `duration` (one frame of the encoder)
+pub fn drain_encoder<PRB: PacketRingBuffer>(
+    ring_buffer: &RingBufferWriter<PRB>,
+    encoder: &mut codec::encoder::Encoder,
+    duration: i64,
+) -> Result<()> {
+    encoder.send_eof()?;
+    ring_buffer.receive_packets(encoder, duration, duration);
     Ok(())
 }
\ No newline at end of file
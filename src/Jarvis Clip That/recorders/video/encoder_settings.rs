@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use ffmpeg_next::codec::encoder::video::Video;
+use ffmpeg_next::codec::Flags;
+use ffmpeg_next::Dictionary;
+use serde::Deserialize;
+
+use crate::recorders::video::sources::enums::VideoCodec;
+
+const FF_QP2LAMBDA: i32 = 118; // libavutil/avutil.h
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityPreset {
+    Low,
+    #[default]
+    Balanced,
+    Archival,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateControl {
+    Cbr,
+    Vbr,
+    Cqp,
+    Crf, // hardware encoders use their closest constant quality mode
+}
+
+// Everything left at None comes from `preset`
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct VideoEncoderSettings {
+    pub preset: QualityPreset,
+    pub rate_control: Option<RateControl>,
+    pub bitrate_kbps: Option<u32>,
+    pub max_bitrate_kbps: Option<u32>, // VBR only, CBR uses bitrate_kbps
+    pub quality: Option<u32>, // the qp for CQP, the crf/cq value for CRF
+    pub gop_secs: Option<f32>, // keyframe interval, clips can only start on a keyframe
+    pub b_frames: Option<u32>,
+    pub profile: Option<String>,
+    pub encoder_preset: Option<String>, // the encoder's own speed preset, e.g. "veryfast" for x264 or "p4" for NVENC
+    pub options: HashMap<String, String>, // private encoder options, passed as-is and applied last
+}
+
+struct PresetDefaults {
+    rate_control: RateControl,
+    bitrate_kbps: u32,
+    max_bitrate_kbps: u32,
+    quality: u32,
+    gop_secs: f32,
+    b_frames: u32,
+}
+
+impl QualityPreset {
+    fn defaults(&self) -> PresetDefaults {
+        match self {
+            QualityPreset::Low => PresetDefaults {
+                rate_control: RateControl::Vbr,
+                bitrate_kbps: 4_000,
+                max_bitrate_kbps: 6_000,
+                quality: 28,
+                gop_secs: 2.,
+                b_frames: 0,
+            },
+            QualityPreset::Balanced => PresetDefaults {
+                rate_control: RateControl::Vbr,
+                bitrate_kbps: 8_000,
+                max_bitrate_kbps: 10_000,
+                quality: 23,
+                gop_secs: 1.,
+                b_frames: 0,
+            },
+            QualityPreset::Archival => PresetDefaults {
+                rate_control: RateControl::Crf,
+                bitrate_kbps: 40_000,
+                max_bitrate_kbps: 60_000,
+                quality: 18,
+                gop_secs: 1.,
+                b_frames: 2,
+            },
+        }
+    }
+
+    // Speed presets of the encoders themselves, faster ones for the lower tiers since they run next to a game
+    fn encoder_preset(&self, video_codec: &VideoCodec) -> &'static str {
+        match (video_codec, self) {
+            (VideoCodec::Amf, QualityPreset::Low) => "speed",
+            (VideoCodec::Amf, QualityPreset::Balanced) => "balanced",
+            (VideoCodec::Amf, QualityPreset::Archival) => "quality",
+            (VideoCodec::Nvenc, QualityPreset::Low) => "p2",
+            (VideoCodec::Nvenc, QualityPreset::Balanced) => "p4",
+            (VideoCodec::Nvenc, QualityPreset::Archival) => "p6",
+            (VideoCodec::Qsv, QualityPreset::Low) => "veryfast",
+            (VideoCodec::Qsv, QualityPreset::Balanced) => "medium",
+            (VideoCodec::Qsv, QualityPreset::Archival) => "slower",
+            (VideoCodec::X264, QualityPreset::Low) => "ultrafast",
+            (VideoCodec::X264, QualityPreset::Balanced) => "veryfast",
+            (VideoCodec::X264, QualityPreset::Archival) => "medium",
+            (VideoCodec::X265, QualityPreset::Low) => "ultrafast",
+            (VideoCodec::X265, QualityPreset::Balanced) => "veryfast",
+            (VideoCodec::X265, QualityPreset::Archival) => "fast",
+            (VideoCodec::SvtAv1, QualityPreset::Low) => "12",
+            (VideoCodec::SvtAv1, QualityPreset::Balanced) => "10",
+            (VideoCodec::SvtAv1, QualityPreset::Archival) => "6",
+        }
+    }
+}
+
+impl VideoEncoderSettings {
+    pub fn gop_frames(&self, fps: i32) -> u32 {
+        let gop_secs = self.gop_secs.unwrap_or(self.preset.defaults().gop_secs);
+        ((gop_secs * fps as f32).round() as u32).max(1)
+    }
+
+    // Sets the generic fields on `enc` and returns the private options to open it with
+    pub fn apply(
+        &self,
+        enc: &mut Video,
+        video_codec: &VideoCodec,
+        fps: i32,
+    ) -> Dictionary<'static> {
+        let defaults = self.preset.defaults();
+        let rate_control = self.rate_control.unwrap_or(defaults.rate_control);
+        let bitrate = self.bitrate_kbps.unwrap_or(defaults.bitrate_kbps) as usize * 1000;
+        let max_bitrate = self.max_bitrate_kbps.unwrap_or(defaults.max_bitrate_kbps) as usize * 1000;
+        let quality = self.quality.unwrap_or(defaults.quality).to_string();
+
+        enc.set_gop(self.gop_frames(fps));
+        enc.set_max_b_frames(self.b_frames.unwrap_or(defaults.b_frames) as usize);
+
+        let mut options = Dictionary::new();
+        let preset_key = match video_codec {
+            VideoCodec::Amf => { "quality" }
+            _ => { "preset" }
+        };
+        options.set(preset_key, self.encoder_preset.as_deref().unwrap_or(self.preset.encoder_preset(video_codec)));
+        if let Some(profile) = &self.profile {
+            options.set("profile", profile);
+        }
+
+        match rate_control {
+            RateControl::Cbr => {
+                enc.set_bit_rate(bitrate);
+                enc.set_max_bit_rate(bitrate);
+                unsafe { (*enc.as_mut_ptr()).rc_buffer_size = bitrate as i32; } // one second of VBV
+            }
+            RateControl::Vbr => {
+                enc.set_bit_rate(bitrate);
+                enc.set_max_bit_rate(max_bitrate.max(bitrate));
+                unsafe { (*enc.as_mut_ptr()).rc_buffer_size = max_bitrate.max(bitrate) as i32; }
+            }
+            RateControl::Cqp | RateControl::Crf => {}
+        }
+
+        match (video_codec, rate_control) {
+            (VideoCodec::Amf, RateControl::Cbr) => { options.set("rc", "cbr"); }
+            (VideoCodec::Amf, RateControl::Vbr) => { options.set("rc", "vbr_peak"); }
+            (VideoCodec::Amf, RateControl::Cqp | RateControl::Crf) => {
+                options.set("rc", "cqp");
+                options.set("qp_i", &quality);
+                options.set("qp_p", &quality);
+                options.set("qp_b", &quality);
+            }
+            (VideoCodec::Nvenc, RateControl::Cbr) => { options.set("rc", "cbr"); }
+            (VideoCodec::Nvenc, RateControl::Vbr) => { options.set("rc", "vbr"); }
+            (VideoCodec::Nvenc, RateControl::Cqp) => {
+                options.set("rc", "constqp");
+                options.set("qp", &quality);
+            }
+            (VideoCodec::Nvenc, RateControl::Crf) => {
+                options.set("rc", "vbr");
+                options.set("cq", &quality);
+            }
+            // QSV picks its mode from the generic fields: maxrate == bitrate is CBR, QSCALE is CQP, global_quality alone is ICQ
+            (VideoCodec::Qsv, RateControl::Cbr | RateControl::Vbr) => {}
+            (VideoCodec::Qsv, RateControl::Cqp) => {
+                // ffmpeg-next has no getter for the flags, so QSCALE is added to the ones the caller set (GLOBAL_HEADER) on the raw context
+                unsafe { (*enc.as_mut_ptr()).flags |= Flags::QSCALE.bits() as i32; }
+                enc.set_global_quality(self.quality.unwrap_or(defaults.quality) as i32 * FF_QP2LAMBDA);
+            }
+            (VideoCodec::Qsv, RateControl::Crf) => {
+                enc.set_global_quality(self.quality.unwrap_or(defaults.quality) as i32);
+            }
+            (VideoCodec::X264, RateControl::Cbr) => { options.set("nal-hrd", "cbr"); }
+            (VideoCodec::X264 | VideoCodec::X265, RateControl::Vbr) => {}
+            (VideoCodec::X265, RateControl::Cbr) => { options.set("x265-params", "strict-cbr=1"); }
+            (VideoCodec::X264 | VideoCodec::X265 | VideoCodec::SvtAv1, RateControl::Cqp) => { options.set("qp", &quality); }
+            (VideoCodec::X264 | VideoCodec::X265 | VideoCodec::SvtAv1, RateControl::Crf) => { options.set("crf", &quality); }
+            (VideoCodec::SvtAv1, RateControl::Cbr) => { options.set("svtav1-params", "rc=2"); }
+            (VideoCodec::SvtAv1, RateControl::Vbr) => { options.set("svtav1-params", "rc=1"); }
+        }
+
+        for (key, value) in &self.options {
+            options.set(key, value);
+        }
+        options
+    }
+}
\ No newline at end of file
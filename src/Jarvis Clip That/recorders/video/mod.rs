@@ -1,2 +1,4 @@
 pub mod video_recorder;
-pub mod sources;
\ No newline at end of file
+pub mod sources;
+pub mod encoder_settings;
+pub mod transform;
\ No newline at end of file
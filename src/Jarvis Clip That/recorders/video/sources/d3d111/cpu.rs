@@ -0,0 +1,206 @@
+use ffmpeg_next::ffi::{av_frame_make_writable, AVBufferRef, AVFrame, sws_scale};
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::software::scaling::{Context, Flags};
+use windows::Win32::Graphics::Direct3D11::{D3D11_CPU_ACCESS_READ, D3D11_MAP_READ, D3D11_MAPPED_SUBRESOURCE, D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING, ID3D11Device, ID3D11Texture2D};
+
+use crate::error::{CustomError, Error};
+use crate::recorders::video::sources::d3d111::traits::D3d11EncoderHwContext;
+use crate::recorders::video::transform::{Rect, VideoTransformSettings};
+use crate::types::Result;
+use crate::wrappers::{MaybeSafeFFIPtrWrapper, MaybeSafeScalerWrapper};
+
+// Copies the NV12 texture back into system memory for encoders that don't take D3D11 frames (software encoders, QSV).
+// NV12 at the frame's size goes straight into the frame, anything else (yuv420p for x265/SVT-AV1, crop, scaling) goes through swscale
+pub struct CpuUploadAdapter {
+    format: Pixel,
+    transform: Option<VideoTransformSettings>, // None if the texture already is what the encoder wants
+
+    staging: Option<ID3D11Texture2D>,
+    nv12_frame: Option<ffmpeg_next::frame::Video>,
+    scaler: Option<(MaybeSafeScalerWrapper, [u32; 4])>, // with the source and destination size it was made for
+}
+
+impl CpuUploadAdapter {
+    pub fn new(
+        format: Pixel,
+        transform: Option<VideoTransformSettings>,
+    ) -> Result<Self> {
+        match format {
+            Pixel::NV12 | Pixel::YUV420P => {}
+            _ => { return Err(Error::NonExistentParameterCombination.into()); }
+        }
+
+        Ok(Self {
+            format,
+            transform,
+
+            staging: None,
+            nv12_frame: None,
+            scaler: None,
+        })
+    }
+}
+
+impl D3d11EncoderHwContext for CpuUploadAdapter {
+    fn setup_hw_and_frame_ctx(
+        &self,
+        _device: &ID3D11Device,
+        _width: i32,
+        _height: i32,
+    ) -> Result<(Option<*mut AVBufferRef>, *mut AVBufferRef)> {
+        // the encoder gets plain system memory frames, see create_encoder_software
+        Err(Error::NonExistentParameterCombination.into())
+    }
+
+    fn prepare_frame(
+        &mut self,
+        av_frame: &MaybeSafeFFIPtrWrapper<AVFrame>,
+        texture: &ID3D11Texture2D,
+    ) -> Result<()> {
+        unsafe {
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            texture.GetDesc(&mut desc);
+            let device = texture.GetDevice()?;
+            let context = device.GetImmediateContext()?;
+
+            let (out_width, out_height) = ((***av_frame).width as u32, (***av_frame).height as u32);
+            let (source, destination) = match &self.transform {
+                Some(transform) => {
+                    let source = transform.source_rect(desc.Width, desc.Height);
+                    (source, transform.destination_rect(&source, out_width, out_height))
+                }
+                None => { (Rect::full(desc.Width, desc.Height), Rect::full(out_width, out_height)) }
+            };
+            let direct = self.format == Pixel::NV12
+                && (desc.Width, desc.Height) == (out_width, out_height)
+                && source == Rect::full(desc.Width, desc.Height)
+                && destination == Rect::full(out_width, out_height);
+
+            if self.nv12_frame.as_ref().is_some_and(|frame| (frame.width(), frame.height()) != (desc.Width, desc.Height)) {
+                // the monitor's mode changed
+                self.staging = None;
+                self.nv12_frame = None;
+            }
+            if self.staging.is_none() {
+                let staging_desc = D3D11_TEXTURE2D_DESC {
+                    Usage: D3D11_USAGE_STAGING,
+                    BindFlags: 0,
+                    CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+                    MiscFlags: 0,
+                    ..desc
+                };
+                let mut staging = None;
+                device.CreateTexture2D(&staging_desc, None, Some(&mut staging))?;
+                self.staging = staging;
+            }
+            let staging = self.staging.as_ref().ok_or(CustomError::CUSTOM(Error::Unknown))?;
+
+            context.CopyResource(staging, texture);
+
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            context.Map(staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+
+            // the encoder may still hold a reference to the last frame's buffers
+            if av_frame_make_writable(**av_frame) < 0 {
+                context.Unmap(staging, 0);
+                return Err(CustomError::CUSTOM(Error::Unknown));
+            }
+
+            let nv12_frame = match direct {
+                true => { **av_frame }
+                false => {
+                    let nv12_frame = self.nv12_frame.get_or_insert_with(|| ffmpeg_next::frame::Video::new(Pixel::NV12, desc.Width, desc.Height));
+                    nv12_frame.as_mut_ptr()
+                }
+            };
+            copy_nv12(mapped.pData as *const u8, mapped.RowPitch as usize, desc.Width as usize, desc.Height as usize, nv12_frame);
+
+            context.Unmap(staging, 0);
+
+            if direct {
+                return Ok(());
+            }
+
+            let sizes = [source.width, source.height, destination.width, destination.height];
+            if self.scaler.as_ref().map(|(_, scaler_sizes)| *scaler_sizes) != Some(sizes) {
+                let scaler = Context::get(Pixel::NV12, source.width, source.height, self.format, destination.width, destination.height, Flags::BILINEAR)?;
+                self.scaler = Some((MaybeSafeScalerWrapper(scaler), sizes));
+            }
+            let (scaler, _) = self.scaler.as_mut().ok_or(CustomError::CUSTOM(Error::Unknown))?;
+
+            if destination != Rect::full(out_width, out_height) {
+                fill_black(**av_frame, self.format, out_height as usize);
+            }
+
+            let src = &*nv12_frame;
+            let src_planes = plane_pointers(src, Pixel::NV12, &source);
+            let dst_planes = plane_pointers(&***av_frame, self.format, &destination);
+            let ret = sws_scale(
+                scaler.as_mut_ptr(),
+                src_planes.as_ptr() as *const *const u8,
+                src.linesize.as_ptr(),
+                0,
+                source.height as i32,
+                dst_planes.as_ptr(),
+                (***av_frame).linesize.as_ptr(),
+            );
+            if ret < 0 {
+                return Err(CustomError::CUSTOM(Error::Unknown));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// A mapped NV12 texture is the Y plane followed by the interleaved UV plane, both `pitch` bytes per row
+unsafe fn copy_nv12(
+    src: *const u8,
+    pitch: usize,
+    width: usize,
+    height: usize,
+    dst: *mut AVFrame,
+) {
+    let planes = [(0, height), (1, height / 2)];
+    let mut src_row = src;
+    for (plane, rows) in planes {
+        let dst_plane = (*dst).data[plane];
+        let dst_linesize = (*dst).linesize[plane] as usize;
+        if dst_plane.is_null() {
+            return;
+        }
+        for row in 0..rows {
+            std::ptr::copy_nonoverlapping(src_row, dst_plane.add(row * dst_linesize), width);
+            src_row = src_row.add(pitch);
+        }
+    }
+}
+
+// Plane pointers moved to the top left corner of `rect`, chroma is subsampled 2x2 in both formats
+unsafe fn plane_pointers(
+    frame: &AVFrame,
+    format: Pixel,
+    rect: &Rect,
+) -> [*mut u8; 4] {
+    let (x, y) = (rect.x as usize, rect.y as usize);
+    let offset = |plane: usize, x: usize, y: usize| frame.data[plane].add(y * frame.linesize[plane] as usize + x);
+    match format {
+        Pixel::NV12 => [offset(0, x, y), offset(1, x, y / 2), std::ptr::null_mut(), std::ptr::null_mut()],
+        _ => [offset(0, x, y), offset(1, x / 2, y / 2), offset(2, x / 2, y / 2), std::ptr::null_mut()],
+    }
+}
+
+// Black in limited range: Y = 16, Cb = Cr = 128
+unsafe fn fill_black(
+    frame: *mut AVFrame,
+    format: Pixel,
+    height: usize,
+) {
+    let planes = match format {
+        Pixel::NV12 => vec![(0, height, 16), (1, height / 2, 128)],
+        _ => vec![(0, height, 16), (1, height / 2, 128), (2, height / 2, 128)],
+    };
+    for (plane, rows, value) in planes {
+        std::ptr::write_bytes((*frame).data[plane], value, rows * (*frame).linesize[plane] as usize);
+    }
+}
@@ -39,16 +39,17 @@ impl D3d11EncoderHwContext for D3d11vaAdapter {
         // Initialize the context
         let ret = unsafe { av_hwdevice_ctx_init(hw_device_ctx) };
 
-        if ret < 0 as _ {
-            unsafe {
-                av_buffer_unref(&mut hw_device_ctx);
-                panic!("Failed to initialize HW device context");
-            }
+        if ret < 0 as _ { //"Failed to initialize HW device context"
+            unsafe { av_buffer_unref(&mut hw_device_ctx); }
+            return Err(CustomError::CUSTOM(Error::Unknown));
         }
 
 
-        let hw_frame_ctx: *mut AVBufferRef = unsafe { av_hwframe_ctx_alloc(hw_device_ctx) };
-        if hw_frame_ctx.is_null() { panic!("alloc failed"); }
+        let mut hw_frame_ctx: *mut AVBufferRef = unsafe { av_hwframe_ctx_alloc(hw_device_ctx) };
+        if hw_frame_ctx.is_null() { //"alloc failed"
+            unsafe { av_buffer_unref(&mut hw_device_ctx); }
+            return Err(CustomError::CUSTOM(Error::Unknown));
+        }
 
         let frames_ctx = unsafe { &mut *((*hw_frame_ctx).data as *mut AVHWFramesContext) };
         frames_ctx.format = AVPixelFormat::AV_PIX_FMT_D3D11;
@@ -58,13 +59,19 @@ impl D3d11EncoderHwContext for D3d11vaAdapter {
         frames_ctx.initial_pool_size = 0;
 
         let ret = unsafe { av_hwframe_ctx_init(hw_frame_ctx) };
-        if ret < 0 { panic!("init failed"); }
+        if ret < 0 { //"init failed"
+            unsafe {
+                av_buffer_unref(&mut hw_frame_ctx);
+                av_buffer_unref(&mut hw_device_ctx);
+            }
+            return Err(CustomError::CUSTOM(Error::Unknown));
+        }
 
         Ok((Some(hw_device_ctx), hw_frame_ctx))
     }
 
     fn prepare_frame(
-        &self,
+        &mut self,
         av_frame: &MaybeSafeFFIPtrWrapper<AVFrame>,
         texture: &ID3D11Texture2D,
     ) -> Result<()> {
@@ -1,4 +1,5 @@
 pub mod traits;
 pub mod source;
 pub mod d3d11av;
-pub mod qsv;
\ No newline at end of file
+pub mod qsv;
+pub mod cpu;
\ No newline at end of file
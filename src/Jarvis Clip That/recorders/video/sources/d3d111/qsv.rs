@@ -82,7 +82,7 @@ impl D3d11EncoderHwContext for QsvAdapter {
     }
 
     fn prepare_frame(
-        &self,
+        &mut self,
         _av_frame: &MaybeSafeFFIPtrWrapper<AVFrame>,
         _texture: &ID3D11Texture2D,
     ) -> Result<()> {
@@ -3,15 +3,16 @@ use std::ptr::null_mut;
 
 use ffmpeg_next::ffi::AVFrame;
 use windows::core::Interface;
-use windows::Win32::Foundation::{HMODULE, TRUE};
+use windows::Win32::Foundation::{HMODULE, RECT, TRUE};
 use windows::Win32::Graphics::Direct3D::{D3D_DRIVER_TYPE_HARDWARE, D3D_FEATURE_LEVEL_10_0, D3D_FEATURE_LEVEL_11_0};
-use windows::Win32::Graphics::Direct3D11::{D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_SDK_VERSION, D3D11_TEX2D_VPIV, D3D11_TEX2D_VPOV, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_VIDEO_FRAME_FORMAT_PROGRESSIVE, D3D11_VIDEO_PROCESSOR_CONTENT_DESC, D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC, D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC_0, D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC, D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC_0, D3D11_VIDEO_PROCESSOR_STREAM, D3D11_VPIV_DIMENSION_TEXTURE2D, D3D11_VPOV_DIMENSION_TEXTURE2D, D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, ID3D11VideoContext, ID3D11VideoDevice, ID3D11VideoProcessor, ID3D11VideoProcessorEnumerator, ID3D11VideoProcessorInputView, ID3D11VideoProcessorOutputView};
+use windows::Win32::Graphics::Direct3D11::{D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_SDK_VERSION, D3D11_TEX2D_VPIV, D3D11_TEX2D_VPOV, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_VIDEO_COLOR, D3D11_VIDEO_COLOR_0, D3D11_VIDEO_COLOR_RGBA, D3D11_VIDEO_FRAME_FORMAT_PROGRESSIVE, D3D11_VIDEO_PROCESSOR_CONTENT_DESC, D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC, D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC_0, D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC, D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC_0, D3D11_VIDEO_PROCESSOR_STREAM, D3D11_VPIV_DIMENSION_TEXTURE2D, D3D11_VPOV_DIMENSION_TEXTURE2D, D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, ID3D11VideoContext, ID3D11VideoDevice, ID3D11VideoProcessor, ID3D11VideoProcessorEnumerator, ID3D11VideoProcessorInputView, ID3D11VideoProcessorOutputView};
 use windows::Win32::Graphics::Dxgi::{DXGI_OUTDUPL_DESC, DXGI_OUTDUPL_FRAME_INFO, IDXGIAdapter, IDXGIDevice, IDXGIOutput, IDXGIOutput1, IDXGIOutputDuplication, IDXGIResource};
 use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_NV12, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC};
 
 use crate::error::{CustomError, Error};
 use crate::recorders::video::sources::d3d111::traits::D3d11EncoderHwContext;
 use crate::recorders::video::sources::traits::VideoSource;
+use crate::recorders::video::transform::{Rect, VideoTransformSettings};
 use crate::types::Result;
 use crate::wrappers::MaybeSafeFFIPtrWrapper;
 
@@ -26,6 +27,7 @@ pub struct VideoSourceD3d11<E: D3d11EncoderHwContext> {
     nv12_tex: ID3D11Texture2D,
 
     in_desc: DXGI_OUTDUPL_DESC,
+    gpu_transform: Option<VideoTransformSettings>, // None hands the whole monitor at its own size to encoder_hw_ctx
 
     pub encoder_hw_ctx: E,
 }
@@ -34,6 +36,7 @@ impl<E: D3d11EncoderHwContext> VideoSourceD3d11<E> {
     pub fn new(
         monitor: u32,
         encoder_hw_ctx: E,
+        gpu_transform: Option<VideoTransformSettings>,
     ) -> Result<Self> {
         let (device, context, duplication) = create_id3d11(monitor)?;
 
@@ -77,6 +80,7 @@ impl<E: D3d11EncoderHwContext> VideoSourceD3d11<E> {
             nv12_tex,
 
             in_desc,
+            gpu_transform,
 
             encoder_hw_ctx,
         })
@@ -88,7 +92,7 @@ impl<E: D3d11EncoderHwContext> VideoSource for VideoSourceD3d11<E> {
 
     fn get_frame(
         &mut self,
-        av_frame: &MaybeSafeFFIPtrWrapper<AVFrame>,
+        av_frames: &[&MaybeSafeFFIPtrWrapper<AVFrame>],
         out_width: u32,
         out_height: u32,
     ) -> Result<()> {
@@ -106,11 +110,22 @@ impl<E: D3d11EncoderHwContext> VideoSource for VideoSourceD3d11<E> {
             if self.frame_info.AccumulatedFrames != 0 {
                 self.device_tex = dxgi_resource.cast()?;
 
+                let (in_width, in_height) = (self.in_desc.ModeDesc.Width, self.in_desc.ModeDesc.Height);
+                let (out_width, out_height, source, destination) = match &self.gpu_transform {
+                    Some(transform) => {
+                        let source = transform.source_rect(in_width, in_height);
+                        (out_width, out_height, source, transform.destination_rect(&source, out_width, out_height))
+                    }
+                    None => { (in_width, in_height, Rect::full(in_width, in_height), Rect::full(in_width, in_height)) }
+                };
+
                 self.nv12_tex = unsafe {
-                    convert_rgba_to_nv12(&self.device, &self.context, &self.device_tex, self.in_desc.ModeDesc.Width, self.in_desc.ModeDesc.Height, out_width, out_height)?
+                    convert_rgba_to_nv12(&self.device, &self.context, &self.device_tex, in_width, in_height, out_width, out_height, &source, &destination)?
                 };
 
-                self.encoder_hw_ctx.prepare_frame(av_frame, &self.nv12_tex)?;
+                for av_frame in av_frames {
+                    self.encoder_hw_ctx.prepare_frame(av_frame, &self.nv12_tex)?;
+                }
             }
         }
 
@@ -121,7 +136,42 @@ impl<E: D3d11EncoderHwContext> VideoSource for VideoSourceD3d11<E> {
 }
 
 
-fn create_id3d11(monitor: u32) -> Result<(ID3D11Device, ID3D11DeviceContext, IDXGIOutputDuplication)> {
+// What --list-sources shows, `id` is the monitor_id of the d3d11 source
+pub struct MonitorInfo {
+    pub id: u32,
+    pub name: String, // e.g. \\.\DISPLAY1
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// The monitors of the adapter create_id3d11 captures from, in monitor_id order
+pub fn list_monitors() -> Result<Vec<MonitorInfo>> {
+    let (device, _) = create_device()?;
+    let dxgi_device: IDXGIDevice = device.cast()?;
+    let adapter = unsafe { dxgi_device.GetAdapter()? };
+
+    let mut monitors = Vec::new();
+    let mut id = 0;
+    while let Ok(output) = unsafe { adapter.EnumOutputs(id) } {
+        let desc = unsafe { output.GetDesc()? };
+        let name_len = desc.DeviceName.iter().position(|c| *c == 0).unwrap_or(desc.DeviceName.len());
+        let RECT { left, top, right, bottom } = desc.DesktopCoordinates;
+        monitors.push(MonitorInfo {
+            id,
+            name: String::from_utf16_lossy(&desc.DeviceName[..name_len]),
+            x: left,
+            y: top,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+        });
+        id += 1;
+    }
+    Ok(monitors)
+}
+
+fn create_device() -> Result<(ID3D11Device, ID3D11DeviceContext)> {
     let mut device: Option<ID3D11Device> = None;
     let mut context: Option<ID3D11DeviceContext> = None;
     unsafe {
@@ -139,6 +189,11 @@ fn create_id3d11(monitor: u32) -> Result<(ID3D11Device, ID3D11DeviceContext, IDX
     }
     let device: ID3D11Device = device.ok_or(Error::Unknown)?;
     let context: ID3D11DeviceContext = context.ok_or(Error::Unknown)?;
+    Ok((device, context))
+}
+
+fn create_id3d11(monitor: u32) -> Result<(ID3D11Device, ID3D11DeviceContext, IDXGIOutputDuplication)> {
+    let (device, context) = create_device()?;
 
     let adapter: IDXGIAdapter;
     let output: IDXGIOutput;
@@ -164,6 +219,8 @@ unsafe fn convert_rgba_to_nv12(
     in_height: u32,
     out_width: u32,
     out_height: u32,
+    source: &Rect,
+    destination: &Rect,
 ) -> Result<ID3D11Texture2D> {
     // 1) QI for ID3D11VideoDevice
     let video_dev: ID3D11VideoDevice = device.cast()?;
@@ -189,6 +246,22 @@ unsafe fn convert_rgba_to_nv12(
     // 3) Create the VideoProcessor itself
     let vp: ID3D11VideoProcessor = video_dev.CreateVideoProcessor(&vp_enum, 0)?;
 
+    // crop, scale and letterbox, the background fills whatever `destination` leaves uncovered
+    let rect = |rect: &Rect| RECT {
+        left: rect.x as i32,
+        top: rect.y as i32,
+        right: (rect.x + rect.width) as i32,
+        bottom: (rect.y + rect.height) as i32,
+    };
+    video_ctx.VideoProcessorSetStreamSourceRect(&vp, 0, true, Some(&rect(source) as *const RECT));
+    video_ctx.VideoProcessorSetStreamDestRect(&vp, 0, true, Some(&rect(destination) as *const RECT));
+    let black = D3D11_VIDEO_COLOR {
+        Anonymous: D3D11_VIDEO_COLOR_0 {
+            RGBA: D3D11_VIDEO_COLOR_RGBA { R: 0., G: 0., B: 0., A: 1. },
+        },
+    };
+    video_ctx.VideoProcessorSetOutputBackgroundColor(&vp, false, &black);
+
     // 4) Make the NV12 output texture
     let nv12_desc = D3D11_TEXTURE2D_DESC {
         Width: out_width,
@@ -1,16 +1,19 @@
-use ffmpeg_next::Codec;
+use ffmpeg_next::{Codec, Dictionary};
 use ffmpeg_next::codec::encoder::video::Video;
 use ffmpeg_next::codec::Flags;
 use ffmpeg_next::encoder::video::Encoder;
+use ffmpeg_next::format::Pixel;
 use ffmpeg_next::sys::{av_buffer_ref, AVBufferRef, AVFrame};
 use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D};
 use crate::error::{CustomError, Error};
+use crate::recorders::video::encoder_settings::VideoEncoderSettings;
+use crate::recorders::video::sources::enums::VideoCodec;
 use crate::wrappers::MaybeSafeFFIPtrWrapper;
 use crate::types::Result;
 
 pub trait D3d11EncoderHwContext {
     fn setup_hw_and_frame_ctx(&self, device: &ID3D11Device, width: i32, height: i32) -> Result<(Option<*mut AVBufferRef>, *mut AVBufferRef)>;
-    fn prepare_frame(&self, av_frame: &MaybeSafeFFIPtrWrapper<AVFrame>, texture: &ID3D11Texture2D) -> Result<()>;
+    fn prepare_frame(&mut self, av_frame: &MaybeSafeFFIPtrWrapper<AVFrame>, texture: &ID3D11Texture2D) -> Result<()>;
 }
 
 pub fn create_encoder_d3d11(
@@ -20,6 +23,8 @@ pub fn create_encoder_d3d11(
     width: u32,
     height: u32,
     fps: i32,
+    video_codec: &VideoCodec,
+    settings: &VideoEncoderSettings,
 ) -> Result<Encoder> {
     let raw_ctx = unsafe { enc.as_mut_ptr() };
     if raw_ctx.is_null() {
@@ -39,16 +44,43 @@ pub fn create_encoder_d3d11(
         }
         (*raw_ctx).hw_frames_ctx = hw_frames_ctx;
     }
+    let options = configure_encoder(&mut enc, ffmpeg_next::format::Pixel::D3D11, width, height, fps, video_codec, settings);
+
+    let video_encoder = enc.open_as_with(codec, options)?;
+    Ok(video_encoder)
+}
+
+// For encoders fed through CpuUploadAdapter
+pub fn create_encoder_software(
+    mut enc: Video,
+    codec: Codec,
+    format: Pixel,
+    width: u32,
+    height: u32,
+    fps: i32,
+    video_codec: &VideoCodec,
+    settings: &VideoEncoderSettings,
+) -> Result<Encoder> {
+    let options = configure_encoder(&mut enc, format, width, height, fps, video_codec, settings);
+
+    let video_encoder = enc.open_as_with(codec, options)?;
+    Ok(video_encoder)
+}
+
+fn configure_encoder(
+    enc: &mut Video,
+    format: Pixel,
+    width: u32,
+    height: u32,
+    fps: i32,
+    video_codec: &VideoCodec,
+    settings: &VideoEncoderSettings,
+) -> Dictionary<'static> {
     enc.set_width(width);
     enc.set_height(height);
-    enc.set_format(ffmpeg_next::format::Pixel::D3D11);
+    enc.set_format(format);
     enc.set_time_base((1, fps));
     enc.set_frame_rate(Some((fps, 1)));
-    enc.set_bit_rate(8_000_000);
-    enc.set_max_bit_rate(10_000_000);
     enc.set_flags(Flags::GLOBAL_HEADER);
-    enc.set_gop(fps as u32); // Keyframe interval (1 second)
-
-    let video_encoder = enc.open_as(codec)?;
-    Ok(video_encoder)
+    settings.apply(enc, video_codec, fps)
 }
\ No newline at end of file
@@ -1,8 +1,36 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
 pub enum VideoCodec {
     Amf, // AMD
     Qsv, // Intel
+    Nvenc, // NVIDIA
+    X264, // software from here on
+    X265,
+    SvtAv1,
+}
+
+impl VideoCodec {
+    pub fn encoder_name(&self) -> &'static str {
+        match self {
+            VideoCodec::Amf => "hevc_amf",
+            VideoCodec::Qsv => "hevc_qsv",
+            VideoCodec::Nvenc => "hevc_nvenc",
+            VideoCodec::X264 => "libx264",
+            VideoCodec::X265 => "libx265",
+            VideoCodec::SvtAv1 => "libsvtav1",
+        }
+    }
+}
+
+// Hardware first, x264 last so machines without a usable GPU encoder still record
+pub fn default_video_codecs() -> Vec<VideoCodec> {
+    vec![VideoCodec::Amf, VideoCodec::Nvenc, VideoCodec::Qsv, VideoCodec::X264]
 }
 
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum VideoSourceType {
     D3d11 {monitor_id: u32},
 }
\ No newline at end of file
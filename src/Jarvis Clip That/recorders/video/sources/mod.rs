@@ -1,3 +1,4 @@
 pub mod d3d111;
 pub mod traits;
-pub mod enums;
\ No newline at end of file
+pub mod enums;
+pub mod settings;
\ No newline at end of file
@@ -0,0 +1,35 @@
+use serde::Deserialize;
+
+use crate::recorders::video::sources::enums::VideoSourceType;
+
+// One entry per video recorder, each one gets its own encoder, ring buffer and video stream in the clip
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct VideoSourceSettings {
+    #[serde(flatten)]
+    pub source: VideoSourceType,
+    pub title: Option<String>, // also what TrackSettings match against, defaults to default_title
+    pub width: u32,
+    pub height: u32,
+}
+
+impl VideoSourceSettings {
+    pub fn title(&self, index: usize) -> String {
+        self.title.clone().unwrap_or_else(|| default_title(index))
+    }
+}
+
+pub fn default_title(index: usize) -> String {
+    match index {
+        0 => { "Main Video".to_string() }
+        _ => { format!("Video {}", index + 1) }
+    }
+}
+
+pub fn default_video_sources() -> Vec<VideoSourceSettings> {
+    vec![VideoSourceSettings {
+        source: VideoSourceType::D3d11 { monitor_id: 0 },
+        title: None,
+        width: 2560,
+        height: 1440,
+    }]
+}
\ No newline at end of file
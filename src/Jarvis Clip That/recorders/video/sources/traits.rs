@@ -4,5 +4,5 @@ use crate::types::Result;
 
 pub trait VideoSource {
     fn init(&mut self) -> Result<()>;
-    fn get_frame(&mut self, av_frame: &MaybeSafeFFIPtrWrapper<AVFrame>, out_width: u32, out_height: u32) -> Result<()>;
+    fn get_frame(&mut self, av_frames: &[&MaybeSafeFFIPtrWrapper<AVFrame>], out_width: u32, out_height: u32) -> Result<()>; // every frame gets the same picture
 }
\ No newline at end of file
@@ -0,0 +1,181 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn full(
+        width: u32,
+        height: u32,
+    ) -> Self {
+        Self { x: 0, y: 0, width, height }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ScalingBackend {
+    #[default]
+    Gpu,
+    Cpu, // swscale after the download, see CpuUploadAdapter
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct VideoTransformSettings {
+    pub crop: Option<Rect>, // in pixels of the captured monitor, None keeps the whole monitor
+    pub letterbox: bool, // keep the aspect ratio and pad with black, otherwise stretch to the output size
+    pub backend: ScalingBackend, // only honoured by encoders fed from system memory, D3D11 encoders always scale on the GPU
+}
+
+impl Default for VideoTransformSettings {
+    fn default() -> Self {
+        Self {
+            crop: None,
+            letterbox: true,
+            backend: ScalingBackend::default(),
+        }
+    }
+}
+
+impl VideoTransformSettings {
+    // The crop clamped into the input, on even coordinates since NV12 chroma covers 2x2 pixels
+    pub fn source_rect(
+        &self,
+        in_width: u32,
+        in_height: u32,
+    ) -> Rect {
+        let crop = self.crop.unwrap_or(Rect::full(in_width, in_height));
+        let x = even(crop.x.min(in_width.saturating_sub(2)));
+        let y = even(crop.y.min(in_height.saturating_sub(2)));
+        Rect {
+            x,
+            y,
+            width: even(crop.width.min(in_width - x)).max(2),
+            height: even(crop.height.min(in_height - y)).max(2),
+        }
+    }
+
+    // Where `source` ends up in the output frame, everything outside of it is black
+    pub fn destination_rect(
+        &self,
+        source: &Rect,
+        out_width: u32,
+        out_height: u32,
+    ) -> Rect {
+        if !self.letterbox {
+            return Rect::full(out_width, out_height);
+        }
+
+        let scale = (out_width as f64 / source.width as f64).min(out_height as f64 / source.height as f64);
+        let width = even((source.width as f64 * scale).round() as u32).clamp(2, out_width);
+        let height = even((source.height as f64 * scale).round() as u32).clamp(2, out_height);
+        Rect {
+            x: even((out_width - width) / 2),
+            y: even((out_height - height) / 2),
+            width,
+            height,
+        }
+    }
+}
+
+fn even(value: u32) -> u32 {
+    value & !1
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crop(
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> VideoTransformSettings {
+        VideoTransformSettings {
+            crop: Some(Rect { x, y, width, height }),
+            ..Default::default()
+        }
+    }
+
+    fn is_even(rect: &Rect) -> bool {
+        [rect.x, rect.y, rect.width, rect.height].iter().all(|value| value % 2 == 0)
+    }
+
+    #[test]
+    fn without_a_crop_the_whole_input_is_used() {
+        assert_eq!(VideoTransformSettings::default().source_rect(1920, 1080), Rect::full(1920, 1080));
+    }
+
+    #[test]
+    fn crops_partly_outside_are_clamped_into_the_input() {
+        let source = crop(1800, 1000, 400, 200).source_rect(1920, 1080);
+        assert_eq!(source, Rect { x: 1800, y: 1000, width: 120, height: 80 });
+    }
+
+    #[test]
+    fn crops_entirely_outside_keep_a_corner() {
+        let source = crop(3000, 2000, 100, 100).source_rect(1920, 1080);
+        assert_eq!(source, Rect { x: 1918, y: 1078, width: 2, height: 2 });
+    }
+
+    #[test]
+    fn crops_are_rounded_down_to_even_pixels() {
+        let source = crop(101, 51, 641, 361).source_rect(1920, 1080);
+        assert_eq!(source, Rect { x: 100, y: 50, width: 640, height: 360 });
+
+        // an odd input keeps the crop inside
+        let source = crop(0, 0, 1001, 1001).source_rect(1001, 1001);
+        assert_eq!(source, Rect::full(1000, 1000));
+    }
+
+    #[test]
+    fn same_aspect_fills_the_output() {
+        let settings = VideoTransformSettings::default();
+        let source = settings.source_rect(3840, 2160);
+        assert_eq!(settings.destination_rect(&source, 1920, 1080), Rect::full(1920, 1080));
+    }
+
+    #[test]
+    fn wider_sources_are_letterboxed_in_the_middle() {
+        let settings = VideoTransformSettings::default();
+        let destination = settings.destination_rect(&Rect::full(3440, 1440), 1920, 1080);
+        assert_eq!(destination, Rect { x: 0, y: 138, width: 1920, height: 804 });
+        assert_eq!(destination.y, 1080 - destination.y - destination.height);
+
+        // and narrower ones pillarboxed
+        let destination = settings.destination_rect(&Rect::full(1440, 1080), 1920, 1080);
+        assert_eq!(destination, Rect { x: 240, y: 0, width: 1440, height: 1080 });
+    }
+
+    #[test]
+    fn destinations_are_even_and_inside_the_output() {
+        let settings = VideoTransformSettings::default();
+        for (source, (out_width, out_height)) in [
+            (Rect::full(1920, 1080), (1366, 768)),
+            (Rect::full(2560, 1080), (1280, 720)),
+            (Rect { x: 100, y: 50, width: 640, height: 360 }, (1920, 1080)),
+            (Rect::full(2, 2), (1920, 1080)),
+        ] {
+            let destination = settings.destination_rect(&source, out_width, out_height);
+            assert!(is_even(&destination), "{destination:?}");
+            assert!(destination.x + destination.width <= out_width && destination.y + destination.height <= out_height, "{destination:?}");
+        }
+    }
+
+    #[test]
+    fn stretching_fills_the_output() {
+        let settings = VideoTransformSettings {
+            letterbox: false,
+            ..Default::default()
+        };
+        assert_eq!(settings.destination_rect(&Rect::full(3440, 1440), 1920, 1080), Rect::full(1920, 1080));
+    }
+}
@@ -8,44 +8,63 @@ use ffmpeg_next::encoder::video::Encoder;
 use ffmpeg_next::sys::AVFrame;
 use ffmpeg_next::util::frame::video::Video;
 
-use crate::recorders::traits::{send_frame_and_receive_packets, TRecorder};
+use crate::recorders::supervisor::Heartbeat;
+use crate::recorders::traits::{drain_encoder, RingBufferWriter, send_frame_and_receive_packets, TRecorder};
 use crate::recorders::video::sources::traits::VideoSource;
 use crate::ring_buffer::traits::PacketRingBuffer;
-use crate::types::RecorderJoinHandle;
+use crate::types::{RecorderJoinHandle, Result};
 use crate::wrappers::MaybeSafeFFIPtrWrapper;
 
-pub struct VideoRecorder<PRB, VS> {
+// An encoder with its own ring buffer, fed from the recorder's capture
+pub struct VideoOutput<PRB> {
     ring_buffer: Arc<Mutex<PRB>>,
+    video_encoder: Encoder,
+    av_frame: MaybeSafeFFIPtrWrapper<AVFrame>,
+}
+
+impl<PRB> VideoOutput<PRB> {
+    pub fn new(
+        ring_buffer: Arc<Mutex<PRB>>,
+        video_encoder: Encoder,
+        av_frame: MaybeSafeFFIPtrWrapper<AVFrame>,
+    ) -> Self {
+        Self {
+            ring_buffer,
+            video_encoder,
+            av_frame,
+        }
+    }
+}
+
+pub struct VideoRecorder<PRB, VS> {
     video_source: VS,
 
     width: u32,
     height: u32,
     fps: f64,
+    time_origin: Instant, // shared by all video recorders, pts count frames since then
 
-    video_encoder: Encoder,
-    av_frame: MaybeSafeFFIPtrWrapper<AVFrame>,
+    outputs: Vec<VideoOutput<PRB>>,
 }
 
 impl<PRB, VS> VideoRecorder<PRB, VS> {
     pub fn new(
-        ring_buffer: Arc<Mutex<PRB>>,
         video_source: VS,
-        video_encoder: Encoder,
-        av_frame: MaybeSafeFFIPtrWrapper<AVFrame>,
+        outputs: Vec<VideoOutput<PRB>>,
         width: u32,
         height: u32,
         fps: f64,
+        time_origin: Instant,
     ) -> Self {
         Self {
-            ring_buffer,
             video_source,
 
             width,
             height,
             fps,
+            time_origin,
 
-            video_encoder,
-            av_frame,
+            outputs,
         }
     }
 }
@@ -54,19 +73,29 @@ impl<PRB: PacketRingBuffer + 'static, VS: VideoSource + Send + 'static> TRecorde
     fn start_capturing(
         mut self: Box<Self>,
         stop_capturing_callback: Option<Arc<AtomicBool>>,
+        heartbeat: Option<Arc<Heartbeat>>,
+        paused: Option<Arc<AtomicBool>>,
     ) -> RecorderJoinHandle {
         fn help<PRB: PacketRingBuffer, VS: VideoSource + Send>(
             selbst: &mut Box<VideoRecorder<PRB, VS>>,
-            frame: &mut Video,
+            ring_buffers: &[RingBufferWriter<PRB>],
+            frames: &mut [Video],
             frame_duration: &Duration,
             elapsed: &mut Duration,
             expected_elapsed: &mut Duration,
             start_time: &mut Instant,
             total_frames_counter: &mut i64,
-        ) {
+            running: &AtomicBool,
+            heartbeat: Option<&Heartbeat>,
+            paused: Option<&AtomicBool>,
+        ) -> Result<()> {
             *start_time = Instant::now();
 
             for i in 0..u32::MAX {
+                if !running.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+
                 *elapsed = start_time.elapsed();
 
                 *expected_elapsed = frame_duration.saturating_mul(i);
@@ -74,37 +103,54 @@ impl<PRB: PacketRingBuffer + 'static, VS: VideoSource + Send + 'static> TRecorde
                     sleep(*expected_elapsed - *elapsed);
                 }
 
-                let _ = selbst.video_source.get_frame(&selbst.av_frame, selbst.width, selbst.height);
+                *total_frames_counter += 1;
+
+                // paused frames are never encoded, their pts are skipped and leave a gap
+                if paused.is_some_and(|paused| paused.load(Ordering::Relaxed)) {
+                    if let Some(heartbeat) = heartbeat {
+                        heartbeat.beat();
+                    }
+                    continue;
+                }
 
+                let av_frames: Vec<&MaybeSafeFFIPtrWrapper<AVFrame>> = selbst.outputs.iter().map(|output| &output.av_frame).collect();
+                let _ = selbst.video_source.get_frame(&av_frames, selbst.width, selbst.height);
 
-                *total_frames_counter += 1;
+                for ((output, ring_buffer), frame) in selbst.outputs.iter_mut().zip(ring_buffers).zip(frames.iter_mut()) {
+                    frame.set_pts(Some(*total_frames_counter));
 
-                frame.set_pts(Some(*total_frames_counter));
+                    send_frame_and_receive_packets(ring_buffer, &mut output.video_encoder, &frame, 1)?;
+                }
 
-                send_frame_and_receive_packets(&selbst.ring_buffer, &mut selbst.video_encoder, &frame, 1).unwrap_or_else(|err| panic!("VideoRecorder: Failed to send_frame_and_receive_packets because: {:?}", err));
+                if let Some(heartbeat) = heartbeat {
+                    heartbeat.beat();
+                }
             }
+            Ok(())
         }
 
+        // taken now, a restart after this thread got stuck retires them before the new recorder starts
+        let ring_buffers: Vec<RingBufferWriter<PRB>> = self.outputs.iter().map(|output| RingBufferWriter::new(&output.ring_buffer)).collect();
         thread::spawn(move || {
-            self.video_source.init().unwrap_or_else(|err| panic!("Failed to init VideoRecorder: {:?}", err));
+            self.video_source.init()?;
 
-            let mut frame = unsafe { Video::wrap(*self.av_frame) };
+            let mut frames: Vec<Video> = self.outputs.iter().map(|output| unsafe { Video::wrap(*output.av_frame) }).collect();
 
             let frame_duration: Duration = Duration::from_secs_f64(1.0f64 / self.fps);
             let mut elapsed: Duration = Default::default();
             let mut expected_elapsed: Duration = Default::default();
             let mut start_time: Instant = Instant::now();
 
-            let mut total_frames_counter = 0;
+            // recorders started later than the origin start at a later pts, so their streams line up in the clip
+            let mut total_frames_counter = (self.time_origin.elapsed().as_secs_f64() * self.fps) as i64;
 
-            if let Some(stop_capturing_callback) = stop_capturing_callback {
-                while stop_capturing_callback.load(Ordering::Relaxed) {
-                    help(&mut self, &mut frame, &frame_duration, &mut elapsed, &mut expected_elapsed, &mut start_time, &mut total_frames_counter);
-                }
-            } else {
-                loop {
-                    help(&mut self, &mut frame, &frame_duration, &mut elapsed, &mut expected_elapsed, &mut start_time, &mut total_frames_counter);
-                }
+            let running = stop_capturing_callback.unwrap_or_else(|| Arc::new(AtomicBool::new(true)));
+            while running.load(Ordering::Relaxed) {
+                help(&mut self, &ring_buffers, &mut frames, &frame_duration, &mut elapsed, &mut expected_elapsed, &mut start_time, &mut total_frames_counter, &running, heartbeat.as_deref(), paused.as_deref())?;
+            }
+
+            for (output, ring_buffer) in self.outputs.iter_mut().zip(ring_buffers.iter()) {
+                drain_encoder(ring_buffer, &mut output.video_encoder, 1)?;
             }
             Ok(())
         })
@@ -20,6 +20,10 @@ impl PacketHandler for Packet {
     fn get_contents(&self) -> &[Packet] {
         std::slice::from_ref(self)
     }
+
+    fn get_contents_mut(&mut self) -> &mut [Packet] {
+        std::slice::from_mut(self)
+    }
 }
 
 impl PacketHandler for KeyFrameStartPacketWrapper {
@@ -44,4 +48,8 @@ impl PacketHandler for KeyFrameStartPacketWrapper {
     fn get_contents(&self) -> &[Packet] {
         self.buffer.as_slice()
     }
+
+    fn get_contents_mut(&mut self) -> &mut [Packet] {
+        self.buffer.as_mut_slice()
+    }
 }
\ No newline at end of file
@@ -7,6 +7,20 @@ pub struct RingBuffer<T: PacketHandler> {
     frame_counter: i64,
     buffer: VecDeque<T>,
     min_frame_amount: i64,
+    writer: u64,
+}
+
+impl<T: PacketHandler> RingBuffer<T> {
+    fn drop_expired(&mut self) {
+        while let Some(front) = self.buffer.front() {
+            if self.frame_counter - front.get_duration() > self.min_frame_amount {
+                self.frame_counter -= front.get_duration();
+                self.buffer.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
 }
 
 impl<T: PacketHandler> PacketRingBuffer for RingBuffer<T> {
@@ -18,14 +32,7 @@ impl<T: PacketHandler> PacketRingBuffer for RingBuffer<T> {
 
         T::insert(&mut self.buffer, packet);
 
-        while let Some(front) = self.buffer.front() {
-            if self.frame_counter - front.get_duration() > self.min_frame_amount {
-                self.frame_counter -= front.get_duration();
-                self.buffer.pop_front();
-            } else {
-                break;
-            }
-        }
+        self.drop_expired();
     }
 
     fn copy_out(
@@ -56,6 +63,48 @@ impl<T: PacketHandler> PacketRingBuffer for RingBuffer<T> {
             frame_counter: 0,
             buffer: VecDeque::new(),
             min_frame_amount: min_frame_amount as i64,
+            writer: 0,
         }
     }
+
+    fn min_frame_amount(&self) -> i64 {
+        self.min_frame_amount
+    }
+
+    fn frame_amount(&self) -> i64 {
+        self.frame_counter
+    }
+
+    // Shrinking drops the oldest packets right away, growing keeps everything and lets the buffer fill up
+    fn set_min_frame_amount(
+        &mut self,
+        min_frame_amount: u32,
+    ) {
+        self.min_frame_amount = min_frame_amount as i64;
+        self.drop_expired();
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.frame_counter = 0;
+    }
+
+    // Moves every packet by `offset`, used when a restarted recorder counts from 0 again
+    fn offset_pts(
+        &mut self,
+        offset: i64,
+    ) {
+        for packet in self.buffer.iter_mut().flat_map(|item| item.get_contents_mut().iter_mut()) {
+            packet.set_pts(packet.pts().map(|pts| pts + offset));
+            packet.set_dts(packet.dts().map(|dts| dts + offset));
+        }
+    }
+    fn writer(&self) -> u64 {
+        self.writer
+    }
+
+    // The current writer's packets are dropped from now on, recorders started afterwards insert as the next one
+    fn retire_writer(&mut self) {
+        self.writer += 1;
+    }
 }
\ No newline at end of file
@@ -6,10 +6,19 @@ pub trait PacketRingBuffer: Sync + Send {
     fn insert(&mut self, packet: Packet);
     fn copy_out(&self, min_requested_frames: Option<i64>) -> Vec<Packet>;
     fn new(min_frame_amount: u32) -> Self;
+    fn min_frame_amount(&self) -> i64;
+    fn frame_amount(&self) -> i64;
+    fn set_min_frame_amount(&mut self, min_frame_amount: u32);
+    fn offset_pts(&mut self, offset: i64);
+    fn clear(&mut self);
+    // Which recorder thread may insert, see RingBufferWriter
+    fn writer(&self) -> u64;
+    fn retire_writer(&mut self);
 }
 
 pub trait PacketHandler: Sized + Sync + Send {
     fn insert(container: &mut VecDeque<Self>, packet: Packet);
     fn get_duration(&self) -> i64;
     fn get_contents(&self) -> &[Packet];
+    fn get_contents_mut(&mut self) -> &mut [Packet];
 }
\ No newline at end of file
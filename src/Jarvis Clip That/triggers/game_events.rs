@@ -0,0 +1,373 @@
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::io::BufReader;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::debug_println;
+use crate::api::{error_json, read_request, status_text, write_response};
+use crate::error::Error;
+use crate::triggers::trigger::{Trigger, Triggers};
+use crate::types::Result;
+
+// Games that post their state as JSON (e.g. Game State Integration) to http://<bind>:<port>, any path
+#[derive(Deserialize, PartialEq)]
+#[serde(default)]
+pub struct GameEventSettings {
+    pub enabled: bool,
+    pub bind: String,
+    pub port: u16,
+    // checked against "Authorization: Bearer <token>" or the posted auth.token, as the game's config sends it
+    pub token: Option<String>,
+    pub rules: Vec<GameEventRule>,
+}
+
+impl Default for GameEventSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: "127.0.0.1".to_string(),
+            port: 7374,
+            token: None,
+            rules: Vec::new(),
+        }
+    }
+}
+
+// Every post whose conditions all hold is a hit, `count` hits within `within_secs` save a clip
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct GameEventRule {
+    pub name: String,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default = "default_count")]
+    pub count: u32,
+    #[serde(default)]
+    pub within_secs: f64,
+
+    pub save_secs: Option<u32>, // the whole short replay if not set
+    #[serde(default)]
+    pub delay_secs: f64,
+    #[serde(default)]
+    pub cooldown_secs: f64,
+}
+
+// `path` is dotted, with numbers indexing arrays ("allplayers.0.name"). Without any test it only has to exist
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct Condition {
+    pub path: String,
+    pub equals: Option<Value>,
+    pub above: Option<f64>,
+    pub below: Option<f64>,
+    // compared with the same path in the previous post
+    #[serde(default)]
+    pub increased: bool,
+    #[serde(default)]
+    pub changed: bool,
+}
+
+impl Condition {
+    fn holds(&self, payload: &Value, previous: Option<&Value>) -> bool {
+        let Some(value) = lookup(payload, &self.path) else {
+            return false;
+        };
+        let previous = previous.and_then(|previous| lookup(previous, &self.path));
+        let number = value.as_f64();
+
+        self.equals.as_ref().is_none_or(|equals| same_value(value, equals))
+            && self.above.is_none_or(|above| number.is_some_and(|number| number > above))
+            && self.below.is_none_or(|below| number.is_some_and(|number| number < below))
+            && (!self.increased || matches!((number, previous.and_then(Value::as_f64)), (Some(number), Some(previous)) if number > previous))
+            && (!self.changed || previous.is_some_and(|previous| !same_value(value, previous)))
+    }
+}
+
+struct RuleMatcher {
+    rules: Vec<GameEventRule>,
+    hits: Vec<VecDeque<Instant>>, // per rule, only the ones still within its window
+    previous: Option<Value>,
+}
+
+impl RuleMatcher {
+    fn new(rules: Vec<GameEventRule>) -> Self {
+        Self {
+            hits: vec![VecDeque::new(); rules.len()],
+            rules,
+            previous: None,
+        }
+    }
+
+    // The rules that are complete with this post
+    fn feed(&mut self, payload: Value, now: Instant) -> Vec<GameEventRule> {
+        let mut matched = Vec::new();
+        for (rule, hits) in self.rules.iter().zip(self.hits.iter_mut()) {
+            if !rule.conditions.iter().all(|condition| condition.holds(&payload, self.previous.as_ref())) {
+                continue;
+            }
+            hits.push_back(now);
+            while hits.front().is_some_and(|at| now.duration_since(*at).as_secs_f64() > rule.within_secs) {
+                hits.pop_front();
+            }
+            if hits.len() >= rule.count as usize {
+                hits.clear();
+                matched.push(rule.clone());
+            }
+        }
+        self.previous = Some(payload);
+        matched
+    }
+}
+
+// Listens in the background, matched rules go to `triggers`
+pub async fn start_game_events(
+    settings: &GameEventSettings,
+    triggers: Triggers,
+) -> Result<()> {
+    let address: IpAddr = settings.bind.parse().map_err(|_| Error::InvalidConfig(format!("game_events.bind must be an IP address, got {}", settings.bind)))?;
+    let listener = TcpListener::bind(SocketAddr::new(address, settings.port)).await?;
+    let token = settings.token.clone();
+    let matcher = Arc::new(Mutex::new(RuleMatcher::new(settings.rules.clone())));
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => { tokio::spawn(handle_connection(stream, token.clone(), matcher.clone(), triggers.clone())); }
+                Err(err) => {
+                    eprintln!("Game event listener failed: {:?}", err);
+                    break;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    token: Option<String>,
+    matcher: Arc<Mutex<RuleMatcher>>,
+    triggers: Triggers,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let request = match read_request(&mut reader).await {
+        Ok(Some(request)) => request,
+        Ok(None) => return,
+        Err(status) => {
+            let _ = write_response(&mut writer, status, &error_json(status_text(status))).await;
+            return;
+        }
+    };
+    if request.method != "POST" {
+        let _ = write_response(&mut writer, 405, &error_json(status_text(405))).await;
+        return;
+    }
+    let payload: Value = match serde_json::from_slice(&request.body) {
+        Ok(payload) => payload,
+        Err(err) => {
+            let _ = write_response(&mut writer, 400, &error_json(&format!("invalid body: {}", err))).await;
+            return;
+        }
+    };
+
+    if let Some(token) = &token {
+        let bearer = request.headers.get("authorization").and_then(|value| value.strip_prefix("Bearer "));
+        let posted = payload.pointer("/auth/token").and_then(Value::as_str);
+        if bearer != Some(token.as_str()) && posted != Some(token.as_str()) {
+            let _ = write_response(&mut writer, 401, &error_json("missing or wrong token")).await;
+            return;
+        }
+    }
+
+    let matched = matcher.lock().unwrap().feed(payload, Instant::now());
+    for rule in matched {
+        let trigger = Trigger { rule: rule.name.clone(), save_secs: rule.save_secs };
+        if !triggers.fire(trigger, rule.delay_secs, rule.cooldown_secs) {
+            debug_println!("{} is cooling down", rule.name);
+        }
+    }
+    let _ = write_response(&mut writer, 200, "{}").await;
+}
+
+fn lookup<'a>(payload: &'a Value, path: &str) -> Option<&'a Value> {
+    payload.pointer(&format!("/{}", path.replace('.', "/")))
+}
+
+// 3 from the config and 3.0 from the game are the same
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => { a == b }
+        _ => { a == b }
+    }
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use serde_json::json;
+
+    fn condition(path: &str) -> Condition {
+        Condition {
+            path: path.to_string(),
+            equals: None,
+            above: None,
+            below: None,
+            increased: false,
+            changed: false,
+        }
+    }
+
+    fn rule(
+        conditions: Vec<Condition>,
+        count: u32,
+        within_secs: f64,
+    ) -> GameEventRule {
+        GameEventRule {
+            name: "kills".to_string(),
+            conditions,
+            count,
+            within_secs,
+            save_secs: None,
+            delay_secs: 0.,
+            cooldown_secs: 0.,
+        }
+    }
+
+    fn kills(kills: u32) -> Value {
+        json!({ "player": { "state": { "round_kills": kills } } })
+    }
+
+    #[test]
+    fn equals_above_and_below() {
+        let payload = json!({ "map": { "phase": "live", "round": 3 }, "allplayers": [{ "name": "a" }, { "name": "b" }] });
+
+        let mut phase = condition("map.phase");
+        assert!(phase.holds(&payload, None));
+        phase.equals = Some(json!("live"));
+        assert!(phase.holds(&payload, None));
+        phase.equals = Some(json!("warmup"));
+        assert!(!phase.holds(&payload, None));
+
+        let mut name = condition("allplayers.1.name");
+        name.equals = Some(json!("b"));
+        assert!(name.holds(&payload, None));
+        assert!(!condition("allplayers.2.name").holds(&payload, None));
+
+        let mut round = condition("map.round");
+        round.above = Some(2.);
+        round.below = Some(4.);
+        assert!(round.holds(&payload, None));
+        round.above = Some(3.);
+        assert!(!round.holds(&payload, None));
+        // not a number
+        let mut phase = condition("map.phase");
+        phase.above = Some(0.);
+        assert!(!phase.holds(&payload, None));
+    }
+
+    #[test]
+    fn numbers_are_equal_however_they_are_written() {
+        assert!(same_value(&json!(3), &json!(3.0)));
+        assert!(same_value(&json!(-1), &json!(-1.0)));
+        assert!(!same_value(&json!(3), &json!(3.5)));
+        assert!(!same_value(&json!(3), &json!("3")));
+        assert!(same_value(&json!("live"), &json!("live")));
+
+        let mut round = condition("round");
+        round.equals = Some(json!(3));
+        assert!(round.holds(&json!({ "round": 3.0 }), None));
+    }
+
+    #[test]
+    fn increased_and_changed_compare_with_the_previous_post() {
+        let mut increased = condition("player.state.round_kills");
+        increased.increased = true;
+        assert!(!increased.holds(&kills(1), None));
+        assert!(increased.holds(&kills(2), Some(&kills(1))));
+        assert!(!increased.holds(&kills(2), Some(&kills(2))));
+        assert!(!increased.holds(&kills(0), Some(&kills(2))));
+        // the previous post didn't have it
+        assert!(!increased.holds(&kills(1), Some(&json!({}))));
+
+        let mut changed = condition("player.state.round_kills");
+        changed.changed = true;
+        assert!(!changed.holds(&kills(1), None));
+        assert!(changed.holds(&kills(0), Some(&kills(2))));
+        assert!(!changed.holds(&json!({ "player": { "state": { "round_kills": 2.0 } } }), Some(&kills(2))));
+    }
+
+    #[test]
+    fn increased_follows_the_posts_fed_to_the_matcher() {
+        let mut increased = condition("player.state.round_kills");
+        increased.increased = true;
+        let mut matcher = RuleMatcher::new(vec![rule(vec![increased], 1, 0.)]);
+        let start = Instant::now();
+
+        assert!(matcher.feed(kills(0), start).is_empty());
+        assert_eq!(matcher.feed(kills(1), start + Duration::from_secs(1)).len(), 1);
+        // a post without a kill in between
+        assert!(matcher.feed(kills(1), start + Duration::from_secs(2)).is_empty());
+        assert_eq!(matcher.feed(kills(2), start + Duration::from_secs(3)).len(), 1);
+    }
+
+    #[test]
+    fn count_within_the_window_matches() {
+        let mut increased = condition("player.state.round_kills");
+        increased.increased = true;
+        let mut matcher = RuleMatcher::new(vec![rule(vec![increased], 3, 10.)]);
+        let start = Instant::now();
+
+        assert!(matcher.feed(kills(0), start).is_empty());
+        assert!(matcher.feed(kills(1), start + Duration::from_secs(1)).is_empty());
+        assert!(matcher.feed(kills(2), start + Duration::from_secs(4)).is_empty());
+        let matched = matcher.feed(kills(3), start + Duration::from_secs(8));
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].name, "kills");
+
+        // the hits were used up, the next kill starts over
+        assert!(matcher.feed(kills(4), start + Duration::from_secs(9)).is_empty());
+    }
+
+    #[test]
+    fn hits_outside_the_window_expire() {
+        let mut matcher = RuleMatcher::new(vec![rule(vec![condition("kill")], 3, 10.)]);
+        let start = Instant::now();
+        let kill = || json!({ "kill": true });
+
+        assert!(matcher.feed(kill(), start).is_empty());
+        assert!(matcher.feed(kill(), start + Duration::from_secs(6)).is_empty());
+        // the first one is 12 s old by now
+        assert!(matcher.feed(kill(), start + Duration::from_secs(12)).is_empty());
+        assert_eq!(matcher.feed(kill(), start + Duration::from_secs(13)).len(), 1);
+
+        // posts that don't hold don't count, but don't reset either
+        assert!(matcher.feed(json!({}), start + Duration::from_secs(14)).is_empty());
+        assert!(matcher.feed(kill(), start + Duration::from_secs(15)).is_empty());
+        assert!(matcher.feed(kill(), start + Duration::from_secs(16)).is_empty());
+        assert_eq!(matcher.feed(kill(), start + Duration::from_secs(17)).len(), 1);
+    }
+
+    #[test]
+    fn every_rule_counts_on_its_own() {
+        let mut won = condition("round.win_team");
+        won.equals = Some(json!("CT"));
+        let mut rules = vec![rule(vec![condition("kill")], 2, 10.), rule(vec![won], 1, 0.)];
+        rules[1].name = "round won".to_string();
+        let mut matcher = RuleMatcher::new(rules);
+        let start = Instant::now();
+
+        assert!(matcher.feed(json!({ "kill": true }), start).is_empty());
+        let matched = matcher.feed(json!({ "kill": true, "round": { "win_team": "CT" } }), start + Duration::from_secs(1));
+        let names: Vec<&str> = matched.iter().map(|rule| rule.name.as_str()).collect();
+        assert_eq!(names, ["kills", "round won"]);
+    }
+}
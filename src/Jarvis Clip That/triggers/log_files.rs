@@ -0,0 +1,301 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::time::{Duration, SystemTime};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::debug_println;
+use crate::error::Error;
+use crate::triggers::trigger::{Trigger, Triggers};
+use crate::types::Result;
+
+// how often the files are checked for new lines
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// read per file and poll, a file that grew by more is caught up with over the next polls
+const MAX_READ_BYTES: u64 = 1024 * 1024;
+// compared before every read, see TailedFile::read_lines
+const TAIL_BYTES: usize = 64;
+
+// A new line in `path` matching `match` saves a clip
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct LogFileRule {
+    pub name: String,
+    pub path: String,
+    #[serde(rename = "match")]
+    pub pattern: String, // a regex, e.g. "Player .* was eliminated by Me"
+
+    pub save_secs: Option<u32>, // the whole short replay if not set
+    #[serde(default)]
+    pub delay_secs: f64,
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: f64,
+}
+
+// Follows one file like `tail -F`, through rotation and truncation
+struct TailedFile {
+    path: String,
+    position: u64,
+    created: Option<SystemTime>, // a different one means the file was replaced
+    tail: Vec<u8>, // the last bytes before `position`
+    partial_line: Vec<u8>, // the game is still writing it
+    failing: bool, // the error was already printed
+    rules: Vec<(LogFileRule, Regex)>,
+}
+
+impl TailedFile {
+    // Starts at the current end, what's already in the file happened before the recorder was started
+    fn new(path: String) -> Self {
+        let metadata = std::fs::metadata(&path).ok();
+        let position = metadata.as_ref().map_or(0, |metadata| metadata.len());
+        let tail = File::open(&path).and_then(|mut file| bytes_before(&mut file, position)).unwrap_or_default();
+        Self {
+            position,
+            created: metadata.and_then(|metadata| metadata.created().ok()),
+            tail,
+            path,
+            partial_line: Vec::new(),
+            failing: false,
+            rules: Vec::new(),
+        }
+    }
+
+    // The lines completed since the last call, blocks on the file system
+    fn read_lines(&mut self) -> std::io::Result<Vec<String>> {
+        let metadata = match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // rotated away and not recreated yet, or not created at all yet
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                self.reset(None);
+                return Ok(Vec::new());
+            }
+            Err(err) => return Err(err),
+        };
+        let created = metadata.created().ok();
+        if created != self.created || metadata.len() < self.position {
+            // a new file is read from its start
+            self.reset(created);
+        }
+        if metadata.len() == self.position {
+            return Ok(Vec::new());
+        }
+
+        // not kept open, so the game can still rename or delete it
+        let mut file = File::open(&self.path)?;
+        // NTFS gives a file created right after another one of the same name was deleted the old creation time (tunneling),
+        // so a replaced file that already grew past the position is told apart by the bytes before it
+        if bytes_before(&mut file, self.position)? != self.tail {
+            self.reset(created);
+        }
+        file.seek(SeekFrom::Start(self.position))?;
+        let mut bytes = Vec::new();
+        file.take(MAX_READ_BYTES).read_to_end(&mut bytes)?;
+        self.position += bytes.len() as u64;
+        self.tail.extend_from_slice(&bytes);
+        self.tail.drain(..self.tail.len().saturating_sub(TAIL_BYTES));
+
+        self.partial_line.extend_from_slice(&bytes);
+        let Some(end) = self.partial_line.iter().rposition(|byte| *byte == b'\n') else {
+            // no line is that long, it's not a text log
+            if self.partial_line.len() as u64 > MAX_READ_BYTES {
+                self.partial_line.clear();
+            }
+            return Ok(Vec::new());
+        };
+        let complete: Vec<u8> = self.partial_line.drain(..=end).collect();
+        Ok(String::from_utf8_lossy(&complete).lines().map(str::to_string).collect())
+    }
+
+    fn reset(&mut self, created: Option<SystemTime>) {
+        self.position = 0;
+        self.created = created;
+        self.tail.clear();
+        self.partial_line.clear();
+    }
+
+    // Prints an error once until reading works again
+    fn new_lines(&mut self) -> Vec<String> {
+        match self.read_lines() {
+            Ok(lines) => {
+                self.failing = false;
+                lines
+            }
+            Err(err) => {
+                if !self.failing {
+                    eprintln!("Couldn't read {}: {:?}", self.path, err);
+                    self.failing = true;
+                }
+                Vec::new()
+            }
+        }
+    }
+}
+
+// Up to TAIL_BYTES before `position`, fewer if the file is shorter
+fn bytes_before(
+    file: &mut File,
+    position: u64,
+) -> std::io::Result<Vec<u8>> {
+    let start = position.saturating_sub(TAIL_BYTES as u64);
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.take(position - start).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+// Tails every file with a rule in the background, matching lines go to `triggers`
+pub fn start_log_watcher(
+    rules: &[LogFileRule],
+    triggers: Triggers,
+) -> Result<()> {
+    let mut files: Vec<TailedFile> = Vec::new();
+    for rule in rules {
+        let regex = Regex::new(&rule.pattern).map_err(|err| Error::InvalidConfig(format!("log_files: {} isn't a valid regex: {}", rule.pattern, err)))?;
+        let index = match files.iter().position(|file| file.path == rule.path) {
+            Some(index) => index,
+            None => {
+                files.push(TailedFile::new(rule.path.clone()));
+                files.len() - 1
+            }
+        };
+        files[index].rules.push((rule.clone(), regex));
+    }
+
+    tokio::spawn(async move {
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            poll.tick().await;
+            // a slow or network drive mustn't hold up a runtime worker
+            let read = tokio::task::spawn_blocking(move || {
+                let lines: Vec<Vec<String>> = files.iter_mut().map(TailedFile::new_lines).collect();
+                (files, lines)
+            }).await;
+            let (read_files, new_lines) = match read {
+                Ok(read) => read,
+                Err(err) => {
+                    eprintln!("Log file watcher failed: {:?}", err);
+                    break;
+                }
+            };
+            files = read_files;
+
+            for (file, lines) in files.iter().zip(new_lines) {
+                for line in lines {
+                    for (rule, regex) in file.rules.iter() {
+                        if !regex.is_match(&line) {
+                            continue;
+                        }
+                        debug_println!("{} matched: {}", rule.name, line);
+                        let trigger = Trigger { rule: rule.name.clone(), save_secs: rule.save_secs };
+                        if !triggers.fire(trigger, rule.delay_secs, rule.cooldown_secs) {
+                            debug_println!("{} is cooling down", rule.name);
+                        }
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+fn default_cooldown_secs() -> f64 {
+    30.
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // A file of its own per test, removed again when dropped
+    struct TempLog(PathBuf);
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("jarvis-log-files-{}-{}.log", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+
+        fn path(&self) -> String {
+            self.0.to_string_lossy().into_owned()
+        }
+
+        fn append(&self, text: &str) {
+            let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.0).unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+        }
+
+        fn replace(&self, text: &str) {
+            std::fs::write(&self.0, text).unwrap();
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn only_lines_written_after_the_start_count() {
+        let log = TempLog::new("start");
+        log.append("before the recorder started\n");
+        let mut file = TailedFile::new(log.path());
+        assert!(file.read_lines().unwrap().is_empty());
+
+        log.append("first\nsecond\nthird without its end");
+        assert_eq!(file.read_lines().unwrap(), vec!["first", "second"]);
+        log.append(" yet\n");
+        assert_eq!(file.read_lines().unwrap(), vec!["third without its end yet"]);
+        assert!(file.read_lines().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_missing_file_is_read_from_its_start_once_it_exists() {
+        let log = TempLog::new("missing");
+        let mut file = TailedFile::new(log.path());
+        assert!(file.read_lines().unwrap().is_empty());
+
+        log.append("created later\n");
+        assert_eq!(file.read_lines().unwrap(), vec!["created later"]);
+    }
+
+    #[test]
+    fn a_truncated_file_is_read_from_its_start() {
+        let log = TempLog::new("truncated");
+        log.append("a long line from before the truncation\n");
+        let mut file = TailedFile::new(log.path());
+
+        log.replace("short\n");
+        assert_eq!(file.read_lines().unwrap(), vec!["short"]);
+    }
+
+    #[test]
+    fn a_replaced_file_is_noticed_even_if_it_grew_past_the_position() {
+        let log = TempLog::new("replaced");
+        log.append("old session\n");
+        let mut file = TailedFile::new(log.path());
+        log.append("old line\n");
+        assert_eq!(file.read_lines().unwrap(), vec!["old line"]);
+
+        // e.g. a creation time kept by NTFS tunneling, so only the content tells the files apart
+        log.replace("new session started\nnew line\n");
+        file.created = std::fs::metadata(log.path()).unwrap().created().ok();
+        assert_eq!(file.read_lines().unwrap(), vec!["new session started", "new line"]);
+    }
+
+    #[test]
+    fn big_writes_are_read_over_several_polls() {
+        let log = TempLog::new("big");
+        let mut file = TailedFile::new(log.path());
+        let line = format!("{}\n", "x".repeat(1023));
+        log.append(&line.repeat(MAX_READ_BYTES as usize / 1024 + 10));
+
+        assert_eq!(file.read_lines().unwrap().len(), MAX_READ_BYTES as usize / 1024);
+        assert_eq!(file.read_lines().unwrap().len(), 10);
+        assert!(file.read_lines().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::sync::mpsc::Receiver;
+use std::thread;
+
+use serde::Deserialize;
+use tokio::runtime::Handle;
+
+use crate::debug_println;
+use crate::recorders::audio::tap::{AudioChunk, AudioTap};
+use crate::recorders::track_settings::glob_matches;
+use crate::triggers::trigger::{Trigger, Triggers};
+
+// loudness is measured every hop over the last BLOCK_HOPS, 400 ms like a meter's momentary loudness
+const HOPS_PER_SEC: u32 = 10;
+const BLOCK_HOPS: usize = 4;
+// how long the average a jump is measured against looks back
+const BACKGROUND_SECS: f64 = 10.;
+// a jump out of near silence doesn't count below this, otherwise a mouse click after a quiet minute would
+const MIN_JUMP_LUFS: f32 = -45.;
+const MIN_BACKGROUND_LUFS: f32 = -70.;
+
+// A track getting loud saves a clip, e.g. friends screaming on the mic or an explosion in the game.
+// Every threshold that is set has to be crossed for `min_duration_secs`
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct LoudnessRule {
+    pub name: String,
+    // matched like [[tracks]] match: "Main Audio", the device name or a process name, '*' matches anything
+    pub track: String,
+    pub above_lufs: Option<f32>, // loudness of the last 400 ms
+    pub jump_db: Option<f32>, // above the track's average of the last BACKGROUND_SECS
+    // how long the loudness has to stay above, a short bang already holds the 400 ms loudness up for about that long
+    #[serde(default = "default_min_duration_secs")]
+    pub min_duration_secs: f64,
+
+    pub save_secs: Option<u32>, // the whole short replay if not set
+    #[serde(default)]
+    pub delay_secs: f64,
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: f64,
+}
+
+// Hands out a meter per rule to every track the rule matches, see TrackTaps
+pub struct LoudnessTrigger {
+    rules: Vec<LoudnessRule>,
+    triggers: Triggers,
+    runtime: Handle, // triggers are sent through tokio, taps are also made from recorder threads
+}
+
+impl LoudnessTrigger {
+    pub fn new(
+        rules: &[LoudnessRule],
+        triggers: Triggers,
+    ) -> Self {
+        Self {
+            rules: rules.to_vec(),
+            triggers,
+            runtime: Handle::current(),
+        }
+    }
+
+    // One tap per matching rule, each metered in its own thread until the track's recorders are gone
+    pub fn taps(&self, names: &[&str]) -> Vec<AudioTap> {
+        self.rules.iter()
+            .filter(|rule| names.iter().any(|name| glob_matches(&rule.track, name)))
+            .map(|rule| {
+                let (tap, rx) = AudioTap::new();
+                let meter = LoudnessMeter::new(rule.clone(), names.first().copied().unwrap_or_default().to_string(), self.triggers.clone());
+                let runtime = self.runtime.clone();
+                thread::spawn(move || {
+                    let _runtime = runtime.enter();
+                    meter.run(rx);
+                });
+                tap
+            })
+            .collect()
+    }
+}
+
+// Loudness of one track for one rule
+struct LoudnessMeter {
+    rule: LoudnessRule,
+    track: String,
+    triggers: Triggers,
+
+    sample_rate: u32,
+    filter: KWeighting,
+    next_secs: f64, // where the next chunk should start if nothing was lost
+    hop: (f64, usize), // (sum of squares, samples) of the hop being measured
+    block: VecDeque<(f64, usize)>, // the last BLOCK_HOPS hops
+    background: Option<f32>, // LUFS
+    loud_secs: f64, // how long every threshold has been crossed
+    fired: bool, // for the current crossing, it has to get quieter before it counts again
+}
+
+impl LoudnessMeter {
+    fn new(
+        rule: LoudnessRule,
+        track: String,
+        triggers: Triggers,
+    ) -> Self {
+        Self {
+            rule,
+            track,
+            triggers,
+
+            sample_rate: 0,
+            filter: KWeighting::new(48_000),
+            next_secs: 0.,
+            hop: (0., 0),
+            block: VecDeque::new(),
+            background: None,
+            loud_secs: 0.,
+            fired: false,
+        }
+    }
+
+    fn run(mut self, rx: Receiver<AudioChunk>) {
+        while let Ok(chunk) = rx.recv() {
+            self.push(&chunk);
+        }
+    }
+
+    fn push(&mut self, chunk: &AudioChunk) {
+        // a restarted recorder or lost chunks, the filter and the blocks don't continue here
+        if chunk.sample_rate != self.sample_rate || (chunk.start_secs - self.next_secs).abs() > 0.1 {
+            if chunk.sample_rate != self.sample_rate {
+                self.sample_rate = chunk.sample_rate;
+                self.background = None;
+            }
+            self.filter = KWeighting::new(chunk.sample_rate);
+            self.hop = (0., 0);
+            self.block.clear();
+            self.loud_secs = 0.;
+        }
+        self.next_secs = chunk.start_secs + chunk.samples.len() as f64 / chunk.sample_rate as f64;
+
+        let hop_len = (chunk.sample_rate / HOPS_PER_SEC) as usize;
+        for sample in chunk.samples.iter() {
+            let weighted = self.filter.process(*sample as f64);
+            self.hop.0 += weighted * weighted;
+            self.hop.1 += 1;
+            if self.hop.1 == hop_len {
+                self.block.push_back(std::mem::take(&mut self.hop));
+                if self.block.len() > BLOCK_HOPS {
+                    self.block.pop_front();
+                }
+                if self.block.len() == BLOCK_HOPS {
+                    self.measure();
+                }
+            }
+        }
+    }
+
+    // Every hop, once there's a whole block
+    fn measure(&mut self) {
+        let (sum, samples) = self.block.iter().fold((0., 0), |(sum, samples), (hop_sum, hop_samples)| (sum + hop_sum, samples + hop_samples));
+        let mean_square = sum / samples as f64;
+        let loudness = lufs(mean_square);
+        let background = self.background.unwrap_or(loudness).max(MIN_BACKGROUND_LUFS);
+
+        let loud = self.rule.above_lufs.is_none_or(|above_lufs| loudness >= above_lufs)
+            && self.rule.jump_db.is_none_or(|jump_db| loudness >= MIN_JUMP_LUFS && loudness - background >= jump_db);
+        // averaged in dB, so a single bang barely moves it and the next one still counts as a jump
+        let follow = 1. / (BACKGROUND_SECS * HOPS_PER_SEC as f64) as f32;
+        self.background = Some(background + (loudness - background) * follow);
+
+        if !loud {
+            self.loud_secs = 0.;
+            self.fired = false;
+            return;
+        }
+        self.loud_secs += 1. / HOPS_PER_SEC as f64;
+        if self.fired || self.loud_secs < self.rule.min_duration_secs {
+            return;
+        }
+        self.fired = true;
+
+        debug_println!("{} on {}: {:.1} LUFS, {:.1} above the background", self.rule.name, self.track, loudness, loudness - background);
+        let trigger = Trigger { rule: self.rule.name.clone(), save_secs: self.rule.save_secs };
+        if !self.triggers.fire(trigger, self.rule.delay_secs, self.rule.cooldown_secs) {
+            debug_println!("{} is cooling down", self.rule.name);
+        }
+    }
+}
+
+fn lufs(mean_square: f64) -> f32 {
+    (-0.691 + 10. * (mean_square + 1e-12).log10()) as f32
+}
+
+// The K-weighting of ITU-R BS.1770 for any sample rate, a high shelf for how the head boosts the highs and a high pass.
+// Measured on the mono mix the taps hand out, so a stereo track reads about 3 dB below a meter summing both channels
+struct KWeighting {
+    stages: [Biquad; 2],
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+
+        let k = (PI * 1681.974450955533 / rate).tan();
+        let q = 0.7071752369554196;
+        let vh = 10f64.powf(3.999843853973347 / 20.);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1. + k / q + k * k;
+        let shelf = Biquad::new(
+            [(vh + vb * k / q + k * k) / a0, 2. * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        );
+
+        let k = (PI * 38.13547087602444 / rate).tan();
+        let q = 0.5003270373238773;
+        let a0 = 1. + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1., -2., 1.],
+            [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        );
+
+        Self {
+            stages: [shelf, high_pass],
+        }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.stages.iter_mut().fold(sample, |sample, stage| stage.process(sample))
+    }
+}
+
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2], // a1 and a2, a0 is 1
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            state: [0.; 2],
+        }
+    }
+
+    // Transposed direct form II
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+fn default_min_duration_secs() -> f64 {
+    0.5
+}
+
+fn default_cooldown_secs() -> f64 {
+    30.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    // A sine whose RMS level is `dbfs`, its peaks are 3 dB higher
+    fn sine(
+        hz: f64,
+        dbfs: f64,
+        secs: f64,
+        sample_rate: u32,
+    ) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.) * 2f64.sqrt();
+        (0..(secs * sample_rate as f64) as usize)
+            .map(|i| (amplitude * (2. * PI * hz * i as f64 / sample_rate as f64).sin()) as f32)
+            .collect()
+    }
+
+    fn meter(
+        above_lufs: Option<f32>,
+        jump_db: Option<f32>,
+    ) -> (LoudnessMeter, UnboundedReceiver<Trigger>) {
+        let rule = LoudnessRule {
+            name: "loud".to_string(),
+            track: "*".to_string(),
+            above_lufs,
+            jump_db,
+            min_duration_secs: 1.,
+            save_secs: None,
+            delay_secs: 0.,
+            cooldown_secs: 0.,
+        };
+        let (triggers, trigger_rx) = Triggers::new();
+        (LoudnessMeter::new(rule, "Mic".to_string(), triggers), trigger_rx)
+    }
+
+    // `dbfs` for `secs`, in 10 ms chunks like the taps hand them out
+    fn play(
+        meter: &mut LoudnessMeter,
+        dbfs: f64,
+        secs: f64,
+    ) {
+        let samples = sine(997., dbfs, secs, SAMPLE_RATE);
+        for chunk in samples.chunks(SAMPLE_RATE as usize / 100) {
+            let start_secs = meter.next_secs;
+            meter.push(&AudioChunk { samples: chunk.to_vec(), sample_rate: SAMPLE_RATE, start_secs });
+        }
+    }
+
+    // How many triggers were sent since the last call
+    async fn fired(trigger_rx: &mut UnboundedReceiver<Trigger>) -> usize {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let mut count = 0;
+        while trigger_rx.try_recv().is_ok() {
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn k_weighting_reads_a_997_hz_sine_at_its_level() {
+        for sample_rate in [48_000, 44_100] {
+            let mut filter = KWeighting::new(sample_rate);
+            let weighted: Vec<f64> = sine(997., -20., 2., sample_rate).iter().map(|sample| filter.process(*sample as f64)).collect();
+            // after the filters settled
+            let settled = &weighted[sample_rate as usize / 2..];
+            let loudness = lufs(settled.iter().map(|sample| sample * sample).sum::<f64>() / settled.len() as f64);
+            assert!((loudness + 20.).abs() < 0.1, "{loudness} LUFS at {sample_rate} Hz");
+        }
+    }
+
+    #[tokio::test]
+    async fn above_lufs_fires_after_min_duration() {
+        let (mut meter, mut trigger_rx) = meter(Some(-30.), None);
+        play(&mut meter, -60., 2.);
+        assert_eq!(fired(&mut trigger_rx).await, 0);
+
+        play(&mut meter, -20., 0.8);
+        assert_eq!(fired(&mut trigger_rx).await, 0);
+        play(&mut meter, -20., 0.4);
+        assert_eq!(fired(&mut trigger_rx).await, 1);
+    }
+
+    #[tokio::test]
+    async fn it_fires_again_only_after_getting_quieter() {
+        let (mut meter, mut trigger_rx) = meter(Some(-30.), None);
+        play(&mut meter, -20., 1.5);
+        assert_eq!(fired(&mut trigger_rx).await, 1);
+        play(&mut meter, -20., 5.);
+        assert_eq!(fired(&mut trigger_rx).await, 0);
+
+        play(&mut meter, -60., 1.);
+        play(&mut meter, -20., 1.5);
+        assert_eq!(fired(&mut trigger_rx).await, 1);
+    }
+
+    #[tokio::test]
+    async fn jump_db_fires_above_the_background() {
+        let (mut meter, mut trigger_rx) = meter(None, Some(20.));
+        play(&mut meter, -50., 12.);
+        assert_eq!(fired(&mut trigger_rx).await, 0);
+
+        // 10 dB louder isn't enough
+        play(&mut meter, -40., 1.5);
+        assert_eq!(fired(&mut trigger_rx).await, 0);
+        play(&mut meter, -50., 1.);
+        play(&mut meter, -20., 1.5);
+        assert_eq!(fired(&mut trigger_rx).await, 1);
+    }
+
+    #[tokio::test]
+    async fn jumps_out_of_near_silence_dont_count() {
+        let (mut meter, mut trigger_rx) = meter(None, Some(20.));
+        play(&mut meter, -90., 12.);
+        play(&mut meter, -50., 1.5);
+        assert_eq!(fired(&mut trigger_rx).await, 0);
+    }
+}
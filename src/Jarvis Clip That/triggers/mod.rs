@@ -0,0 +1,5 @@
+pub mod trigger;
+pub mod game_events;
+pub mod log_files;
+pub mod voice;
+pub mod loudness;
\ No newline at end of file
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+
+// A rule asking the main loop for a clip
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    pub rule: String, // names of every rule that joined it, see Triggers::fire
+    pub save_secs: Option<u32>, // None saves the whole short replay
+}
+
+impl Trigger {
+    fn join(&mut self, other: Trigger) {
+        if !self.rule.split(", ").any(|rule| rule == other.rule) {
+            self.rule = format!("{}, {}", self.rule, other.rule);
+        }
+        self.save_secs = match (self.save_secs, other.save_secs) {
+            (Some(a), Some(b)) => { Some(a.max(b)) }
+            _ => { None }
+        };
+    }
+}
+
+// Shared by every trigger source, hands their triggers to the main loop
+#[derive(Clone)]
+pub struct Triggers {
+    tx: mpsc::UnboundedSender<Trigger>,
+    last_fired: Arc<Mutex<HashMap<String, Instant>>>,
+    pending: Arc<Mutex<Option<Trigger>>>, // still waiting out its delay
+}
+
+impl Triggers {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Trigger>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let triggers = Self {
+            tx,
+            last_fired: Arc::new(Mutex::new(HashMap::new())),
+            pending: Arc::new(Mutex::new(None)),
+        };
+        (triggers, rx)
+    }
+
+    // Sends `trigger` after `delay_secs`, so the clip also holds what happened right after.
+    // A trigger fired while another one waits joins that one, so one fight makes one clip.
+    // False if the same rule fired less than `cooldown_secs` ago
+    pub fn fire(&self, trigger: Trigger, delay_secs: f64, cooldown_secs: f64) -> bool {
+        let now = Instant::now();
+        let mut last_fired = self.last_fired.lock().unwrap();
+        if last_fired.get(&trigger.rule).is_some_and(|at| now.duration_since(*at).as_secs_f64() < cooldown_secs) {
+            return false;
+        }
+        last_fired.insert(trigger.rule.clone(), now);
+        drop(last_fired);
+
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(pending) = pending.as_mut() {
+            pending.join(trigger);
+            return true;
+        }
+        *pending = Some(trigger);
+        drop(pending);
+
+        let tx = self.tx.clone();
+        let pending = self.pending.clone();
+        tokio::spawn(async move {
+            if delay_secs > 0. {
+                tokio::time::sleep(Duration::from_secs_f64(delay_secs)).await;
+            }
+            let trigger = pending.lock().unwrap().take();
+            if let Some(trigger) = trigger {
+                let _ = tx.send(trigger);
+            }
+        });
+        true
+    }
+}
\ No newline at end of file
@@ -0,0 +1,3 @@
+pub mod features;
+pub mod wav;
+pub mod spotter;
\ No newline at end of file
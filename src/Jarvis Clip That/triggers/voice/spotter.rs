@@ -0,0 +1,415 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
+use std::thread;
+
+use serde::Deserialize;
+
+use crate::debug_println;
+use crate::error::Error;
+use crate::recorders::audio::tap::{AudioChunk, AudioTap};
+use crate::triggers::trigger::{Trigger, Triggers};
+use crate::triggers::voice::features::{dtw_distance, Features, HOP_LEN, hop_levels, level_db, Mfcc, Resampler, SAMPLE_RATE};
+use crate::triggers::voice::wav::read_wav;
+use crate::types::Result;
+
+// the rule name voice triggers are reported and cooled down by
+pub const VOICE_RULE: &str = "voice";
+
+// a pause this long ends an utterance, short enough to answer quickly, long enough for "Jarvis, ... clip that"
+const END_HOPS: usize = 50;
+// kept from before the level rose, the first consonant is quieter than the vowel that gets detected
+const LEAD_IN_HOPS: usize = 10;
+// a hop counts as speech this far above the background noise, and never below MIN_SPEECH_DB (dBFS)
+const SPEECH_ABOVE_NOISE_DB: f32 = 12.;
+const MIN_SPEECH_DB: f32 = -45.;
+// once speaking, quieter hops still count, so a soft syllable doesn't end the utterance
+const SPEECH_HOLD_DB: f32 = 6.;
+// how fast the noise estimate follows a louder room, per hop
+const NOISE_RISE_DB: f32 = 0.02;
+// how much shorter or longer than the templates an utterance may be to be compared at all
+const LENGTH_TOLERANCE: (f32, f32) = (0.6, 1.6);
+// cut from the mic track around a detected command
+const TRIM_MARGIN_SECS: f64 = 0.1;
+// detected commands remembered for trimming
+const MAX_COMMANDS: usize = 64;
+
+// Offline "Jarvis, clip that": the mic is compared against recordings of the phrase, nothing leaves the machine
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[serde(default)]
+pub struct VoiceSettings {
+    pub enabled: bool,
+    // WAVs of you saying the phrase, a few takes with some silence around them, e.g. recorded with Windows' voice recorder
+    pub templates: Vec<String>,
+    // the largest distance (see dtw_distance) still counted as the phrase, lower means stricter
+    pub threshold: f32,
+
+    pub save_secs: Option<u32>, // the whole short replay if not set
+    pub delay_secs: f64,
+    pub cooldown_secs: f64,
+    // leaves the spoken command out of the saved mic track
+    pub trim_command: bool,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            templates: Vec::new(),
+            threshold: 0.4,
+            save_secs: None,
+            delay_secs: 0.,
+            cooldown_secs: 5.,
+            trim_command: true,
+        }
+    }
+}
+
+struct Template {
+    name: String,
+    features: Features,
+}
+
+struct Utterance {
+    samples: Vec<f32>, // at SAMPLE_RATE
+    start_secs: f64,
+    end_secs: f64,
+}
+
+// Splits the mic into utterances by level, relative to a running estimate of the background noise
+struct Segmenter {
+    max_hops: usize,
+    pending: Vec<f32>, // less than a hop
+    pending_start_secs: f64,
+    noise_db: f32,
+
+    lead_in: VecDeque<Vec<f32>>,
+    hops: Vec<Vec<f32>>, // of the current utterance
+    start_secs: f64,
+    quiet_hops: usize,
+    too_long: bool, // waits for the next pause instead
+}
+
+impl Segmenter {
+    fn new(max_hops: usize) -> Self {
+        Self {
+            max_hops,
+            pending: Vec::new(),
+            pending_start_secs: 0.,
+            noise_db: MIN_SPEECH_DB,
+
+            lead_in: VecDeque::new(),
+            hops: Vec::new(),
+            start_secs: 0.,
+            quiet_hops: 0,
+            too_long: false,
+        }
+    }
+
+    // `samples` at SAMPLE_RATE, starting at `start_secs` on the shared clock
+    fn push(&mut self, samples: &[f32], start_secs: f64) -> Vec<Utterance> {
+        let hop_secs = HOP_LEN as f64 / SAMPLE_RATE as f64;
+        let expected_secs = self.pending_start_secs + self.pending.len() as f64 / SAMPLE_RATE as f64;
+        // a pause or a restarted recorder, what came before doesn't continue here
+        if (start_secs - expected_secs).abs() > 0.1 {
+            self.reset();
+            self.pending.clear();
+            self.pending_start_secs = start_secs;
+        }
+        self.pending.extend_from_slice(samples);
+
+        let mut utterances = Vec::new();
+        let whole_hops = self.pending.len() / HOP_LEN;
+        let ready: Vec<f32> = self.pending.drain(..whole_hops * HOP_LEN).collect();
+        for hop in ready.chunks(HOP_LEN).map(<[f32]>::to_vec) {
+            let hop_start_secs = self.pending_start_secs;
+            self.pending_start_secs += hop_secs;
+
+            let level = level_db(&hop);
+            self.noise_db = match level < self.noise_db {
+                true => { level.max(-90.) }
+                false => { self.noise_db + NOISE_RISE_DB }
+            };
+            let hold_db = match self.hops.is_empty() {
+                true => { 0. }
+                false => { SPEECH_HOLD_DB }
+            };
+            let speech = level > (self.noise_db + SPEECH_ABOVE_NOISE_DB).max(MIN_SPEECH_DB) - hold_db;
+
+            if self.hops.is_empty() && !self.too_long {
+                if speech {
+                    self.start_secs = hop_start_secs - self.lead_in.len() as f64 * hop_secs;
+                    self.hops.extend(self.lead_in.drain(..));
+                    self.hops.push(hop);
+                    self.quiet_hops = 0;
+                } else {
+                    self.lead_in.push_back(hop);
+                    if self.lead_in.len() > LEAD_IN_HOPS {
+                        self.lead_in.pop_front();
+                    }
+                }
+                continue;
+            }
+
+            self.quiet_hops = match speech {
+                true => { 0 }
+                false => { self.quiet_hops + 1 }
+            };
+            if !self.too_long {
+                self.hops.push(hop);
+                if self.hops.len() > self.max_hops + END_HOPS {
+                    self.hops.clear();
+                    self.too_long = true;
+                }
+            }
+            if self.quiet_hops >= END_HOPS {
+                if !self.too_long {
+                    // the trailing pause isn't part of it
+                    self.hops.truncate(self.hops.len() - END_HOPS);
+                    let samples: Vec<f32> = self.hops.concat();
+                    let end_secs = self.start_secs + samples.len() as f64 / SAMPLE_RATE as f64;
+                    utterances.push(Utterance { samples, start_secs: self.start_secs, end_secs });
+                }
+                self.reset();
+            }
+        }
+        utterances
+    }
+
+    fn reset(&mut self) {
+        self.lead_in.clear();
+        self.hops.clear();
+        self.quiet_hops = 0;
+        self.too_long = false;
+    }
+}
+
+// Listens to the tapped mic in its own thread and fires a trigger when the phrase is said
+pub struct VoiceTrigger {
+    tap: AudioTap,
+    commands: Arc<Mutex<VecDeque<(f64, f64)>>>, // (start, end) on the shared clock
+}
+
+impl VoiceTrigger {
+    pub fn start(
+        settings: &VoiceSettings,
+        triggers: Triggers,
+    ) -> Result<Self> {
+        let commands = Arc::new(Mutex::new(VecDeque::new()));
+        let spotter = Spotter::new(settings, commands.clone(), triggers)?;
+        let (tap, rx) = AudioTap::new();
+        // triggers are sent through tokio
+        let runtime = tokio::runtime::Handle::current();
+        thread::spawn(move || {
+            let _runtime = runtime.enter();
+            spotter.run(rx);
+        });
+
+        Ok(Self {
+            tap,
+            commands,
+        })
+    }
+
+    // For the mic recorder, see create_audio_recorder
+    pub fn tap(&self) -> AudioTap {
+        self.tap.clone()
+    }
+
+    // The spoken commands to cut from the mic track, empty unless trim_command is set
+    pub fn commands(&self) -> Vec<(f64, f64)> {
+        self.commands.lock().unwrap().iter().copied().collect()
+    }
+}
+
+struct Spotter {
+    settings: VoiceSettings,
+    mfcc: Mfcc,
+    templates: Vec<Template>,
+    segmenter: Segmenter,
+    resampler: Resampler,
+    commands: Arc<Mutex<VecDeque<(f64, f64)>>>,
+    triggers: Triggers,
+}
+
+impl Spotter {
+    fn new(
+        settings: &VoiceSettings,
+        commands: Arc<Mutex<VecDeque<(f64, f64)>>>,
+        triggers: Triggers,
+    ) -> Result<Self> {
+        let mfcc = Mfcc::new();
+        let mut templates = Vec::new();
+        for path in settings.templates.iter() {
+            let (samples, sample_rate) = read_wav(path)?;
+            let samples = trim_silence(&Resampler::new().process(&samples, sample_rate));
+            let features = mfcc.features(&samples);
+            if features.is_empty() {
+                return Err(Error::InvalidConfig(format!("voice.templates: {path} is too short or silent")).into());
+            }
+            templates.push(Template { name: path.clone(), features });
+        }
+        let max_hops = templates.iter().map(|template| template.features.len()).max().unwrap_or_default();
+        let max_hops = (max_hops as f32 * LENGTH_TOLERANCE.1) as usize;
+
+        Ok(Self {
+            settings: settings.clone(),
+            mfcc,
+            templates,
+            segmenter: Segmenter::new(max_hops),
+            resampler: Resampler::new(),
+            commands,
+            triggers,
+        })
+    }
+
+    // Until the mic recorder and every restarted one are gone
+    fn run(mut self, rx: Receiver<AudioChunk>) {
+        while let Ok(chunk) = rx.recv() {
+            let samples = self.resampler.process(&chunk.samples, chunk.sample_rate);
+            for utterance in self.segmenter.push(&samples, chunk.start_secs) {
+                self.check(utterance);
+            }
+        }
+    }
+
+    fn check(&mut self, utterance: Utterance) {
+        let features = self.mfcc.features(&utterance.samples);
+        let closest = self.templates.iter()
+            .filter(|template| {
+                let ratio = features.len() as f32 / template.features.len() as f32;
+                ratio >= LENGTH_TOLERANCE.0 && ratio <= LENGTH_TOLERANCE.1
+            })
+            .map(|template| (template, dtw_distance(&features, &template.features)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        let Some((template, distance)) = closest else {
+            return;
+        };
+
+        debug_println!("voice: {:.2}s utterance, closest to {} at {:.3}", utterance.end_secs - utterance.start_secs, template.name, distance);
+        if distance > self.settings.threshold {
+            // helps finding the right threshold
+            if distance < self.settings.threshold * 1.5 {
+                eprintln!("Voice: almost heard the phrase ({:.3}, threshold {:.3})", distance, self.settings.threshold);
+            }
+            return;
+        }
+
+        eprintln!("Voice: heard the phrase ({:.3})", distance);
+        if self.settings.trim_command {
+            let mut commands = self.commands.lock().unwrap();
+            commands.push_back((utterance.start_secs - TRIM_MARGIN_SECS, utterance.end_secs + TRIM_MARGIN_SECS));
+            if commands.len() > MAX_COMMANDS {
+                commands.pop_front();
+            }
+        }
+        let trigger = Trigger { rule: VOICE_RULE.to_string(), save_secs: self.settings.save_secs };
+        if !self.triggers.fire(trigger, self.settings.delay_secs, self.settings.cooldown_secs) {
+            debug_println!("voice is cooling down");
+        }
+    }
+}
+
+// Without the silence before and after the loudest part, like the segmenter cuts the mic
+fn trim_silence(samples: &[f32]) -> Vec<f32> {
+    let levels = hop_levels(samples);
+    let loudest = levels.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let first = levels.iter().position(|level| *level > loudest - 30.).unwrap_or(0);
+    let last = levels.iter().rposition(|level| *level > loudest - 30.).unwrap_or(0);
+    samples[first * HOP_LEN..((last + 1) * HOP_LEN).min(samples.len())].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    // where the fixtures start on the shared clock, anything but 0 catches mixing up the clocks
+    const START_SECS: f64 = 30.;
+    // every fixture has half a second of room noise before what's said
+    const SPEECH_START_SECS: f64 = 0.5;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/src/Jarvis Clip That/triggers/voice/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    fn spotter() -> (Spotter, UnboundedReceiver<Trigger>) {
+        let settings = VoiceSettings {
+            enabled: true,
+            templates: vec![fixture("clip_that.wav")],
+            ..Default::default()
+        };
+        let (triggers, trigger_rx) = Triggers::new();
+        let spotter = Spotter::new(&settings, Arc::new(Mutex::new(VecDeque::new())), triggers).unwrap();
+        (spotter, trigger_rx)
+    }
+
+    // Feeds `name` in 100 ms chunks like the tap, then a second of quiet to end what was said.
+    // The utterances it was cut into, each checked like Spotter::run does
+    fn hear(
+        spotter: &mut Spotter,
+        name: &str,
+    ) -> Vec<(f64, f64)> {
+        let (samples, sample_rate) = read_wav(&fixture(name)).unwrap();
+        let mut samples = spotter.resampler.process(&samples, sample_rate);
+        samples.extend(vec![0.; SAMPLE_RATE as usize]);
+
+        let chunk_len = SAMPLE_RATE as usize / 10;
+        let mut heard = Vec::new();
+        for (i, chunk) in samples.chunks(chunk_len).enumerate() {
+            let start_secs = START_SECS + (i * chunk_len) as f64 / SAMPLE_RATE as f64;
+            for utterance in spotter.segmenter.push(chunk, start_secs) {
+                heard.push((utterance.start_secs, utterance.end_secs));
+                spotter.check(utterance);
+            }
+        }
+        heard
+    }
+
+    #[tokio::test]
+    async fn the_phrase_fires_and_is_trimmed() {
+        let (mut spotter, mut trigger_rx) = spotter();
+        let heard = hear(&mut spotter, "clip_that_again.wav");
+
+        assert_eq!(heard.len(), 1);
+        let (start_secs, end_secs) = heard[0];
+        // the lead-in reaches a little before the first vowel, the pause after it isn't part of it
+        let speech_start_secs = START_SECS + SPEECH_START_SECS;
+        assert!(start_secs > speech_start_secs - 0.15 && start_secs <= speech_start_secs, "{start_secs}");
+        assert!(end_secs > speech_start_secs + 1.2 && end_secs < speech_start_secs + 1.45, "{end_secs}");
+
+        let trigger = tokio::time::timeout(std::time::Duration::from_secs(1), trigger_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(trigger.rule, VOICE_RULE);
+        let commands: Vec<(f64, f64)> = spotter.commands.lock().unwrap().iter().copied().collect();
+        assert_eq!(commands, vec![(start_secs - TRIM_MARGIN_SECS, end_secs + TRIM_MARGIN_SECS)]);
+    }
+
+    #[tokio::test]
+    async fn other_phrases_dont_fire() {
+        let (mut spotter, mut trigger_rx) = spotter();
+        assert_eq!(hear(&mut spotter, "other_phrase.wav").len(), 1);
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(trigger_rx.try_recv().is_err());
+        assert!(spotter.commands.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn silence_isnt_an_utterance() {
+        let (mut spotter, mut trigger_rx) = spotter();
+        assert!(hear(&mut spotter, "silence.wav").is_empty());
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(trigger_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn without_trim_command_it_only_fires() {
+        let (mut spotter, mut trigger_rx) = spotter();
+        spotter.settings.trim_command = false;
+        hear(&mut spotter, "clip_that_again.wav");
+
+        assert!(tokio::time::timeout(std::time::Duration::from_secs(1), trigger_rx.recv()).await.unwrap().is_some());
+        assert!(spotter.commands.lock().unwrap().is_empty());
+    }
+}
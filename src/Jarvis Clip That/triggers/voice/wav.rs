@@ -0,0 +1,62 @@
+use crate::error::Error;
+use crate::recorders::audio::convert::{decode_sample, SampleEncoding};
+use crate::types::Result;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// Mono samples and their rate. PCM with 16, 24 or 32 bits or 32 bit float, as recorders and editors write them
+pub fn read_wav(path: &str) -> Result<(Vec<f32>, u32)> {
+    let bytes = std::fs::read(path)?;
+    let invalid = |problem: &str| Error::InvalidConfig(format!("{path}: {problem}"));
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file").into());
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let id = &bytes[position..position + 4];
+        let size = u32::from_le_bytes([bytes[position + 4], bytes[position + 5], bytes[position + 6], bytes[position + 7]]) as usize;
+        let body = &bytes[position + 8..(position + 8 + size).min(bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let mut tag = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                // the real format is in the first two bytes of the sub format GUID
+                if tag == WAVE_FORMAT_EXTENSIBLE && body.len() >= 26 {
+                    tag = u16::from_le_bytes([body[24], body[25]]);
+                }
+                format = Some((tag, channels, sample_rate, bits));
+            }
+            b"data" => { data = Some(body); }
+            _ => {}
+        }
+        // chunks are padded to an even size
+        position += 8 + size + size % 2;
+    }
+
+    let (Some((tag, channels, sample_rate, bits)), Some(data)) = (format, data) else {
+        return Err(invalid("no fmt or data chunk").into());
+    };
+    let encoding = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 16) => { SampleEncoding::I16 }
+        (WAVE_FORMAT_PCM, 24) => { SampleEncoding::I24 }
+        (WAVE_FORMAT_PCM, 32) => { SampleEncoding::I32 }
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => { SampleEncoding::F32 }
+        _ => { return Err(invalid(&format!("unsupported sample format {tag} with {bits} bits")).into()); }
+    };
+    if channels == 0 || sample_rate == 0 {
+        return Err(invalid("no channels or no sample rate").into());
+    }
+
+    let channels = channels as usize;
+    let samples = data.chunks_exact(encoding.bytes() * channels)
+        .map(|frame| frame.chunks_exact(encoding.bytes()).map(|bytes| decode_sample(encoding, bytes)).sum::<f32>() / channels as f32)
+        .collect();
+    Ok((samples, sample_rate))
+}
\ No newline at end of file
@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut};
 use windows::Win32::Foundation::HANDLE;
 
 pub struct MaybeSafeComWrapper<I: windows::core::Interface>(pub I);
@@ -26,4 +26,18 @@ impl<P> Deref for MaybeSafeFFIPtrWrapper<P> {
     fn deref(&self) -> &Self::Target {
         &self.0
     }
+}
+
+pub struct MaybeSafeScalerWrapper(pub ffmpeg_next::software::scaling::Context);
+unsafe impl Send for MaybeSafeScalerWrapper {}
+impl Deref for MaybeSafeScalerWrapper {
+    type Target = ffmpeg_next::software::scaling::Context;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl DerefMut for MaybeSafeScalerWrapper {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
 }
\ No newline at end of file